@@ -0,0 +1,806 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::Path,
+    io::{self, Read, Write, Seek, SeekFrom},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use zip::{CompressionMethod, ZipWriter, write::FileOptions};
+
+use crate::{Notice, Notify};
+
+pub use atomic::part_path;
+pub use dir::{Walked, walk};
+
+mod atomic;
+mod dir;
+mod directory;
+mod seekable;
+mod sevenz;
+mod split;
+mod stream;
+
+/// The kinds of archives that can be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// A zip archive.
+    Zip,
+    
+    /// An uncompressed tarball.
+    Tar,
+    
+    /// A gzip-compressed tarball.
+    #[value(name = "tar.gz")]
+    TarGz,
+    
+    /// A zstd-compressed tarball.
+    #[value(name = "tar.zst")]
+    TarZst,
+    
+    /// A zstd-compressed tarball in the seekable format, whose independent frames allow random access;
+    /// plain zstd decoders read it like any other.
+    #[value(name = "tar.zst-seekable")]
+    TarZstSeekable,
+    
+    /// A 7z archive, compressed as a single solid stream with bzip2 unless `--compression` says otherwise.
+    #[value(name = "7z")]
+    SevenZip,
+    
+    /// A directory, with every entry as a regular file within it.
+    Dir,
+}
+
+/// The compression methods available for zip entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// No compression at all.
+    #[value(alias = "store")]
+    Stored,
+    
+    /// Deflate; the most widely supported method.
+    Deflate,
+    
+    /// Bzip2; slower, but usually smaller.
+    Bzip2,
+    
+    /// Zstandard; fast, with a wide range of levels.
+    Zstd,
+}
+
+impl From<Compression> for CompressionMethod {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Stored => CompressionMethod::Stored,
+            Compression::Deflate => CompressionMethod::Deflated,
+            Compression::Bzip2 => CompressionMethod::Bzip2,
+            Compression::Zstd => CompressionMethod::Zstd,
+        }
+    }
+}
+
+/// When to use the zip64 extensions, which lift the 4 GiB limits of zip archives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Zip64 {
+    /// Only for entries that need them; the archive itself gets them as needed either way.
+    #[default]
+    Auto,
+    
+    /// For every entry, even small ones.
+    Always,
+}
+
+/// Settings that affect how entries are written.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// The compression method for zip entries; the default is deflate.
+    pub compression: Option<Compression>,
+    
+    /// The compression level, for zip entries and compressed tarballs.
+    pub level: Option<i32>,
+    
+    /// The password to AES-encrypt zip entries with.
+    pub password: Option<String>,
+    
+    /// The modification time of all entries; the default is when the archive was opened.
+    pub mtime: Option<SystemTime>,
+    
+    /// When zip entries get the zip64 extensions; other formats have no such limits.
+    pub zip64: Zip64,
+    
+    /// The permissions of file entries; the files written with [`Format::Dir`] only get them on Unix, if they were set.
+    pub modes: Modes,
+    
+    /// Whether the data of replaced zip entries is dropped from the file, rather than only left unreachable.
+    pub compact: bool,
+    
+    /// The boundary the data of stored zip entries starts at, like `zipalign`, so they can be memory-mapped.
+    pub align: Option<u16>,
+}
+
+impl Options {
+    fn mtime(&self) -> SystemTime {
+        self.mtime.unwrap_or_else(SystemTime::now)
+    }
+}
+
+/// A sink for the captured outputs of commands.
+pub trait ArchiveWriter: Send {
+    /// Writes a single file of the given size into the archive, streaming its content from the reader.
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()>;
+    
+    /// Like [`append`](Self::append), but with the given compression instead of that of the archive;
+    /// only zip archives compress their entries individually.
+    fn append_with(&mut self, name: &str, content: &mut dyn Read, size: u64, _compression: Compression, _level: Option<i32>) -> io::Result<()> {
+        self.append(name, content, size)
+    }
+    
+    /// Writes a directory entry, for extractors that don't create directories on their own.
+    /// 
+    /// The name must not end with `/`; that is added as needed.
+    fn add_directory(&mut self, name: &str) -> io::Result<()>;
+    
+    /// Removes the previously written entry of the given name, so that a later one of the same name replaces it.
+    fn remove(&mut self, name: &str);
+    
+    /// Attaches a comment to the previously written entry of the given name; only zip archives can hold those.
+    fn comment(&mut self, _name: &str, _comment: &str) {}
+    
+    /// Sets the comment of the archive as a whole; only zip archives can hold one.
+    fn set_comment(&mut self, _comment: &str) {}
+    
+    /// The names of the entries that were already in the archive, when appending to it.
+    fn existing_names(&self) -> &[String] {
+        &[]
+    }
+    
+    /// When the existing entry of the given name was last modified, if the archive records it.
+    fn modified(&self, _name: &str) -> Option<SystemTime> {
+        None
+    }
+    
+    /// Finishes writing the archive, flushing everything to disk.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A compression method (and level) for the zip entries whose names match a glob pattern, for `--compress-rule`.
+#[derive(Debug, Clone)]
+pub struct CompressRule {
+    pub pattern: String,
+    pub compression: Compression,
+    pub level: Option<i32>,
+}
+
+impl CompressRule {
+    /// Parses a rule like `*.json=zstd:19` or `*.png=store`.
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let Some((pattern, method)) = rule.rsplit_once('=') else {
+            return Err(format!("invalid compression rule `{rule}`; expected `GLOB=METHOD[:LEVEL]`"));
+        };
+        let (method, level) = match method.split_once(':') {
+            Some((method, level)) => (method, Some(level.parse::<i32>().map_err(|err| format!("invalid compression level `{level}`: {err}"))?)),
+            None => (method, None),
+        };
+        let compression = <Compression as clap::ValueEnum>::from_str(method, true)?;
+        
+        // Let the zip crate validate the method/level combination, as it would only fail once an entry matches.
+        let options = FileOptions::default().compression_method(compression.into()).compression_level(level);
+        ZipWriter::new(io::Cursor::new(Vec::new())).start_file("", options).map_err(|err| format!("invalid compression rule `{rule}`: {err}"))?;
+        Ok(Self { pattern: pattern.to_string(), compression, level })
+    }
+    
+    pub fn matches(&self, name: &str) -> bool {
+        crate::names::matches_glob(&self.pattern, name)
+    }
+}
+
+/// The permissions of file entries, for `--file-mode` and `--exec-mode`; directories are always `0755`.
+#[derive(Debug, Clone, Default)]
+pub struct Modes {
+    /// The permissions of all file entries; the default is `0644`.
+    pub file: Option<u32>,
+    
+    /// The rules giving some entries other permissions, the first matching one applying.
+    pub exec: Vec<ModeRule>,
+}
+
+impl Modes {
+    /// The permissions of the file entry of the given name.
+    pub fn of(&self, name: &str) -> u32 {
+        let mode = self.file.unwrap_or(0o644);
+        match self.exec.iter().find(|r| r.matches(name)) {
+            // Executable by whoever may read it.
+            Some(rule) => rule.mode.unwrap_or(mode | (mode & 0o444) >> 2),
+            None => mode,
+        }
+    }
+    
+    /// Whether permissions were asked for at all, rather than left to the defaults.
+    pub fn is_set(&self) -> bool {
+        self.file.is_some() || !self.exec.is_empty()
+    }
+}
+
+/// The permissions for the entries whose names match a glob pattern, for `--exec-mode`.
+#[derive(Debug, Clone)]
+pub struct ModeRule {
+    pub pattern: String,
+    
+    /// The default is the file mode plus the executable bits.
+    pub mode: Option<u32>,
+}
+
+impl ModeRule {
+    /// Parses a rule like `*.sh` or `bin/*=0750`.
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        match rule.rsplit_once('=') {
+            Some((pattern, mode)) => Ok(Self { pattern: pattern.to_string(), mode: Some(parse_mode(mode)?) }),
+            None => Ok(Self { pattern: rule.to_string(), mode: None }),
+        }
+    }
+    
+    pub fn matches(&self, name: &str) -> bool {
+        crate::names::matches_glob(&self.pattern, name)
+    }
+}
+
+/// Parses octal permissions like `0644` or `755`.
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+        Ok(mode @ 0..=0o777) => Ok(mode),
+        _ => Err(format!("invalid mode `{mode}`; expected octal permissions like `0644`")),
+    }
+}
+
+/// Extensions of formats that are compressed already, like images, videos and archives.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "heic", "jxl",
+    "mp3", "m4a", "aac", "ogg", "opus", "flac", "mp4", "m4v", "mkv", "webm", "mov", "avi",
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "br", "lz4",
+    "jar", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "woff", "woff2", "pdf",
+];
+
+/// Magic bytes of formats that are compressed already, along with their offset.
+const COMPRESSED_MAGIC: &[(usize, &[u8])] = &[
+    (0, b"\x89PNG"),
+    (0, b"\xFF\xD8\xFF"),
+    (0, b"GIF8"),
+    (0, b"PK\x03\x04"),
+    (0, b"\x1F\x8B"),
+    (0, b"BZh"),
+    (0, b"\xFD7zXZ\x00"),
+    (0, b"\x28\xB5\x2F\xFD"),
+    (0, b"7z\xBC\xAF\x27\x1C"),
+    (0, b"Rar!"),
+    (0, b"OggS"),
+    (0, b"fLaC"),
+    (0, b"ID3"),
+    (4, b"ftyp"),
+    (8, b"WEBP"),
+];
+
+/// Whether the content of an entry is compressed already, judging by its extension or its first bytes;
+/// compressing it again would waste time for little to no gain.
+pub fn is_compressed(name: &str, head: &[u8]) -> bool {
+    let extension = name.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()).unwrap_or_default();
+    COMPRESSED_EXTENSIONS.contains(&extension.as_str())
+        || COMPRESSED_MAGIC.iter().any(|(offset, magic)| head.get(*offset..).is_some_and(|h| h.starts_with(magic)))
+}
+
+/// Warns about the options that don't apply to the format, which opening an archive of it ignores.
+pub fn ignored(format: Format, options: &Options) -> Vec<Notice> {
+    let ignored = [
+        (options.compression.is_some() && ![Format::Zip, Format::SevenZip].contains(&format), "Compression method only applies to zip and 7z archives; ignoring it."),
+        (options.level.is_some() && format == Format::Dir, "Compression level doesn't apply to directories; ignoring it."),
+        (options.zip64 == Zip64::Always && format != Format::Zip, "Zip64 only applies to zip archives; ignoring it."),
+    ];
+    ignored.into_iter().filter(|(ignored, _)| *ignored).map(|(_, message)| Notice::Warning(message.to_string())).collect()
+}
+
+/// Checks that the options can be applied to the format, as far as that's possible before touching the file.
+fn check(format: Format, options: &Options) -> io::Result<()> {
+    if format == Format::Zip {
+        // Let the zip crate validate the method/level combination, as it would only fail once an entry is written.
+        ZipWriter::new(io::Cursor::new(Vec::new())).start_file("", zip_options(options))?;
+    }
+    
+    if options.password.is_some() && format != Format::Zip {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "only zip archives can be encrypted"));
+    }
+    Ok(())
+}
+
+/// Opens (or creates) the archive at the given path in the given format; `-` writes it to stdout instead.
+/// 
+/// New archives are written to [`part_path`] first, and only replace whatever is at the path once they're finished.
+/// Archives appended to are written in place, as are directories.
+pub fn open(format: Format, path: &Path, append: bool, options: &Options) -> io::Result<Box<dyn ArchiveWriter>> {
+    check(format, options)?;
+    
+    if format == Format::Dir {
+        return Ok(Box::new(dir::DirArchive::open(path, append, options)?));
+    }
+    
+    if path == Path::new("-") {
+        if append {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "stdout can't be appended to"));
+        }
+        return open_stream(format, io::stdout(), options);
+    }
+    
+    if append {
+        return open_file(format, path, true, options);
+    }
+    let part = part_path(path);
+    let inner = open_file(format, &part, false, options)?;
+    Ok(Box::new(atomic::AtomicArchive::new(inner, part, path.to_path_buf())))
+}
+
+/// Opens (or creates) the archive file at exactly the given path.
+fn open_file(format: Format, path: &Path, append: bool, options: &Options) -> io::Result<Box<dyn ArchiveWriter>> {
+    let password = options.password.as_ref().map(String::as_bytes);
+    Ok(match (format, append) {
+        (Format::Zip, false) if password.is_some() => Box::new(stream::StreamZipArchive::new(
+            io::BufWriter::new(File::create(path)?),
+            password,
+            options
+        )),
+        (Format::Zip, true) if password.is_some() => return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "encrypted archives can't be appended to"
+        )),
+        (Format::Zip, false) => {
+            // Readable as well, for editing the central directory once finished.
+            let file = File::options().read(true).write(true).create(true).truncate(true).open(path)?;
+            Box::new(ZipArchive::new(ZipWriter::new(file), Vec::new(), options))
+        },
+        (Format::Zip, true) => {
+            let file = File::options().read(true).write(true).open(path)?;
+            let mut archive = zip::ZipArchive::new(&file).map_err(io::Error::from)?;
+            let existing = archive.file_names().map(String::from).collect();
+            
+            // The zip crate drops the comments of existing entries when appending, so they're restored once finished.
+            let (mut comments, mut modified) = (HashMap::new(), HashMap::new());
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i).map_err(io::Error::from)?;
+                if !entry.comment().is_empty() {
+                    comments.insert(entry.name().to_string(), entry.comment().to_string());
+                }
+                if let Ok(time) = entry.last_modified().to_time() {
+                    modified.insert(entry.name().to_string(), time.into());
+                }
+            }
+            drop(archive);
+            
+            let writer = ZipWriter::new_append(file).map_err(io::Error::from)?;
+            Box::new(ZipArchive { comments, modified, ..ZipArchive::new(writer, existing, options) })
+        },
+        (Format::SevenZip, false) => Box::new(sevenz::SevenZipArchive::create(path, options)?),
+        (Format::SevenZip, true) => return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "7z archives can't be appended to"
+        )),
+        (Format::Tar, true) => {
+            let mut file = File::options().read(true).write(true).open(path)?;
+            let (existing, modified) = seek_tar_end(&mut file)?.into_iter().unzip();
+            Box::new(TarArchive { existing, modified, ..TarArchive::new(file, options) })
+        },
+        (_, false) => return open_tar(format, File::create(path)?, options),
+        (Format::TarGz | Format::TarZst | Format::TarZstSeekable, true) => return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compressed tarballs can't be appended to"
+        )),
+        (Format::Dir, _) => unreachable!("opened by `open`"),
+    })
+}
+
+/// Starts a new archive of the given format on a writer that can't seek, like stdout or a pipe.
+pub fn open_stream<W: Write + Send + 'static>(format: Format, inner: W, options: &Options) -> io::Result<Box<dyn ArchiveWriter>> {
+    check(format, options)?;
+    let inner = io::BufWriter::new(inner);
+    match format {
+        Format::Zip => Ok(Box::new(stream::StreamZipArchive::new(inner, options.password.as_ref().map(String::as_bytes), options))),
+        Format::Dir => Err(io::Error::new(io::ErrorKind::Unsupported, "directories can't be streamed")),
+        // The header at the start is only complete once all entries are written.
+        Format::SevenZip => Err(io::Error::new(io::ErrorKind::Unsupported, "7z archives can't be streamed")),
+        _ => open_tar(format, inner, options),
+    }
+}
+
+/// Starts a new tarball of the given format on the writer.
+fn open_tar<W: Finish + Send + 'static>(format: Format, inner: W, options: &Options) -> io::Result<Box<dyn ArchiveWriter>> {
+    Ok(match format {
+        Format::Zip | Format::Dir | Format::SevenZip => unreachable!("not a tarball"),
+        Format::Tar => Box::new(TarArchive::new(inner, options)),
+        Format::TarGz => {
+            let level = match options.level {
+                Some(level @ 0..=9) => flate2::Compression::new(level as u32),
+                Some(level) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("gzip compression level must be within 0..=9, not {level}")
+                )),
+                None => flate2::Compression::default(),
+            };
+            let encoder = flate2::write::GzEncoder::new(inner, level);
+            Box::new(TarArchive::new(encoder, options))
+        },
+        Format::TarZst => {
+            let encoder = zstd::Encoder::new(inner, zstd_level(options)?)?;
+            Box::new(TarArchive::new(encoder, options))
+        },
+        Format::TarZstSeekable => {
+            let encoder = seekable::SeekableEncoder::new(inner, zstd_level(options)?);
+            Box::new(TarArchive::new(encoder, options))
+        },
+    })
+}
+
+/// Writers that take more than a flush to be done with, like compressors writing their last frame;
+/// finishing them explicitly, instead of when they're dropped, lets tarballs fail if that fails.
+trait Finish: Write {
+    fn finish(self) -> io::Result<()>;
+}
+
+impl Finish for File {
+    fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> Finish for io::BufWriter<W> {
+    fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Finish> Finish for flate2::write::GzEncoder<W> {
+    fn finish(self) -> io::Result<()> {
+        flate2::write::GzEncoder::finish(self)?.finish()
+    }
+}
+
+impl<W: Finish> Finish for zstd::Encoder<'static, W> {
+    fn finish(self) -> io::Result<()> {
+        zstd::Encoder::finish(self)?.finish()
+    }
+}
+
+impl<W: Finish> Finish for seekable::SeekableEncoder<W> {
+    fn finish(self) -> io::Result<()> {
+        seekable::SeekableEncoder::finish(self)?.finish()
+    }
+}
+
+/// The level of zstd-compressed tarballs; 0 picks zstd's default.
+fn zstd_level(options: &Options) -> io::Result<i32> {
+    let level = options.level.unwrap_or(0);
+    if !zstd::compression_level_range().contains(&level) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("zstd compression level must be within {:?}, not {level}", zstd::compression_level_range())
+        ));
+    }
+    Ok(level)
+}
+
+/// Like [`open`], but spreads the entries over multiple archives named after the given path:
+/// one per shard, as picked by the pattern, and a new one whenever the current one would exceed the size.
+/// 
+/// Each new archive is told about via `notify`, as it's started.
+pub fn open_split(format: Format, path: &Path, options: &Options, size: Option<u64>, shard_by: Option<regex::Regex>, notify: Notify) -> Box<dyn ArchiveWriter> {
+    Box::new(split::SplitArchive::new(format, path, options, size, shard_by, notify))
+}
+
+/// Makes an archive that wasn't finished, e.g. due to a crash, readable (and appendable) again.
+/// 
+/// Returns the number of entries that were recovered, or `None` if the archive was fine as-is.
+pub fn recover(format: Format, path: &Path) -> io::Result<Option<usize>> {
+    match format {
+        Format::Zip => {
+            let mut file = File::options().read(true).write(true).open(path)?;
+            if zip::ZipArchive::new(&file).is_ok() {
+                return Ok(None);
+            }
+            let directory = directory::Directory::recover(&mut file)?;
+            directory.write(&mut file)?;
+            Ok(Some(directory.records.len()))
+        },
+        // Incomplete entries are dropped when seeking to the end of a tarball anyway.
+        Format::Tar | Format::Dir => Ok(None),
+        Format::TarGz | Format::TarZst | Format::TarZstSeekable => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compressed tarballs can't be recovered"
+        )),
+        Format::SevenZip => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "7z archives can't be recovered"
+        )),
+    }
+}
+
+fn zip_options(options: &Options) -> FileOptions {
+    let mtime = zip::DateTime::try_from(time::OffsetDateTime::from(options.mtime())).unwrap_or_default();
+    FileOptions::default()
+        .compression_method(options.compression.unwrap_or(Compression::Deflate).into())
+        .compression_level(options.level)
+        .last_modified_time(mtime)
+}
+
+struct ZipArchive {
+    inner: ZipWriter<File>,
+    options: FileOptions,
+    compression: Compression,
+    zip64: Zip64,
+    align: u16,
+    modes: Modes,
+    compact: bool,
+    existing: Vec<String>,
+    
+    /// Names of entries that are replaced by later ones, which only happens once the archive is finished.
+    replaced: HashSet<String>,
+    
+    /// Comments of entries, which the zip crate can't write; they're added once the archive is finished as well.
+    comments: HashMap<String, String>,
+    
+    /// When the existing entries were last modified.
+    modified: HashMap<String, SystemTime>,
+}
+
+impl ZipArchive {
+    fn new(inner: ZipWriter<File>, existing: Vec<String>, options: &Options) -> Self {
+        Self {
+            inner,
+            options: zip_options(options),
+            compression: options.compression.unwrap_or(Compression::Deflate),
+            zip64: options.zip64,
+            align: options.align.unwrap_or(1),
+            modes: options.modes.clone(),
+            compact: options.compact,
+            existing,
+            replaced: HashSet::new(),
+            comments: HashMap::new(),
+            modified: HashMap::new(),
+        }
+    }
+}
+
+impl ZipArchive {
+    fn append_using(&mut self, name: &str, content: &mut dyn Read, size: u64, options: FileOptions, compression: Compression) -> io::Result<()> {
+        // The sizes in the local header are only known after writing, so this has to be decided up-front,
+        // allowing for incompressible data growing slightly.
+        let large = self.zip64 == Zip64::Always || size.saturating_add(size / 64) >= u32::MAX as u64;
+        let options = options.large_file(large).unix_permissions(self.modes.of(name));
+        match compression {
+            // Padding the local header's extra field moves the data to the boundary.
+            Compression::Stored if self.align > 1 => self.inner.start_file_aligned(name, options, self.align).map(drop)?,
+            _ => self.inner.start_file(name, options)?,
+        }
+        io::copy(content, &mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+impl ArchiveWriter for ZipArchive {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        self.append_using(name, content, size, self.options, self.compression)
+    }
+    
+    fn append_with(&mut self, name: &str, content: &mut dyn Read, size: u64, compression: Compression, level: Option<i32>) -> io::Result<()> {
+        self.append_using(name, content, size, self.options.compression_method(compression.into()).compression_level(level), compression)
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.inner.add_directory(name, self.options)?;
+        self.inner.flush()
+    }
+    
+    fn remove(&mut self, name: &str) {
+        self.replaced.insert(name.to_string());
+        self.comments.remove(name);
+    }
+    
+    fn comment(&mut self, name: &str, comment: &str) {
+        self.comments.insert(name.to_string(), comment.to_string());
+    }
+    
+    fn set_comment(&mut self, comment: &str) {
+        self.inner.set_comment(comment);
+    }
+    
+    fn existing_names(&self) -> &[String] {
+        &self.existing
+    }
+    
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        self.modified.get(name).copied()
+    }
+    
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let mut file = self.inner.finish()?;
+        if !self.replaced.is_empty() || !self.comments.is_empty() {
+            // The replaced entries' data stays in place, but nothing refers to it anymore.
+            let mut directory = directory::Directory::read(&mut file)?;
+            directory.keep_last(&self.replaced);
+            if self.compact && !self.replaced.is_empty() {
+                directory.compact(&mut file, self.align as u64)?;
+            }
+            for record in &mut directory.records {
+                if let Some(comment) = self.comments.get(&record.name) {
+                    record.set_comment(comment.as_bytes());
+                }
+            }
+            directory.write(&mut file)?;
+        }
+        file.flush()
+    }
+}
+
+const BLOCK: usize = 512;
+
+struct TarArchive<W: Write> {
+    inner: W,
+    mtime: u64,
+    modes: Modes,
+    existing: Vec<String>,
+    
+    /// When the existing entries were last modified, in the same order.
+    modified: Vec<u64>,
+}
+
+impl<W: Write> TarArchive<W> {
+    fn new(inner: W, options: &Options) -> Self {
+        let mtime = options.mtime().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Self { inner, mtime, modes: options.modes.clone(), existing: Vec::new(), modified: Vec::new() }
+    }
+    
+    /// Writes a header and its (block-padded) data.
+    fn write_entry(&mut self, name: &[u8], kind: u8, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        if name.len() > 100 {
+            // GNU-style long name, stored as a pseudo-entry preceding the real one.
+            let mut long_name = name.to_vec();
+            long_name.push(0);
+            self.write_entry(b"././@LongLink", b'L', &mut long_name.as_slice(), long_name.len() as u64)?;
+        }
+        
+        let mut header = [0u8; BLOCK];
+        header[..name.len().min(100)].copy_from_slice(&name[..name.len().min(100)]);
+        let mode = match kind {
+            b'0' => self.modes.of(&String::from_utf8_lossy(name)),
+            b'5' => 0o755,
+            _ => 0o644,
+        };
+        write_octal(&mut header[100..108], mode as u64);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_number(&mut header[124..136], size);
+        write_octal(&mut header[136..148], self.mtime);
+        header[156] = kind;
+        header[257..265].copy_from_slice(b"ustar  \0");
+        
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|b| *b as u64).sum();
+        write_octal(&mut header[148..155], checksum);
+        
+        self.inner.write_all(&header)?;
+        let copied = io::copy(&mut content.take(size), &mut self.inner)?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry content ended early"));
+        }
+        let padding = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+        self.inner.write_all(&[0u8; BLOCK][..padding])
+    }
+}
+
+impl<W: Finish + Send> ArchiveWriter for TarArchive<W> {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        self.write_entry(name.as_bytes(), b'0', content, size)
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.write_entry(format!("{name}/").as_bytes(), b'5', &mut io::empty(), 0)
+    }
+    
+    /// Tarballs can't drop entries, but extracting them lets later entries overwrite earlier ones anyway.
+    fn remove(&mut self, _name: &str) {}
+    
+    fn existing_names(&self) -> &[String] {
+        &self.existing
+    }
+    
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        // The last of the same name is the one extractors end up with.
+        let index = self.existing.iter().rposition(|n| n == name)?;
+        Some(UNIX_EPOCH + Duration::from_secs(self.modified[index]))
+    }
+    
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.inner.write_all(&[0u8; BLOCK * 2])?;
+        self.inner.finish()
+    }
+}
+
+/// Writes a NUL-terminated, zero-padded octal number.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()..].fill(0);
+}
+
+/// Writes a number as octal if it fits, or else in GNU base-256 encoding.
+fn write_number(field: &mut [u8], value: u64) {
+    if value < 1 << (3 * (field.len() - 1)) {
+        write_octal(field, value);
+    } else {
+        field.fill(0);
+        field[0] = 0x80;
+        let len = field.len();
+        field[len - 8..].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Parses a header number, either octal or GNU base-256 encoded.
+fn read_number(field: &[u8]) -> u64 {
+    if field[0] & 0x80 != 0 {
+        field[field.len() - 8..].iter().fold(0, |n, b| n << 8 | *b as u64)
+    } else {
+        let digits = String::from_utf8_lossy(field);
+        u64::from_str_radix(digits.trim_matches(|c: char| c == '\0' || c == ' '), 8).unwrap_or(0)
+    }
+}
+
+/// Positions the file right before the end-of-archive marker of a tarball, returning the names of its entries,
+/// along with their modification times.
+/// 
+/// If the tarball ends within an entry, it's positioned before that entry instead.
+fn seek_tar_end(file: &mut File) -> io::Result<Vec<(String, u64)>> {
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; BLOCK];
+    let mut names = Vec::new();
+    let mut long_name: Option<(Vec<u8>, u64)> = None;
+    loop {
+        let position = file.stream_position()?;
+        // A long name belongs to the entry following it, so they're dropped together.
+        let entry_start = long_name.as_ref().map_or(position, |(_, at)| *at);
+        match file.read_exact(&mut header) {
+            Ok(()) if header.iter().any(|b| *b != 0) => {
+                let size = read_number(&header[124..136]);
+                let blocks = size.div_ceil(BLOCK as u64);
+                if position + (blocks + 1) * BLOCK as u64 > file_len {
+                    file.seek(SeekFrom::Start(entry_start))?;
+                    return Ok(names);
+                }
+                
+                if header[156] == b'L' {
+                    let mut name = vec![0u8; size as usize];
+                    file.read_exact(&mut name)?;
+                    long_name = Some((name, entry_start));
+                    file.seek(SeekFrom::Start(position + (blocks + 1) * BLOCK as u64))?;
+                    continue;
+                }
+                
+                let name = long_name.take().map(|(name, _)| name).unwrap_or_else(|| {
+                    let mut name = header[..100].to_vec();
+                    // POSIX tarballs may split long names into a prefix and the name proper.
+                    if &header[257..263] == b"ustar\0" && header[345] != 0 {
+                        name = [&header[345..500], b"/", &header[..100]].concat();
+                        name.retain(|b| *b != 0);
+                    }
+                    name
+                });
+                let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+                names.push((String::from_utf8_lossy(&name[..end]).into_owned(), read_number(&header[136..148])));
+                
+                file.seek(SeekFrom::Current((blocks * BLOCK as u64) as i64))?;
+            },
+            Ok(()) => {
+                file.seek(SeekFrom::Start(entry_start))?;
+                return Ok(names);
+            },
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                file.seek(SeekFrom::Start(entry_start))?;
+                return Ok(names);
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
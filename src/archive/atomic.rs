@@ -0,0 +1,74 @@
+//! Writing a new archive next to its final path, and only moving it there once it's finished;
+//! so that a failed run never leaves a truncated archive in place of the previous one.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::{ArchiveWriter, Compression};
+
+/// Where an archive is written to until it's finished: `NAME.part`, next to it.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    part.into()
+}
+
+pub struct AtomicArchive {
+    inner: Box<dyn ArchiveWriter>,
+    part: PathBuf,
+    path: PathBuf,
+}
+
+impl AtomicArchive {
+    pub fn new(inner: Box<dyn ArchiveWriter>, part: PathBuf, path: PathBuf) -> Self {
+        Self { inner, part, path }
+    }
+}
+
+impl ArchiveWriter for AtomicArchive {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        self.inner.append(name, content, size)
+    }
+    
+    fn append_with(&mut self, name: &str, content: &mut dyn Read, size: u64, compression: Compression, level: Option<i32>) -> io::Result<()> {
+        self.inner.append_with(name, content, size, compression, level)
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.inner.add_directory(name)
+    }
+    
+    fn remove(&mut self, name: &str) {
+        self.inner.remove(name)
+    }
+    
+    fn comment(&mut self, name: &str, comment: &str) {
+        self.inner.comment(name, comment)
+    }
+    
+    fn set_comment(&mut self, comment: &str) {
+        self.inner.set_comment(comment)
+    }
+    
+    fn existing_names(&self) -> &[String] {
+        self.inner.existing_names()
+    }
+    
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        self.inner.modified(name)
+    }
+    
+    /// Replaces whatever was at the final path only once the archive was finished successfully.
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let Self { inner, part, path } = *self;
+        let left = |err: io::Error| io::Error::new(
+            err.kind(),
+            format!("{err}; the unfinished archive is left at `{}`", part.display())
+        );
+        inner.finish().map_err(left)?;
+        std::fs::rename(&part, &path).map_err(left)
+    }
+}
@@ -0,0 +1,108 @@
+//! Writing entries as loose files into a directory, for when an archive isn't wanted after all.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::{ArchiveWriter, Modes, Options};
+
+pub struct DirArchive {
+    root: PathBuf,
+    mtime: Option<SystemTime>,
+    modes: Modes,
+    existing: Vec<String>,
+}
+
+impl DirArchive {
+    /// Creates the directory as needed; when appending, the files already within it count as entries.
+    pub fn open(root: &Path, append: bool, options: &Options) -> io::Result<Self> {
+        fs::create_dir_all(root)?;
+        let mut existing = Vec::new();
+        if append {
+            existing = walk(root)?.into_iter().map(|walked| walked.name).collect();
+        }
+        Ok(Self { root: root.to_path_buf(), mtime: options.mtime, modes: options.modes.clone(), existing })
+    }
+    
+    /// Absolute names are kept within the directory as well.
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name.trim_start_matches('/'))
+    }
+}
+
+impl ArchiveWriter for DirArchive {
+    fn append(&mut self, name: &str, content: &mut dyn Read, _size: u64) -> io::Result<()> {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&path)?;
+        io::copy(content, &mut file)?;
+        if let Some(mtime) = self.mtime {
+            file.set_modified(mtime)?;
+        }
+        // Otherwise, the files get the permissions the umask leaves, like any other.
+        #[cfg(unix)]
+        if self.modes.is_set() {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(self.modes.of(name)))?;
+        }
+        Ok(())
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        fs::create_dir_all(self.path(name))
+    }
+    
+    /// The later entry overwrites the file anyway.
+    fn remove(&mut self, _name: &str) {}
+    
+    fn existing_names(&self) -> &[String] {
+        &self.existing
+    }
+    
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        fs::metadata(self.path(name)).and_then(|m| m.modified()).ok()
+    }
+    
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Something found within a directory by [`walk`].
+pub struct Walked {
+    /// The path relative to the directory walked, with forward-slashes; directories end in one.
+    pub name: String,
+    pub path: PathBuf,
+    
+    /// The type of the entry itself; symlinks aren't followed.
+    pub kind: fs::FileType,
+}
+
+/// Collects everything within the directory, recursively: files, the directories holding them,
+/// and anything else, like symlinks; each directory is listed before what's within it.
+pub fn walk(dir: &Path) -> io::Result<Vec<Walked>> {
+    let mut found = Vec::new();
+    walk_into(dir, "", &mut found)?;
+    Ok(found)
+}
+
+fn walk_into(dir: &Path, prefix: &str, found: &mut Vec<Walked>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let mut name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if kind.is_dir() {
+            name.push('/');
+            found.push(Walked { name: name.clone(), path: entry.path(), kind });
+            walk_into(&entry.path(), &name, found)?;
+        } else {
+            found.push(Walked { name, path: entry.path(), kind });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+//! Editing the central directory of finished zip archives, for what the zip crate can't do while writing.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read, Write, Seek, SeekFrom},
+};
+
+const END_SIGNATURE: u32 = 0x06054b50;
+const END64_SIGNATURE: u32 = 0x06064b50;
+const END64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+
+/// The length of the end-of-central-directory record, without the archive comment following it.
+const END_LEN: usize = 22;
+const MAX_COMMENT_LEN: usize = u16::MAX as usize;
+
+/// One record of the central directory.
+pub struct Record {
+    pub name: String,
+    bytes: Vec<u8>,
+}
+
+impl Record {
+    /// Replaces the comment of the entry, cutting it off at the 64 KiB a comment can hold.
+    pub fn set_comment(&mut self, comment: &[u8]) {
+        let comment = &comment[..comment.len().min(MAX_COMMENT_LEN)];
+        let len = 46 + u16_at(&self.bytes, 28) as usize + u16_at(&self.bytes, 30) as usize;
+        self.bytes.truncate(len);
+        self.bytes[32..34].copy_from_slice(&(comment.len() as u16).to_le_bytes());
+        self.bytes.extend(comment);
+    }
+    
+    /// Where the entry's local header is, and where in the record that offset is kept.
+    fn offset(&self) -> (u64, usize) {
+        let offset = u32_at(&self.bytes, 42) as u64;
+        if offset != u32::MAX as u64 {
+            return (offset, 42);
+        }
+        // The zip64 extra field holds the sizes first, but only those that didn't fit either.
+        let name_len = u16_at(&self.bytes, 28) as usize;
+        let extra = 46 + name_len..46 + name_len + u16_at(&self.bytes, 30) as usize;
+        let mut at = extra.start;
+        while at + 4 <= extra.end {
+            let len = u16_at(&self.bytes, at + 2) as usize;
+            if u16_at(&self.bytes, at) == 0x0001 {
+                let skip = [20, 24].iter().filter(|i| u32_at(&self.bytes, **i) == u32::MAX).count() * 8;
+                if skip + 8 <= len {
+                    return (u64_at(&self.bytes, at + 4 + skip), at + 4 + skip);
+                }
+            }
+            at += 4 + len;
+        }
+        (offset, 42)
+    }
+    
+    fn set_offset(&mut self, offset: u64) {
+        match self.offset().1 {
+            42 => self.bytes[42..46].copy_from_slice(&(offset as u32).to_le_bytes()),
+            at => self.bytes[at..at + 8].copy_from_slice(&offset.to_le_bytes()),
+        }
+    }
+}
+
+/// The central directory of a zip archive, as read from (and written back to) its end.
+pub struct Directory {
+    pub records: Vec<Record>,
+    
+    /// Where the entries start, including those dropped since, and where the central directory starts;
+    /// in order, so that each entry ends where the next one starts.
+    bounds: Vec<u64>,
+    start: u64,
+    zip64: bool,
+    comment: Vec<u8>,
+}
+
+impl Directory {
+    /// Reads the central directory of the finished zip archive.
+    pub fn read(file: &mut File) -> io::Result<Self> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        let tail_len = file_len.min((END_LEN + MAX_COMMENT_LEN) as u64);
+        let mut tail = vec![0u8; tail_len as usize];
+        file.seek(SeekFrom::End(-(tail_len as i64)))?;
+        file.read_exact(&mut tail)?;
+        
+        let end = (0..=tail.len().saturating_sub(END_LEN)).rev()
+            .find(|i| u32_at(&tail, *i) == END_SIGNATURE)
+            .ok_or_else(|| invalid("missing end of central directory"))?;
+        let end_offset = file_len - tail_len + end as u64;
+        
+        let comment_len = u16_at(&tail, end + 20) as usize;
+        let comment = tail.get(end + END_LEN..end + END_LEN + comment_len).unwrap_or_default().to_vec();
+        
+        let mut count = u16_at(&tail, end + 10) as u64;
+        let mut size = u32_at(&tail, end + 12) as u64;
+        let mut start = u32_at(&tail, end + 16) as u64;
+        
+        let mut locator = [0u8; 20];
+        let zip64 = end_offset >= 20 && {
+            file.seek(SeekFrom::Start(end_offset - 20))?;
+            file.read_exact(&mut locator)?;
+            u32_at(&locator, 0) == END64_LOCATOR_SIGNATURE
+        };
+        if zip64 {
+            let mut record = [0u8; 56];
+            file.seek(SeekFrom::Start(u64_at(&locator, 8)))?;
+            file.read_exact(&mut record)?;
+            if u32_at(&record, 0) != END64_SIGNATURE {
+                return Err(invalid("missing zip64 end of central directory"));
+            }
+            count = u64_at(&record, 32);
+            size = u64_at(&record, 40);
+            start = u64_at(&record, 48);
+        }
+        
+        let mut directory = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut directory)?;
+        
+        let mut records = Vec::with_capacity(count as usize);
+        let mut at = 0;
+        while at + 46 <= directory.len() {
+            if u32_at(&directory, at) != CENTRAL_SIGNATURE {
+                return Err(invalid("corrupt central directory"));
+            }
+            let name_len = u16_at(&directory, at + 28) as usize;
+            let len = 46 + name_len + u16_at(&directory, at + 30) as usize + u16_at(&directory, at + 32) as usize;
+            let bytes = directory.get(at..at + len).ok_or_else(|| invalid("corrupt central directory"))?.to_vec();
+            let name = String::from_utf8_lossy(&bytes[46..46 + name_len]).into_owned();
+            records.push(Record { name, bytes });
+            at += len;
+        }
+        
+        let mut bounds: Vec<u64> = records.iter().map(|r| r.offset().0).chain([start]).collect();
+        bounds.sort_unstable();
+        Ok(Self { records, bounds, start, zip64, comment })
+    }
+    
+    /// Rebuilds the central directory of an unfinished zip archive, from the local headers of its entries.
+    /// 
+    /// Scanning stops at the first entry that wasn't completely written; that and anything following it is dropped.
+    pub fn recover(file: &mut File) -> io::Result<Self> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = io::BufReader::new(&mut *file);
+        
+        let mut records = Vec::new();
+        let mut offset = 0u64;
+        let mut local = [0u8; 30];
+        while reader.read_exact(&mut local).is_ok() && u32_at(&local, 0) == LOCAL_SIGNATURE {
+            let name_len = u16_at(&local, 26) as usize;
+            let mut name_extra = vec![0u8; name_len + u16_at(&local, 28) as usize];
+            if reader.read_exact(&mut name_extra).is_err() {
+                break;
+            }
+            let (name, extra) = name_extra.split_at(name_len);
+            
+            let mut compressed_size = u32_at(&local, 18) as u64;
+            if compressed_size == u32::MAX as u64 {
+                // The zip64 extra field holds the uncompressed size, followed by the compressed one.
+                let mut at = 0;
+                while at + 4 <= extra.len() {
+                    let len = u16_at(extra, at + 2) as usize;
+                    if u16_at(extra, at) == 0x0001 && len >= 16 && at + 20 <= extra.len() {
+                        compressed_size = u64_at(extra, at + 12);
+                    }
+                    at += 4 + len;
+                }
+            }
+            
+            // Entries are only complete if they're followed by another entry, or by the end of the file.
+            let data_start = offset + 30 + name_extra.len() as u64;
+            let data_end = data_start + compressed_size;
+            if data_end > file_len || offset >= u32::MAX as u64 {
+                break;
+            }
+            reader.seek_relative(compressed_size as i64)?;
+            if data_end + 4 <= file_len {
+                let mut next = [0u8; 4];
+                reader.read_exact(&mut next)?;
+                reader.seek_relative(-4)?;
+                if ![LOCAL_SIGNATURE, CENTRAL_SIGNATURE].contains(&u32::from_le_bytes(next)) {
+                    break;
+                }
+            }
+            
+            let directory = name.ends_with(b"/");
+            let mut bytes = Vec::with_capacity(46 + name_extra.len());
+            bytes.extend(CENTRAL_SIGNATURE.to_le_bytes());
+            bytes.extend((3u16 << 8 | u16_at(&local, 4)).to_le_bytes());
+            bytes.extend(&local[4..30]);
+            bytes.extend(0u16.to_le_bytes()); // comment length
+            bytes.extend(0u16.to_le_bytes()); // disk number
+            bytes.extend(0u16.to_le_bytes()); // internal attributes
+            bytes.extend((if directory { 0o40755u32 } else { 0o100644 } << 16).to_le_bytes());
+            bytes.extend((offset as u32).to_le_bytes());
+            bytes.extend(&name_extra);
+            records.push(Record { name: String::from_utf8_lossy(name).into_owned(), bytes });
+            
+            offset = data_end;
+        }
+        
+        Ok(Self { records, bounds: Vec::new(), start: offset, zip64: false, comment: Vec::new() })
+    }
+    
+    /// Drops all but the last record of each of the given names.
+    pub fn keep_last(&mut self, names: &HashSet<String>) {
+        let mut seen = HashSet::new();
+        let mut records: Vec<_> = std::mem::take(&mut self.records).into_iter().rev()
+            .filter(|r| !names.contains(&r.name) || seen.insert(r.name.clone()))
+            .collect();
+        records.reverse();
+        self.records = records;
+    }
+    
+    /// Moves the entries down over the gaps left by those dropped, so that their data is gone from the file as well;
+    /// the central directory then starts where the last entry ends.
+    /// 
+    /// Entries only move by multiples of `align`, keeping the alignment of their data.
+    pub fn compact(&mut self, file: &mut File, align: u64) -> io::Result<()> {
+        let mut order: Vec<usize> = (0..self.records.len()).collect();
+        order.sort_by_key(|i| self.records[*i].offset().0);
+        
+        let mut to = 0;
+        let mut buffer = vec![0u8; 64 * 1024];
+        for i in order {
+            let from = self.records[i].offset().0;
+            let end = self.bounds.iter().copied().find(|b| *b > from).unwrap_or(self.start);
+            to += (from - to) % align;
+            if from != to {
+                // Copying front to back is safe, as the entries only move towards the start.
+                let mut at = 0;
+                while at < end - from {
+                    let len = buffer.len().min((end - from - at) as usize);
+                    file.seek(SeekFrom::Start(from + at))?;
+                    file.read_exact(&mut buffer[..len])?;
+                    file.seek(SeekFrom::Start(to + at))?;
+                    file.write_all(&buffer[..len])?;
+                    at += len as u64;
+                }
+                self.records[i].set_offset(to);
+            }
+            to += end - from;
+        }
+        self.start = to;
+        Ok(())
+    }
+    
+    /// Writes the (edited) central directory back in place, truncating whatever followed it.
+    pub fn write(&self, file: &mut File) -> io::Result<()> {
+        let mut out = Vec::new();
+        for record in &self.records {
+            out.extend(&record.bytes);
+        }
+        let size = out.len() as u64;
+        let count = self.records.len() as u64;
+        
+        if self.zip64 {
+            let record_start = self.start + size;
+            out.extend(END64_SIGNATURE.to_le_bytes());
+            out.extend(44u64.to_le_bytes());
+            out.extend((3u16 << 8 | 45).to_le_bytes());
+            out.extend(45u16.to_le_bytes());
+            out.extend(0u32.to_le_bytes());
+            out.extend(0u32.to_le_bytes());
+            out.extend(count.to_le_bytes());
+            out.extend(count.to_le_bytes());
+            out.extend(size.to_le_bytes());
+            out.extend(self.start.to_le_bytes());
+            
+            out.extend(END64_LOCATOR_SIGNATURE.to_le_bytes());
+            out.extend(0u32.to_le_bytes());
+            out.extend(record_start.to_le_bytes());
+            out.extend(1u32.to_le_bytes());
+        }
+        out.extend(END_SIGNATURE.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+        out.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+        out.extend((size.min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend((self.start.min(u32::MAX as u64) as u32).to_le_bytes());
+        out.extend((self.comment.len() as u16).to_le_bytes());
+        out.extend(&self.comment);
+        
+        file.seek(SeekFrom::Start(self.start))?;
+        file.write_all(&out)?;
+        file.set_len(self.start + out.len() as u64)?;
+        file.flush()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().expect("slice of 2 bytes"))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().expect("slice of 4 bytes"))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().expect("slice of 8 bytes"))
+}
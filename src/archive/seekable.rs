@@ -0,0 +1,76 @@
+//! Writing zstd streams in the seekable format, for random access into compressed tarballs.
+//!
+//! The content is cut into frames compressed independently of each other, followed by a table of their sizes
+//! within a skippable frame; other zstd decoders read it as a plain stream, skipping the table.
+
+use std::io::{self, Write};
+
+/// How much content goes into each frame; smaller frames are quicker to seek within, but compress worse.
+const FRAME_SIZE: usize = 1024 * 1024;
+
+/// The magic number of the skippable frame holding the seek table, and that of the table's footer.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+
+pub struct SeekableEncoder<W: Write> {
+    inner: W,
+    level: i32,
+    
+    /// The content of the frame that's yet to be written.
+    buffer: Vec<u8>,
+    
+    /// The compressed and decompressed sizes of the frames written so far.
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableEncoder<W> {
+    pub fn new(inner: W, level: i32) -> Self {
+        Self { inner, level, buffer: Vec::with_capacity(FRAME_SIZE), frames: Vec::new() }
+    }
+    
+    fn write_frame(&mut self, len: usize) -> io::Result<()> {
+        let frame = zstd::bulk::compress(&self.buffer[..len], self.level)?;
+        self.inner.write_all(&frame)?;
+        self.frames.push((frame.len() as u32, len as u32));
+        self.buffer.drain(..len);
+        Ok(())
+    }
+    
+    /// Writes the last frame and the seek table, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() {
+            self.write_frame(self.buffer.len())?;
+        }
+        
+        // Without checksums, which would need xxHash.
+        let mut table = Vec::with_capacity(8 + self.frames.len() * 8 + 9);
+        table.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend((self.frames.len() as u32 * 8 + 9).to_le_bytes());
+        for (compressed, decompressed) in &self.frames {
+            table.extend(compressed.to_le_bytes());
+            table.extend(decompressed.to_le_bytes());
+        }
+        table.extend((self.frames.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend(SEEKABLE_MAGIC.to_le_bytes());
+        self.inner.write_all(&table)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SeekableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(FRAME_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == FRAME_SIZE {
+            self.write_frame(FRAME_SIZE)?;
+        }
+        Ok(len)
+    }
+    
+    /// Frames are only ever cut once they're full, so that flushing doesn't hurt the compression.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,288 @@
+//! Writing 7z archives, with the content of all entries compressed as one solid stream.
+//!
+//! The stream follows the signature header right away, while the names, sizes and checksums of the entries
+//! are collected along the way and written into the header at the end; so the file must be seekable.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use super::{ArchiveWriter, Compression, Modes, Options};
+
+/// The signature every 7z archive starts with, followed by the format version 0.4.
+const SIGNATURE: [u8; 8] = [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C, 0, 4];
+
+/// The size of the signature header, which the packed stream follows.
+const SIGNATURE_HEADER_SIZE: u64 = 32;
+
+/// The seconds between 1601, where Windows file times start, and the Unix epoch.
+const FILETIME_EPOCH: u64 = 11_644_473_600;
+
+/// The property IDs of the header.
+mod id {
+    pub const END: u8 = 0x00;
+    pub const HEADER: u8 = 0x01;
+    pub const MAIN_STREAMS_INFO: u8 = 0x04;
+    pub const FILES_INFO: u8 = 0x05;
+    pub const PACK_INFO: u8 = 0x06;
+    pub const UNPACK_INFO: u8 = 0x07;
+    pub const SUBSTREAMS_INFO: u8 = 0x08;
+    pub const SIZE: u8 = 0x09;
+    pub const CRC: u8 = 0x0A;
+    pub const FOLDER: u8 = 0x0B;
+    pub const CODERS_UNPACK_SIZE: u8 = 0x0C;
+    pub const NUM_UNPACK_STREAM: u8 = 0x0D;
+    pub const EMPTY_STREAM: u8 = 0x0E;
+    pub const EMPTY_FILE: u8 = 0x0F;
+    pub const NAME: u8 = 0x11;
+    pub const MTIME: u8 = 0x14;
+    pub const ATTRIBUTES: u8 = 0x15;
+}
+
+/// The attributes telling directories apart, and that the upper 16 bits hold the Unix mode.
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+const ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+/// Compresses the content of the entries, in the order they were appended.
+enum Packer {
+    Copy(BufWriter<File>),
+    Deflate(flate2::write::DeflateEncoder<BufWriter<File>>),
+    Bzip2(bzip2::write::BzEncoder<BufWriter<File>>),
+}
+
+impl Packer {
+    /// The ID of the coder, as listed in the header.
+    fn coder(&self) -> &'static [u8] {
+        match self {
+            Packer::Copy(_) => &[0x00],
+            Packer::Deflate(_) => &[0x04, 0x01, 0x08],
+            Packer::Bzip2(_) => &[0x04, 0x02, 0x02],
+        }
+    }
+    
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Packer::Copy(writer) => writer,
+            Packer::Deflate(encoder) => encoder,
+            Packer::Bzip2(encoder) => encoder,
+        }
+    }
+    
+    fn finish(self) -> io::Result<File> {
+        let writer = match self {
+            Packer::Copy(writer) => writer,
+            Packer::Deflate(encoder) => encoder.finish()?,
+            Packer::Bzip2(encoder) => encoder.finish()?,
+        };
+        writer.into_inner().map_err(io::IntoInnerError::into_error)
+    }
+}
+
+struct Entry {
+    name: String,
+    size: u64,
+    crc: u32,
+    directory: bool,
+    mode: u32,
+}
+
+pub struct SevenZipArchive {
+    packer: Packer,
+    entries: Vec<Entry>,
+    
+    /// The modification time of all entries, as a Windows file time.
+    mtime: u64,
+    modes: Modes,
+}
+
+impl SevenZipArchive {
+    /// Creates the archive, compressing with bzip2 unless told otherwise; zstd isn't supported by 7-Zip itself.
+    pub fn create(path: &Path, options: &Options) -> io::Result<Self> {
+        let level = |range: std::ops::RangeInclusive<i32>, default: i32| match options.level {
+            Some(level) if !range.contains(&level) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("compression level must be within {range:?}, not {level}")
+            )),
+            level => Ok(level.unwrap_or(default) as u32),
+        };
+        
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&[0; SIGNATURE_HEADER_SIZE as usize])?;
+        let packer = match options.compression.unwrap_or(Compression::Bzip2) {
+            Compression::Stored => Packer::Copy(file),
+            Compression::Deflate => Packer::Deflate(flate2::write::DeflateEncoder::new(file, flate2::Compression::new(level(0..=9, 6)?))),
+            Compression::Bzip2 => Packer::Bzip2(bzip2::write::BzEncoder::new(file, bzip2::Compression::new(level(1..=9, 9)?))),
+            Compression::Zstd => return Err(io::Error::new(io::ErrorKind::Unsupported, "7z archives can't be compressed with zstd")),
+        };
+        
+        let mtime = options.mtime().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Ok(Self { packer, entries: Vec::new(), mtime: (mtime + FILETIME_EPOCH) * 10_000_000, modes: options.modes.clone() })
+    }
+    
+    /// The header listing the packed stream, decoded by the coder of the given ID, and the entries within it.
+    fn header(entries: &[Entry], coder: &[u8], mtime: u64, packed: u64) -> Vec<u8> {
+        let streams: Vec<&Entry> = entries.iter().filter(|e| e.size > 0).collect();
+        
+        let mut header = vec![id::HEADER];
+        if !streams.is_empty() {
+            header.push(id::MAIN_STREAMS_INFO);
+            
+            header.push(id::PACK_INFO);
+            write_number(&mut header, 0);
+            write_number(&mut header, 1);
+            header.push(id::SIZE);
+            write_number(&mut header, packed);
+            header.push(id::END);
+            
+            // A single folder, decoded by a single coder without properties.
+            header.extend([id::UNPACK_INFO, id::FOLDER]);
+            write_number(&mut header, 1);
+            header.push(0);
+            write_number(&mut header, 1);
+            header.push(coder.len() as u8);
+            header.extend(coder);
+            header.push(id::CODERS_UNPACK_SIZE);
+            write_number(&mut header, streams.iter().map(|e| e.size).sum());
+            header.push(id::END);
+            
+            header.extend([id::SUBSTREAMS_INFO, id::NUM_UNPACK_STREAM]);
+            write_number(&mut header, streams.len() as u64);
+            header.push(id::SIZE);
+            for entry in &streams[..streams.len() - 1] {
+                write_number(&mut header, entry.size);
+            }
+            header.extend([id::CRC, 1]);
+            for entry in &streams {
+                header.extend(entry.crc.to_le_bytes());
+            }
+            header.push(id::END);
+            
+            header.push(id::END);
+        }
+        
+        header.push(id::FILES_INFO);
+        write_number(&mut header, entries.len() as u64);
+        
+        let empty: Vec<&Entry> = entries.iter().filter(|e| e.size == 0).collect();
+        if !empty.is_empty() {
+            property(&mut header, id::EMPTY_STREAM, bits(entries.iter().map(|e| e.size == 0)));
+            property(&mut header, id::EMPTY_FILE, bits(empty.iter().map(|e| !e.directory)));
+        }
+        
+        let mut names = vec![0];
+        for entry in entries {
+            names.extend(entry.name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        }
+        property(&mut header, id::NAME, names);
+        
+        let mut mtimes = vec![1, 0];
+        mtimes.extend(entries.iter().flat_map(|_| mtime.to_le_bytes()));
+        property(&mut header, id::MTIME, mtimes);
+        
+        let mut attributes = vec![1, 0];
+        for entry in entries {
+            let attribute = match entry.directory {
+                true => ATTRIBUTE_DIRECTORY | ATTRIBUTE_UNIX_EXTENSION | (0o040000 | entry.mode) << 16,
+                false => ATTRIBUTE_UNIX_EXTENSION | (0o100000 | entry.mode) << 16,
+            };
+            attributes.extend(attribute.to_le_bytes());
+        }
+        property(&mut header, id::ATTRIBUTES, attributes);
+        
+        header.extend([id::END, id::END]);
+        header
+    }
+}
+
+impl ArchiveWriter for SevenZipArchive {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        let mut content = flate2::CrcReader::new(content.take(size));
+        let copied = io::copy(&mut content, self.packer.writer())?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry content ended early"));
+        }
+        let mode = self.modes.of(name);
+        self.entries.push(Entry { name: name.to_string(), size, crc: content.crc().sum(), directory: false, mode });
+        Ok(())
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.entries.push(Entry { name: name.to_string(), size: 0, crc: 0, directory: true, mode: 0o755 });
+        Ok(())
+    }
+    
+    /// The content of entries is part of the solid stream for good, but extracting lets later entries overwrite earlier ones.
+    fn remove(&mut self, _name: &str) {}
+    
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let Self { packer, entries, mtime, .. } = *self;
+        let coder = packer.coder();
+        let mut file = packer.finish()?;
+        let end = file.stream_position()?;
+        
+        let header = match entries.is_empty() {
+            // Even the header is left out of empty archives.
+            true => Vec::new(),
+            false => Self::header(&entries, coder, mtime, end - SIGNATURE_HEADER_SIZE),
+        };
+        file.write_all(&header)?;
+        
+        let mut start = Vec::with_capacity(20);
+        start.extend((end - SIGNATURE_HEADER_SIZE).to_le_bytes());
+        start.extend((header.len() as u64).to_le_bytes());
+        start.extend(crc(&header).to_le_bytes());
+        
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&SIGNATURE)?;
+        file.write_all(&crc(&start).to_le_bytes())?;
+        file.write_all(&start)?;
+        file.flush()
+    }
+}
+
+fn crc(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Writes a number in the variable-length encoding of 7z: the leading one bits of the first byte tell
+/// how many bytes follow, little-endian, with the rest of the first byte holding the highest bits.
+fn write_number(out: &mut Vec<u8>, value: u64) {
+    let (mut first, mut mask, mut count) = (0u8, 0x80u8, 0);
+    while count < 8 {
+        if value < 1 << (7 * (count + 1)) {
+            first |= (value >> (8 * count)) as u8;
+            break;
+        }
+        first |= mask;
+        mask >>= 1;
+        count += 1;
+    }
+    out.push(first);
+    out.extend(&value.to_le_bytes()[..count]);
+}
+
+/// Writes a property of the files, preceded by its ID and size.
+fn property(out: &mut Vec<u8>, id: u8, data: Vec<u8>) {
+    out.push(id);
+    write_number(out, data.len() as u64);
+    out.extend(data);
+}
+
+/// Packs the flags into bytes, the first one into the highest bit.
+fn bits(flags: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, flag) in flags.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if flag {
+            *bytes.last_mut().expect("pushed above") |= 0x80 >> (i % 8);
+        }
+    }
+    bytes
+}
@@ -0,0 +1,170 @@
+//! Spreading entries over multiple archives, by size and/or by name.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use super::{ArchiveWriter, Compression, Format, Options};
+use crate::{Notice, Notify};
+
+/// One of the archives entries are routed to.
+struct Part {
+    writer: Box<dyn ArchiveWriter>,
+    path: PathBuf,
+    index: usize,
+    entries: usize,
+    
+    /// How many more bytes finishing the part takes, at most; e.g. for the central directory of zip archives.
+    reserved: u64,
+}
+
+/// A generous estimate of the bytes an entry takes in addition to its content and name, in either format.
+const ENTRY_OVERHEAD: u64 = 1024 + 128;
+
+/// A generous estimate of the bytes finishing an archive takes, in addition to the entries' overhead.
+const FINISH_OVERHEAD: u64 = 1024 + 128;
+
+/// Routes entries into `NAME.SHARD.NNN.EXT` archives, rolling over to the next number once a part would grow too large.
+pub struct SplitArchive {
+    format: Format,
+    options: Options,
+    stem: PathBuf,
+    extension: &'static str,
+    size: Option<u64>,
+    shard_by: Option<Regex>,
+    parts: HashMap<String, Part>,
+    
+    /// The comment every part gets, once it's finished.
+    comment: Option<String>,
+    
+    notify: Notify,
+}
+
+impl SplitArchive {
+    pub fn new(format: Format, path: &Path, options: &Options, size: Option<u64>, shard_by: Option<Regex>, notify: Notify) -> Self {
+        let extension = match format {
+            Format::Zip => ".zip",
+            Format::Tar => ".tar",
+            Format::TarGz => ".tar.gz",
+            Format::TarZst | Format::TarZstSeekable => ".tar.zst",
+            Format::SevenZip => ".7z",
+            Format::Dir => "",
+        };
+        let path = path.to_string_lossy();
+        let stem = path.strip_suffix(extension).unwrap_or(&path).into();
+        
+        Self {
+            format,
+            options: options.clone(),
+            stem,
+            extension,
+            size,
+            shard_by,
+            parts: HashMap::new(),
+            comment: None,
+            notify,
+        }
+    }
+    
+    /// The shard an entry goes into: the first capture group of the pattern (or the whole match), if any.
+    fn shard(&self, name: &str) -> String {
+        let Some(captures) = self.shard_by.as_ref().and_then(|r| r.captures(name)) else {
+            return String::new();
+        };
+        let shard = captures.get(1).or(captures.get(0)).map_or("", |m| m.as_str());
+        shard.replace(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == '.'), "_")
+    }
+    
+    fn path(&self, shard: &str, index: usize) -> PathBuf {
+        let mut path = self.stem.clone().into_os_string();
+        if !shard.is_empty() {
+            path.push(format!(".{shard}"));
+        }
+        if self.size.is_some() {
+            path.push(format!(".{index:03}"));
+        }
+        path.push(self.extension);
+        path.into()
+    }
+    
+    /// Returns the part the entry goes into, rolling over to a new one if it would grow too large.
+    fn part(&mut self, name: &str, size: u64) -> io::Result<&mut Part> {
+        let shard = self.shard(name);
+        
+        let mut index = 1;
+        if let (Some(part), Some(limit)) = (self.parts.get(&shard), self.size) {
+            // Even if it doesn't fit, an entry has to go somewhere; so parts hold at least one.
+            let needed = size + ENTRY_OVERHEAD + 2 * name.len() as u64;
+            if part.entries > 0 && std::fs::metadata(super::part_path(&part.path))?.len() + part.reserved + needed > limit {
+                let mut part = self.parts.remove(&shard).expect("part exists");
+                index = part.index + 1;
+                if let Some(comment) = &self.comment {
+                    part.writer.set_comment(comment);
+                }
+                part.writer.finish()?;
+            }
+        }
+        
+        if !self.parts.contains_key(&shard) {
+            let path = self.path(&shard, index);
+            (self.notify)(Notice::Info(0, format!("Writing to archive `{}`", path.display())));
+            let writer = super::open(self.format, &path, false, &self.options)?;
+            self.parts.insert(shard.clone(), Part { writer, path, index, entries: 0, reserved: FINISH_OVERHEAD });
+        }
+        Ok(self.parts.get_mut(&shard).expect("part exists"))
+    }
+}
+
+impl ArchiveWriter for SplitArchive {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        let part = self.part(name, size)?;
+        part.writer.append(name, content, size)?;
+        part.entries += 1;
+        part.reserved += 128 + name.len() as u64;
+        Ok(())
+    }
+    
+    fn append_with(&mut self, name: &str, content: &mut dyn Read, size: u64, compression: Compression, level: Option<i32>) -> io::Result<()> {
+        let part = self.part(name, size)?;
+        part.writer.append_with(name, content, size, compression, level)?;
+        part.entries += 1;
+        part.reserved += 128 + name.len() as u64;
+        Ok(())
+    }
+    
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.part(name, 0)?.writer.add_directory(name)
+    }
+    
+    fn comment(&mut self, name: &str, comment: &str) {
+        if let Some(part) = self.parts.get_mut(&self.shard(name)) {
+            part.writer.comment(name, comment);
+            part.reserved += comment.len() as u64;
+        }
+    }
+    
+    fn set_comment(&mut self, comment: &str) {
+        self.comment = Some(comment.to_string());
+    }
+    
+    /// Entries can only be removed from parts that weren't finished yet.
+    fn remove(&mut self, name: &str) {
+        for part in self.parts.values_mut() {
+            part.writer.remove(name);
+        }
+    }
+    
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        for mut part in self.parts.into_values() {
+            if let Some(comment) = &self.comment {
+                part.writer.set_comment(comment);
+            }
+            part.writer.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//! A minimal zip writer that never seeks, so it can write to pipes;
+//! it also produces WinZip AES-256 (AE-2) encrypted entries, since the zip crate can only *read* those.
+
+use std::io::{self, Read, Write};
+
+use aes::cipher::{BlockEncrypt, KeyInit, generic_array::GenericArray};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use super::{ArchiveWriter, Compression, Options, Zip64};
+use crate::capture::Capture;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const AUTH_CODE_LEN: usize = 10;
+const PBKDF2_ROUNDS: u32 = 1000;
+
+/// Version 5.1 is the minimum for AES encryption, 4.5 for zip64.
+const VERSION_AES: u16 = 51;
+const VERSION_ZIP64: u16 = 45;
+const VERSION_DEFAULT: u16 = 20;
+const METHOD_AES: u16 = 99;
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+const FLAG_UTF8: u16 = 1 << 11;
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    time: u16,
+    date: u16,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    offset: u64,
+    mode: u32,
+    comment: String,
+}
+
+pub struct StreamZipArchive<W: Write> {
+    inner: W,
+    offset: u64,
+    password: Option<Vec<u8>>,
+    options: Options,
+    entries: Vec<Entry>,
+    comment: String,
+}
+
+impl<W: Write> StreamZipArchive<W> {
+    /// Entries are encrypted if there's a password.
+    pub fn new(inner: W, password: Option<&[u8]>, options: &Options) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            password: password.map(<[u8]>::to_vec),
+            options: options.clone(),
+            entries: Vec::new(),
+            comment: String::new(),
+        }
+    }
+    
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+    
+    /// Writes a local or central file header; they only differ in a few fields.
+    fn write_header(&mut self, entry: &Entry, central: bool) -> io::Result<()> {
+        let zip64 = self.options.zip64 == Zip64::Always
+            || entry.compressed_size >= u32::MAX as u64
+            || entry.uncompressed_size >= u32::MAX as u64
+            || (central && entry.offset >= u32::MAX as u64);
+        
+        let encrypted = self.password.is_some();
+        let directory = entry.name.ends_with('/');
+        
+        let mut extra = Vec::with_capacity(40);
+        if zip64 {
+            let mut fields = vec![entry.uncompressed_size, entry.compressed_size];
+            if central {
+                fields.push(entry.offset);
+            }
+            extra.extend(0x0001u16.to_le_bytes());
+            extra.extend((fields.len() as u16 * 8).to_le_bytes());
+            fields.iter().for_each(|f| extra.extend(f.to_le_bytes()));
+        }
+        if encrypted {
+            // The AES extra field: AE-2, vendor "AE", AES-256, and the actual compression method.
+            extra.extend(0x9901u16.to_le_bytes());
+            extra.extend(7u16.to_le_bytes());
+            extra.extend(2u16.to_le_bytes());
+            extra.extend(b"AE");
+            extra.push(3);
+            extra.extend(entry.method.to_le_bytes());
+        }
+        if let Some(align) = self.options.align.filter(|a| *a > 1 && !central && !encrypted && entry.method == 0) {
+            // Pads the extra field so that the data starts at the boundary, like the zip crate does.
+            let align = align as u64;
+            let start = self.offset + 30 + entry.name.len() as u64 + extra.len() as u64 + 4;
+            let padding = (align - start % align) % align;
+            extra.extend(b"za");
+            extra.extend((padding as u16).to_le_bytes());
+            extra.resize(extra.len() + padding as usize, 0);
+        }
+        
+        let version = match (encrypted, zip64) {
+            (true, _) => VERSION_AES,
+            (false, true) => VERSION_ZIP64,
+            (false, false) => VERSION_DEFAULT,
+        };
+        let flags = if encrypted { FLAG_ENCRYPTED | FLAG_UTF8 } else { FLAG_UTF8 };
+        
+        let clamp = |n: u64| if zip64 { u32::MAX } else { n as u32 };
+        
+        let mut header = Vec::with_capacity(46 + entry.name.len() + extra.len());
+        if central {
+            header.extend(0x02014b50u32.to_le_bytes());
+            // Made by unix, so that the permissions are honored.
+            header.extend((3 << 8 | version).to_le_bytes());
+        } else {
+            header.extend(0x04034b50u32.to_le_bytes());
+        }
+        header.extend(version.to_le_bytes());
+        header.extend(flags.to_le_bytes());
+        header.extend(if encrypted { METHOD_AES } else { entry.method }.to_le_bytes());
+        header.extend(entry.time.to_le_bytes());
+        header.extend(entry.date.to_le_bytes());
+        // AE-2 omits the CRC, as the authentication code covers it.
+        header.extend(if encrypted { 0 } else { entry.crc }.to_le_bytes());
+        header.extend(clamp(entry.compressed_size).to_le_bytes());
+        header.extend(clamp(entry.uncompressed_size).to_le_bytes());
+        header.extend((entry.name.len() as u16).to_le_bytes());
+        header.extend((extra.len() as u16).to_le_bytes());
+        if central {
+            header.extend((entry.comment.len() as u16).to_le_bytes());
+            header.extend(0u16.to_le_bytes()); // disk number
+            header.extend(0u16.to_le_bytes()); // internal attributes
+            header.extend((if directory { 0o40755u32 } else { 0o100000 | entry.mode } << 16).to_le_bytes());
+            header.extend(clamp(entry.offset).to_le_bytes());
+        }
+        header.extend(entry.name.as_bytes());
+        header.extend(extra);
+        if central {
+            header.extend(entry.comment.as_bytes());
+        }
+        self.write(&header)
+    }
+}
+
+impl<W: Write> StreamZipArchive<W> {
+    fn write_plain(&mut self, mut entry: Entry, compressed: &mut Capture) -> io::Result<()> {
+        entry.compressed_size = compressed.len();
+        self.write_header(&entry, false)?;
+        let mut reader = compressed.reader()?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.write(&buffer[..read])?;
+        }
+        drop(reader);
+        self.entries.push(entry);
+        self.inner.flush()
+    }
+    
+    fn write_encrypted(&mut self, mut entry: Entry, compressed: &mut Capture) -> io::Result<()> {
+        let password = self.password.as_deref().expect("entry is encrypted");
+        let salt = random_salt();
+        let mut keys = [0u8; KEY_LEN * 2 + 2];
+        pbkdf2::pbkdf2::<Hmac<Sha1>>(password, &salt, PBKDF2_ROUNDS, &mut keys);
+        let (aes_key, rest) = keys.split_at(KEY_LEN);
+        let (hmac_key, verifier) = rest.split_at(KEY_LEN);
+        
+        entry.compressed_size = (SALT_LEN + verifier.len() + AUTH_CODE_LEN) as u64 + compressed.len();
+        self.write_header(&entry, false)?;
+        self.write(&salt)?;
+        self.write(verifier)?;
+        
+        let mut cipher = AesCtr::new(aes_key);
+        let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(hmac_key).expect("HMAC accepts any key length");
+        let mut reader = compressed.reader()?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            cipher.apply(&mut buffer[..read]);
+            mac.update(&buffer[..read]);
+            self.write(&buffer[..read])?;
+        }
+        drop(reader);
+        
+        let auth_code = mac.finalize().into_bytes();
+        self.write(&auth_code[..AUTH_CODE_LEN])?;
+        self.entries.push(entry);
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Send> StreamZipArchive<W> {
+    fn append_using(&mut self, name: &str, content: &mut dyn Read, size: u64, compression: Compression, level: Option<i32>) -> io::Result<()> {
+        // The sizes go into the header, so the data has to be compressed up-front.
+        let mut compressed = Capture::default();
+        let mut content = flate2::CrcReader::new(content);
+        let method = compress(&mut content, &mut compressed, compression, level)?;
+        
+        let mtime = zip::DateTime::try_from(time::OffsetDateTime::from(self.options.mtime())).unwrap_or_default();
+        let entry = Entry {
+            name: name.to_string(),
+            method,
+            crc: content.crc().sum(),
+            time: mtime.timepart(),
+            date: mtime.datepart(),
+            compressed_size: 0,
+            uncompressed_size: size,
+            offset: self.offset,
+            mode: self.options.modes.of(name),
+            comment: String::new(),
+        };
+        
+        match self.password {
+            Some(_) => self.write_encrypted(entry, &mut compressed),
+            None => self.write_plain(entry, &mut compressed),
+        }
+    }
+}
+
+impl<W: Write + Send> ArchiveWriter for StreamZipArchive<W> {
+    fn append(&mut self, name: &str, content: &mut dyn Read, size: u64) -> io::Result<()> {
+        self.append_using(name, content, size, self.options.compression.unwrap_or(Compression::Deflate), self.options.level)
+    }
+    
+    fn append_with(&mut self, name: &str, content: &mut dyn Read, size: u64, compression: Compression, level: Option<i32>) -> io::Result<()> {
+        self.append_using(name, content, size, compression, level)
+    }
+    
+    /// Written as an empty entry; extractors recognize directories by the trailing `/` alone.
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.append(&format!("{name}/"), &mut io::empty(), 0)
+    }
+    
+    fn remove(&mut self, name: &str) {
+        // The entry's data stays in place, but the central directory won't refer to it.
+        self.entries.retain(|e| e.name != name);
+    }
+    
+    fn comment(&mut self, name: &str, comment: &str) {
+        if let Some(entry) = self.entries.iter_mut().rev().find(|e| e.name == name) {
+            // Cut off at the 64 KiB a comment can hold, without splitting a character.
+            let len = (0..=comment.len().min(u16::MAX as usize)).rev().find(|i| comment.is_char_boundary(*i)).unwrap_or(0);
+            entry.comment = comment[..len].to_string();
+        }
+    }
+    
+    fn set_comment(&mut self, comment: &str) {
+        let len = (0..=comment.len().min(u16::MAX as usize)).rev().find(|i| comment.is_char_boundary(*i)).unwrap_or(0);
+        self.comment = comment[..len].to_string();
+    }
+    
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let entries = std::mem::take(&mut self.entries);
+        let directory_start = self.offset;
+        for entry in &entries {
+            self.write_header(entry, true)?;
+        }
+        let directory_size = self.offset - directory_start;
+        let count = entries.len() as u64;
+        
+        let mut end = Vec::with_capacity(98);
+        let zip64 = directory_start >= u32::MAX as u64 || count >= u16::MAX as u64;
+        if zip64 {
+            let record_start = self.offset;
+            end.extend(0x06064b50u32.to_le_bytes());
+            end.extend(44u64.to_le_bytes());
+            end.extend((3 << 8 | VERSION_ZIP64).to_le_bytes());
+            end.extend(45u16.to_le_bytes());
+            end.extend(0u32.to_le_bytes());
+            end.extend(0u32.to_le_bytes());
+            end.extend(count.to_le_bytes());
+            end.extend(count.to_le_bytes());
+            end.extend(directory_size.to_le_bytes());
+            end.extend(directory_start.to_le_bytes());
+            
+            end.extend(0x07064b50u32.to_le_bytes());
+            end.extend(0u32.to_le_bytes());
+            end.extend(record_start.to_le_bytes());
+            end.extend(1u32.to_le_bytes());
+        }
+        end.extend(0x06054b50u32.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+        end.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+        end.extend((directory_size.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend((directory_start.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend((self.comment.len() as u16).to_le_bytes());
+        end.extend(self.comment.as_bytes());
+        self.write(&end)?;
+        self.inner.flush()
+    }
+}
+
+/// Compresses the content into the output, returning the zip method-id used.
+fn compress(content: &mut dyn Read, output: &mut Capture, compression: Compression, level: Option<i32>) -> io::Result<u16> {
+    Ok(match compression {
+        Compression::Stored => {
+            io::copy(content, output)?;
+            0
+        },
+        Compression::Deflate => {
+            let level = flate2::Compression::new(level.unwrap_or(6).clamp(0, 9) as u32);
+            let mut encoder = flate2::write::DeflateEncoder::new(output, level);
+            io::copy(content, &mut encoder)?;
+            encoder.finish()?;
+            8
+        },
+        Compression::Bzip2 => {
+            let level = bzip2::Compression::new(level.unwrap_or(6).clamp(1, 9) as u32);
+            let mut encoder = bzip2::write::BzEncoder::new(output, level);
+            io::copy(content, &mut encoder)?;
+            encoder.finish()?;
+            12
+        },
+        Compression::Zstd => {
+            zstd::stream::copy_encode(content, output, level.unwrap_or(0))?;
+            93
+        },
+    })
+}
+
+/// AES-256 in the little-endian CTR mode WinZip specifies.
+struct AesCtr {
+    cipher: aes::Aes256,
+    counter: u64,
+    keystream: [u8; 16],
+    used: usize,
+}
+
+impl AesCtr {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: aes::Aes256::new(GenericArray::from_slice(key)),
+            counter: 0,
+            keystream: [0; 16],
+            used: 16,
+        }
+    }
+    
+    /// Encrypts (or decrypts) the data in-place, continuing where the last call left off.
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == 16 {
+                self.counter += 1;
+                let mut block = [0u8; 16];
+                block[..8].copy_from_slice(&self.counter.to_le_bytes());
+                let mut block = GenericArray::from(block);
+                self.cipher.encrypt_block(&mut block);
+                self.keystream = block.into();
+                self.used = 0;
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+/// Salts only need to be unique, but use the OS's randomness where available.
+fn random_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    if let Ok(mut urandom) = std::fs::File::open("/dev/urandom") {
+        if urandom.read_exact(&mut salt).is_ok() {
+            return salt;
+        }
+    }
+    
+    use std::hash::{BuildHasher, Hasher};
+    for chunk in salt.chunks_mut(8) {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    salt
+}
@@ -0,0 +1,131 @@
+use std::{
+    fs::File,
+    path::PathBuf,
+    io::{self, Read, Write, Seek, SeekFrom},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+/// How many bytes of output are kept in memory, before spilling the rest to disk.
+pub const SPILL_THRESHOLD: usize = 8 * 1024 * 1024;
+
+/// How many bytes of output all captures together hold in memory, and how many they may; see [`set_memory_budget`].
+static HELD: AtomicU64 = AtomicU64::new(0);
+static BUDGET: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Caps the bytes of output held in memory by all captures together, whether their commands are still running or
+/// their output is waiting for the archive writer; once it's reached, further output is spilled to disk right away.
+pub fn set_memory_budget(bytes: u64) {
+    BUDGET.store(bytes, Ordering::Relaxed);
+}
+
+/// How many bytes of output all captures together hold in memory right now.
+pub fn held() -> u64 {
+    HELD.load(Ordering::Relaxed)
+}
+
+/// Output captured from a child-process.
+/// 
+/// The first [`SPILL_THRESHOLD`] bytes are kept in memory;
+/// anything beyond that goes into a temporary file, so memory use stays bounded.
+/// So does anything beyond the memory budget, if there is one.
+#[derive(Debug, Default)]
+pub struct Capture {
+    head: Vec<u8>,
+    tail: Option<Spill>,
+    len: u64,
+}
+
+#[derive(Debug)]
+struct Spill {
+    file: File,
+    path: PathBuf,
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+impl Capture {
+    /// The total number of captured bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    
+    /// The part of the output that is held in memory; all of it, unless it was spilled.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+    
+    /// Returns a reader over the entire captured output.
+    pub fn reader(&mut self) -> io::Result<impl Read + '_> {
+        let tail: Box<dyn Read> = match &mut self.tail {
+            Some(spill) => {
+                spill.file.seek(SeekFrom::Start(0))?;
+                Box::new(&spill.file)
+            },
+            None => Box::new(io::empty()),
+        };
+        Ok(self.head.as_slice().chain(tail))
+    }
+}
+
+impl From<Vec<u8>> for Capture {
+    fn from(head: Vec<u8>) -> Self {
+        HELD.fetch_add(head.len() as u64, Ordering::Relaxed);
+        Self { len: head.len() as u64, head, tail: None }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        HELD.fetch_sub(self.head.len() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let budget = BUDGET.load(Ordering::Relaxed).saturating_sub(held());
+        let room = SPILL_THRESHOLD.saturating_sub(self.head.len()).min(budget.try_into().unwrap_or(usize::MAX));
+        // Once anything was spilled, the rest must follow it, to keep the order.
+        let written = if room > 0 && self.tail.is_none() {
+            let written = buf.len().min(room);
+            self.head.extend_from_slice(&buf[..written]);
+            HELD.fetch_add(written as u64, Ordering::Relaxed);
+            written
+        } else {
+            let spill = match &mut self.tail {
+                Some(spill) => spill,
+                None => self.tail.insert(Spill::create()?),
+            };
+            spill.file.write(buf)?
+        };
+        self.len += written as u64;
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.tail {
+            Some(spill) => spill.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Spill {
+    fn create() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cmd2zip-{}-{}.spill",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Self { file, path })
+    }
+}
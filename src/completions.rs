@@ -0,0 +1,40 @@
+//! Generating shell completions and a man page from the command-line definition, for packagers.
+
+use std::io::{self, Write};
+
+use clap::Command;
+
+/// What to generate instead of running any commands; subcommands of their own.
+#[derive(Debug, clap::Subcommand)]
+pub enum Generate {
+    /// Print completions for the given shell.
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    
+    /// Print the man page, in roff.
+    Manpage,
+}
+
+impl Generate {
+    /// Prints what's to be generated for the command to stdout.
+    pub fn print(self, mut command: Command) -> io::Result<()> {
+        // Generated into memory first, as `clap_complete` panics on failing writes, like into a closed pipe.
+        let mut out = Vec::new();
+        match self {
+            Generate::Completions { shell } => {
+                let name = command.get_name().to_string();
+                clap_complete::generate(shell, &mut command, name, &mut out);
+            },
+            // Neither is part of the command, as there's no `--version` and the help starts with a heading.
+            Generate::Manpage => {
+                let command = command.version(env!("CARGO_PKG_VERSION")).disable_version_flag(true).about(env!("CARGO_PKG_DESCRIPTION"));
+                clap_mangen::Man::new(command).render(&mut out)?;
+            },
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&out)?;
+        stdout.flush()
+    }
+}
@@ -0,0 +1,147 @@
+//! Reading options from a config file, for invocations too long for the shell history.
+//!
+//! The file is a flat subset of TOML: `key = value` pairs, where keys are the long names of options
+//! and values are strings, numbers, booleans or (single-line) arrays thereof.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// The config file picked up from the current directory, unless `--config` or `--no-config` is given.
+pub const DEFAULT_PATH: &str = ".cmd2zip.toml";
+
+/// Finds the config file to use, by peeking at the raw arguments.
+pub fn find(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|a| a.to_string_lossy()).take_while(|a| a != "--");
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--no-config" => return None,
+            "--config" => return args.next().map(|path| PathBuf::from(path.as_ref())),
+            arg => if let Some(path) = arg.strip_prefix("--config=") {
+                return Some(path.into());
+            },
+        }
+    }
+    Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.is_file())
+}
+
+/// Reads the config file, turning its pairs into arguments like `--key=value`.
+pub fn load(path: &Path) -> Result<Vec<OsString>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut args = Vec::new();
+    
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}", i + 1);
+        
+        let (key, value) = line.split_once('=').ok_or_else(|| error("expected `key = value`"))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() || key.starts_with('[') {
+            return Err(error("tables aren't supported; use plain `key = value` pairs"));
+        }
+        
+        let value = value.trim();
+        let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(items) => split_array(items).into_iter().map(scalar).collect::<Result<Vec<_>, _>>(),
+            None => scalar(value).map(|v| vec![v]),
+        }.map_err(|err| error(&err))?;
+        
+        for value in values {
+            match value {
+                Scalar::Bool(true) => args.push(format!("--{key}").into()),
+                Scalar::Bool(false) => {},
+                Scalar::Text(text) => args.push(format!("--{key}={text}").into()),
+            }
+        }
+    }
+    Ok(args)
+}
+
+enum Scalar {
+    Bool(bool),
+    Text(String),
+}
+
+fn scalar(value: &str) -> Result<Scalar, String> {
+    let value = value.trim();
+    match value {
+        "true" => Ok(Scalar::Bool(true)),
+        "false" => Ok(Scalar::Bool(false)),
+        _ if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') => {
+            Ok(Scalar::Text(value[1..value.len() - 1].to_string()))
+        },
+        _ if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => {
+            unescape(&value[1..value.len() - 1]).map(Scalar::Text)
+        },
+        _ if value.replace('_', "").parse::<f64>().is_ok() => Ok(Scalar::Text(value.replace('_', ""))),
+        _ => Err(format!("invalid value `{value}`; strings must be quoted")),
+    }
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            other => return Err(format!("unsupported escape `\\{}`", other.map(String::from).unwrap_or_default())),
+        });
+    }
+    Ok(out)
+}
+
+/// Drops a trailing `# comment`, unless the `#` is within a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Splits the items of an array at the commas outside of strings.
+fn split_array(items: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quote, mut escaped) = (0, None, false);
+    for (i, c) in items.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ',') => {
+                parts.push(&items[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+        escaped = false;
+    }
+    parts.push(&items[start..]);
+    parts.into_iter().filter(|p| !p.trim().is_empty()).collect()
+}
@@ -0,0 +1,368 @@
+//! Inspecting and tuning a run while it's going, via `SIGUSR1` and `--control-file`, and stopping it on Ctrl-C.
+//!
+//! On either, the commands currently running are dumped to stderr; the control file can also
+//! raise or lower the number of commands run at once. Commands of a group with a limit of its own,
+//! see `--group-limit`, are held back until one of the group finished.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use cmd2zip::{exec::{self, Limits}, json::Value, progress, events, status, verbose};
+
+/// How often the signal flag and the control file are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many of the latest failures are kept, for `--tui`.
+const RECENT_FAILURES: usize = 10;
+
+/// Set by the signal handlers, as nothing else is safe to do within them.
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// A command that's running right now.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// The name of its entry.
+    pub name: String,
+    pub command: String,
+    pub started: Instant,
+    
+    /// The bytes of stdout and stderr captured so far.
+    pub captured: Arc<AtomicU64>,
+}
+
+struct State {
+    /// The number of commands allowed to run at once.
+    limit: usize,
+    
+    /// While set, no further commands are started.
+    paused: bool,
+    
+    /// The commands running right now, by their index.
+    running: BTreeMap<usize, Job>,
+    
+    /// The latest commands that failed, as their entry name and command.
+    failures: VecDeque<(String, String)>,
+    
+    /// How many commands of each limited group were started and aren't finished yet.
+    groups: HashMap<String, usize>,
+}
+
+/// The state of a run as of now, for `--tui`.
+pub struct Snapshot {
+    pub elapsed: Duration,
+    pub limit: usize,
+    pub paused: bool,
+    pub running: Vec<Job>,
+    pub failures: Vec<(String, String)>,
+}
+
+pub struct Control {
+    started: Instant,
+    
+    /// The size of the thread-pool, which the limit can't be raised beyond.
+    max: usize,
+    
+    /// How many commands of each group may run at once; other groups are only limited like all commands are.
+    group_limits: HashMap<String, usize>,
+    
+    state: Mutex<State>,
+    changed: Condvar,
+    stopped: AtomicBool,
+}
+
+impl Control {
+    /// Runs `threads` commands at once, which may later be changed to anything up to `max`.
+    pub fn new(threads: usize, max: usize, group_limits: HashMap<String, usize>) -> Self {
+        Self {
+            started: Instant::now(),
+            max,
+            group_limits,
+            state: Mutex::new(State { limit: threads, paused: false, running: BTreeMap::new(), failures: VecDeque::new(), groups: HashMap::new() }),
+            changed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+        }
+    }
+    
+    /// Blocks until another command may run, then counts it as running until the returned guard is dropped.
+    pub fn start(&self, index: usize, name: &str, command: &str) -> Slot<'_> {
+        let state = self.state.lock().expect("failed to lock mutex");
+        let mut state = self.changed.wait_while(state, |state| state.paused || state.running.len() >= state.limit).expect("failed to lock mutex");
+        let captured = Arc::new(AtomicU64::new(0));
+        state.running.insert(index, Job { name: name.to_string(), command: command.to_string(), started: Instant::now(), captured: captured.clone() });
+        Slot(self, index, captured)
+    }
+    
+    /// Counts another command of the group as started until the returned guard is dropped,
+    /// unless as many as the group may run at once already are.
+    pub fn reserve(&self, group: &str) -> Option<Group<'_>> {
+        let Some(limit) = self.group_limits.get(group) else {
+            return Some(Group(self, None));
+        };
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let count = state.groups.entry(group.to_string()).or_default();
+        if *count >= *limit {
+            return None;
+        }
+        *count += 1;
+        Some(Group(self, Some(group.to_string())))
+    }
+    
+    /// A command failed, for listing it among the latest failures.
+    pub fn failed(&self, name: &str, command: &str) {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        if state.failures.len() == RECENT_FAILURES {
+            state.failures.pop_front();
+        }
+        state.failures.push_back((name.to_string(), command.to_string()));
+    }
+    
+    /// Stops starting further commands, or resumes doing so; returns whether it's paused now.
+    pub fn toggle_pause(&self) -> bool {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        state.paused = !state.paused;
+        self.changed.notify_all();
+        state.paused
+    }
+    
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.lock().expect("failed to lock mutex");
+        Snapshot {
+            elapsed: self.started.elapsed(),
+            limit: state.limit,
+            paused: state.paused,
+            running: state.running.values().cloned().collect(),
+            failures: state.failures.iter().cloned().collect(),
+        }
+    }
+    
+    /// How many commands are running right now, and how many may at once.
+    pub fn running(&self) -> (usize, usize) {
+        let state = self.state.lock().expect("failed to lock mutex");
+        (state.running.len(), state.limit)
+    }
+    
+    /// Changes the number of commands run at once; commands that are already running are left alone.
+    pub fn set_limit(&self, limit: usize) {
+        let limit = match limit.clamp(1, self.max) {
+            clamped if clamped != limit => {
+                status!("!! Can't run {limit} commands at once; running {clamped} instead (see `--max-threads`)");
+                clamped
+            },
+            limit => limit,
+        };
+        
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        if state.limit != limit {
+            verbose!(0, "-- Running up to {limit} commands at once, instead of {}", state.limit);
+            state.limit = limit;
+            self.changed.notify_all();
+        }
+    }
+    
+    /// Prints the commands currently running, and how many are still waiting to.
+    fn dump(&self) {
+        let state = self.state.lock().expect("failed to lock mutex");
+        let progress::Counts { queued, done, failed, .. } = progress::counts();
+        let waiting = queued.saturating_sub(done).saturating_sub(state.running.len() as u64);
+        status!("-- Status after {}: {} running (up to {} at once), {waiting} waiting, {done} finished ({failed} failed)",
+            progress::format_duration(self.started.elapsed()), state.running.len(), state.limit);
+        for job in state.running.values() {
+            status!("--   {:.2}s `{}` << `{}`", job.started.elapsed().as_secs_f64(), job.name, job.command);
+        }
+        
+        let running = state.running.values()
+            .map(|job| Value::object([
+                ("name", Value::from(job.name.as_str())),
+                ("command", job.command.as_str().into()),
+                ("duration", job.started.elapsed().as_secs_f64().into()),
+                ("captured", job.captured.load(Ordering::Relaxed).into()),
+            ]))
+            .collect();
+        events::emit("status", Value::object([
+            ("elapsed", Value::from(self.started.elapsed().as_secs_f64())),
+            ("limit", state.limit.into()),
+            ("waiting", waiting.into()),
+            ("finished", done.into()),
+            ("failed", failed.into()),
+            ("running", Value::Array(running)),
+        ]));
+    }
+    
+    /// Reads the control file: a number sets how many commands run at once, and `status` dumps the status.
+    fn apply(&self, path: &Path) {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                status!("!! Failed to read control file `{}`: {err}", path.display());
+                return;
+            },
+        };
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match line {
+                "status" => self.dump(),
+                line => match line.parse() {
+                    Ok(limit) => self.set_limit(limit),
+                    Err(_) => status!("!! Ignoring unknown line in control file `{}`: {line}", path.display()),
+                },
+            }
+        }
+    }
+}
+
+/// Held while a command runs; see [`Control::start`].
+pub struct Slot<'a>(&'a Control, usize, Arc<AtomicU64>);
+
+impl Slot<'_> {
+    /// Counts the bytes captured from the command so far, see [`cmd2zip::Job::captured`].
+    pub fn captured(&self) -> Arc<AtomicU64> {
+        self.2.clone()
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().expect("failed to lock mutex").running.remove(&self.1);
+        self.0.changed.notify_all();
+    }
+}
+
+/// Held from when a command of a limited group is started, until it's finished; see [`Control::reserve`].
+pub struct Group<'a>(&'a Control, Option<String>);
+
+impl Drop for Group<'_> {
+    fn drop(&mut self) {
+        if let Some(group) = &self.1 {
+            let mut state = self.0.state.lock().expect("failed to lock mutex");
+            *state.groups.get_mut(group).expect("group is counted") -= 1;
+            self.0.changed.notify_all();
+        }
+    }
+}
+
+/// The commands held back as their group is at its limit, each along with what starts it.
+pub struct Pending<'a, F> {
+    control: &'a Control,
+    queue: VecDeque<(String, F)>,
+}
+
+impl<'a, F: FnOnce(Option<Group<'a>>)> Pending<'a, F> {
+    pub fn new(control: &'a Control) -> Self {
+        Self { control, queue: VecDeque::new() }
+    }
+    
+    /// Starts the command right away if its group has room, else holds it back after the others of its group.
+    pub fn start(&mut self, group: Option<String>, start: F) {
+        let Some(group) = group else {
+            return start(None);
+        };
+        if self.queue.iter().any(|(g, _)| *g == group) {
+            self.queue.push_back((group, start));
+            return;
+        }
+        match self.control.reserve(&group) {
+            Some(reserved) => start(Some(reserved)),
+            None => self.queue.push_back((group, start)),
+        }
+    }
+    
+    /// Starts the commands held back whose group has room by now, in order.
+    pub fn start_ready(&mut self) {
+        let mut full = Vec::new();
+        for (group, start) in std::mem::take(&mut self.queue) {
+            match full.contains(&group) {
+                true => self.queue.push_back((group, start)),
+                false => match self.control.reserve(&group) {
+                    Some(reserved) => start(Some(reserved)),
+                    None => {
+                        full.push(group.clone());
+                        self.queue.push_back((group, start));
+                    },
+                },
+            }
+        }
+    }
+    
+    /// Blocks until all commands held back are started.
+    pub fn drain(&mut self) {
+        while !self.queue.is_empty() {
+            self.start_ready();
+            if !self.queue.is_empty() {
+                let state = self.control.state.lock().expect("failed to lock mutex");
+                drop(self.control.changed.wait_timeout(state, POLL_INTERVAL).expect("failed to lock mutex"));
+            }
+        }
+    }
+}
+
+/// Starts watching for `SIGUSR1`, Ctrl-C and changes to the control file, if any.
+/// 
+/// On Ctrl-C (or `SIGTERM`), no further commands are run and the running ones are stopped, with the given limits;
+/// a second Ctrl-C quits at once.
+pub fn start(control: Arc<Control>, path: Option<PathBuf>, limits: Limits) -> JoinHandle<()> {
+    install_handler();
+    
+    std::thread::Builder::new()
+        .name("control".into())
+        .spawn(move || {
+            // A control file that's there from the start is applied right away.
+            let mut modified = None::<SystemTime>;
+            while !control.stopped.load(Ordering::Relaxed) {
+                if SIGNALED.swap(false, Ordering::Relaxed) {
+                    control.dump();
+                }
+                if INTERRUPTED.swap(false, Ordering::Relaxed) {
+                    status!("!! Interrupted; stopping the running commands and finishing the archive (Ctrl-C again to quit at once)");
+                    exec::stop_all(&limits);
+                }
+                
+                if let Some(path) = &path {
+                    let mtime = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+                    if mtime.is_some() && mtime != modified {
+                        modified = mtime;
+                        control.apply(path);
+                    }
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })
+        .expect("failed to spawn control thread")
+}
+
+/// Stops watching, once all commands are done.
+pub fn stop(control: &Control, watcher: JoinHandle<()>) {
+    control.stopped.store(true, Ordering::Relaxed);
+    watcher.join().ok();
+}
+
+#[cfg(unix)]
+fn install_handler() {
+    extern "C" fn on_status(_: libc::c_int) {
+        SIGNALED.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn on_interrupt(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        // SAFETY: `signal` is async-signal-safe; the next one is handled as usual, quitting at once.
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+    unsafe {
+        libc::signal(libc::SIGUSR1, on_status as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+/// There's no `SIGUSR1` elsewhere; the status can still be dumped via the control file.
+/// Ctrl-C reaches the commands directly, as they share the console.
+#[cfg(not(unix))]
+fn install_handler() {}
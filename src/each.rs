@@ -0,0 +1,99 @@
+//! Running a template command for each of a set of paths, like `--each` does; one path at a time, or in batches.
+
+use std::path::Path;
+
+/// Instantiates an `--each`/`--arg-template` template for the given path.
+pub fn expand(template: &str, path: &str) -> String {
+    let quote = |s: &str| shlex::try_quote(s).map(|q| q.into_owned()).unwrap_or_else(|_| s.to_string());
+    let file = Path::new(path);
+    
+    let mut out = String::with_capacity(template.len() + path.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        
+        let placeholders: [(&str, &dyn Fn() -> String); 5] = [
+            ("{}", &|| quote(path)),
+            ("{.}", &|| quote(&file.with_extension("").to_string_lossy())),
+            ("{/}", &|| quote(&file.file_name().unwrap_or_default().to_string_lossy())),
+            ("{/.}", &|| quote(&file.file_stem().unwrap_or_default().to_string_lossy())),
+            ("{//}", &|| match file.parent().filter(|p| !p.as_os_str().is_empty()) {
+                Some(parent) => quote(&parent.to_string_lossy()),
+                None => ".".into(),
+            }),
+        ];
+        
+        match placeholders.iter().find(|(p, _)| rest.starts_with(p)) {
+            Some((p, value)) => {
+                out.push_str(&value());
+                rest = &rest[p.len()..];
+            },
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The placeholders of `--each` templates.
+pub const PLACEHOLDERS: [&str; 5] = ["{}", "{.}", "{/}", "{/.}", "{//}"];
+
+/// Instantiates an `--each` template for a batch of paths, repeating every word holding a placeholder for each path.
+pub fn expand_batch(template: &str, paths: &[&str]) -> String {
+    if let [path] = paths {
+        return expand(template, path);
+    }
+    
+    let mut out = String::with_capacity(template.len() * paths.len());
+    let mut rest = template;
+    while !rest.is_empty() {
+        let start = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..end];
+        rest = &rest[end..];
+        match PLACEHOLDERS.iter().any(|p| word.contains(p)) {
+            true => out.push_str(&paths.iter().map(|path| expand(word, path)).collect::<Vec<_>>().join(" ")),
+            false => out.push_str(word),
+        }
+    }
+    out
+}
+
+/// Groups the items into batches of `size`, joined into one by `join`; the last one may come out short.
+/// 
+/// Items that are `single`, like comments, pass through on their own, after the batch before them.
+pub fn batches<T>(mut items: impl Iterator<Item = T>, size: usize, single: impl Fn(&T) -> bool, join: impl Fn(Vec<T>) -> T) -> impl Iterator<Item = T> {
+    let mut batch = Vec::with_capacity(size);
+    // A single item, which comes after the batch before it.
+    let mut held = None;
+    std::iter::from_fn(move || {
+        if let Some(item) = held.take() {
+            return Some(item);
+        }
+        loop {
+            match items.next() {
+                Some(item) if single(&item) => match batch.is_empty() {
+                    true => return Some(item),
+                    false => {
+                        held = Some(item);
+                        return Some(join(std::mem::take(&mut batch)));
+                    },
+                },
+                Some(item) => {
+                    batch.push(item);
+                    if batch.len() == size {
+                        return Some(join(std::mem::take(&mut batch)));
+                    }
+                },
+                None => return (!batch.is_empty()).then(|| join(std::mem::take(&mut batch))),
+            }
+        }
+    })
+}
@@ -0,0 +1,199 @@
+//! Transcoding the output of commands into UTF-8, for `--encoding` and `--normalize-newlines`.
+
+use std::io::{self, Read, Write};
+
+use crate::capture::Capture;
+
+/// The encodings the output of commands can be transcoded from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Leave the output as it is.
+    #[default]
+    Keep,
+    
+    /// UTF-8, replacing invalid sequences with `�`.
+    Utf8,
+    
+    /// ISO-8859-1, where every byte is a character of its own.
+    Latin1,
+    
+    /// UTF-16, little-endian unless a byte order mark says otherwise.
+    Utf16,
+    
+    /// Guessed from a byte order mark or the first bytes; output that looks binary is left as it is.
+    Auto,
+}
+
+/// How many of the first bytes are looked at to guess the encoding.
+const SAMPLE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoder {
+    /// Passes the bytes through, for normalizing newlines only.
+    Raw,
+    Utf8,
+    Latin1,
+    Utf16 { big_endian: bool },
+}
+
+impl Decoder {
+    /// Decodes as much of the input as possible into UTF-8, returning how many bytes were used up;
+    /// the rest belong to a character that continues in the next chunk, unless this is the `last` one.
+    fn decode(self, input: &[u8], last: bool, output: &mut Vec<u8>) -> usize {
+        match self {
+            Decoder::Raw => {
+                output.extend_from_slice(input);
+                input.len()
+            },
+            Decoder::Latin1 => {
+                input.iter().for_each(|b| push(output, *b as char));
+                input.len()
+            },
+            Decoder::Utf8 => {
+                let mut rest = input;
+                loop {
+                    match std::str::from_utf8(rest) {
+                        Ok(valid) => {
+                            output.extend_from_slice(valid.as_bytes());
+                            return input.len();
+                        },
+                        Err(err) => {
+                            output.extend_from_slice(&rest[..err.valid_up_to()]);
+                            match err.error_len() {
+                                None if !last => return input.len() - rest.len() + err.valid_up_to(),
+                                None => {
+                                    push(output, char::REPLACEMENT_CHARACTER);
+                                    return input.len();
+                                },
+                                Some(len) => {
+                                    push(output, char::REPLACEMENT_CHARACTER);
+                                    rest = &rest[err.valid_up_to() + len..];
+                                },
+                            }
+                        },
+                    }
+                }
+            },
+            Decoder::Utf16 { big_endian } => {
+                let mut units: Vec<u16> = input.chunks_exact(2)
+                    .map(|pair| match big_endian {
+                        true => u16::from_be_bytes([pair[0], pair[1]]),
+                        false => u16::from_le_bytes([pair[0], pair[1]]),
+                    })
+                    .collect();
+                // A high surrogate is only decodable along with the low one that follows it.
+                if !last && units.last().is_some_and(|u| (0xD800..0xDC00).contains(u)) {
+                    units.pop();
+                }
+                char::decode_utf16(units.iter().copied())
+                    .for_each(|c| push(output, c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+                
+                match last && input.len() % 2 == 1 {
+                    true => {
+                        push(output, char::REPLACEMENT_CHARACTER);
+                        input.len()
+                    },
+                    false => units.len() * 2,
+                }
+            },
+        }
+    }
+}
+
+fn push(output: &mut Vec<u8>, c: char) {
+    output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// The decoder for the output starting with the given bytes, and the length of its byte order mark;
+/// `None` if it's to be left as it is.
+fn detect(encoding: Encoding, head: &[u8]) -> Option<(Decoder, usize)> {
+    let bom = match head {
+        [0xEF, 0xBB, 0xBF, ..] => Some((Decoder::Utf8, 3)),
+        [0xFF, 0xFE, ..] => Some((Decoder::Utf16 { big_endian: false }, 2)),
+        [0xFE, 0xFF, ..] => Some((Decoder::Utf16 { big_endian: true }, 2)),
+        _ => None,
+    };
+    match encoding {
+        Encoding::Keep => Some((Decoder::Raw, 0)),
+        Encoding::Utf8 => Some((Decoder::Utf8, bom.filter(|(d, _)| *d == Decoder::Utf8).map_or(0, |(_, len)| len))),
+        Encoding::Latin1 => Some((Decoder::Latin1, 0)),
+        Encoding::Utf16 => bom.filter(|(d, _)| *d != Decoder::Utf8).or(Some((Decoder::Utf16 { big_endian: false }, 0))),
+        Encoding::Auto => bom.or_else(|| guess(&head[..head.len().min(SAMPLE_LEN)]).map(|d| (d, 0))),
+    }
+}
+
+/// Guesses the encoding of output without a byte order mark.
+fn guess(sample: &[u8]) -> Option<Decoder> {
+    // ASCII text in UTF-16 has every other byte zero.
+    let pairs = sample.len() / 2;
+    let (even, odd) = sample.chunks_exact(2).fold((0, 0), |(even, odd), pair| (even + (pair[0] == 0) as usize, odd + (pair[1] == 0) as usize));
+    if pairs > 0 && odd * 4 >= pairs * 3 && even * 4 < pairs {
+        return Some(Decoder::Utf16 { big_endian: false });
+    }
+    if pairs > 0 && even * 4 >= pairs * 3 && odd * 4 < pairs {
+        return Some(Decoder::Utf16 { big_endian: true });
+    }
+    if sample.contains(&0) {
+        return None;
+    }
+    
+    // Valid UTF-8 is used as it is, even if the sample cuts off a character at its end.
+    match std::str::from_utf8(sample) {
+        Err(err) if err.error_len().is_some() => Some(Decoder::Latin1),
+        _ => Some(Decoder::Utf8),
+    }
+}
+
+/// Transcodes the output into UTF-8, and turns CRLF line endings into LF if `newlines` is set.
+pub fn normalize(content: &mut Capture, encoding: Encoding, newlines: bool) -> io::Result<()> {
+    let Some((decoder, bom)) = detect(encoding, content.head()) else {
+        return Ok(());
+    };
+    if decoder == Decoder::Raw && !newlines {
+        return Ok(());
+    }
+    
+    let mut output = Capture::default();
+    {
+        let mut reader = content.reader()?;
+        io::copy(&mut reader.by_ref().take(bom as u64), &mut io::sink())?;
+        
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut pending = Vec::new();
+        let mut decoded = Vec::new();
+        // A carriage return at the end of the previous chunk, which may precede a line feed.
+        let mut cr = false;
+        loop {
+            let read = reader.read(&mut buffer)?;
+            pending.extend_from_slice(&buffer[..read]);
+            
+            decoded.clear();
+            let used = decoder.decode(&pending, read == 0, &mut decoded);
+            pending.drain(..used);
+            
+            if newlines {
+                let mut normalized = Vec::with_capacity(decoded.len() + 1);
+                for b in decoded.iter().copied() {
+                    if std::mem::take(&mut cr) && b != b'\n' {
+                        normalized.push(b'\r');
+                    }
+                    match b {
+                        b'\r' => cr = true,
+                        b => normalized.push(b),
+                    }
+                }
+                if read == 0 && cr {
+                    normalized.push(b'\r');
+                }
+                decoded = normalized;
+            }
+            output.write_all(&decoded)?;
+            
+            if read == 0 {
+                break;
+            }
+        }
+    }
+    *content = output;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Machine-readable progress events, for `--log-format json`.
+
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::{OnceLock, atomic::{AtomicBool, AtomicI8, Ordering}},
+};
+
+use crate::json::{self, Value};
+
+/// How progress is reported on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One human-readable line per finished command.
+    #[default]
+    Text,
+    
+    /// One JSON object per event, one per line.
+    Json,
+}
+
+/// When to color the console output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// If the output goes to a terminal, and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// The colors console output is painted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// Commands that succeeded.
+    Green,
+    
+    /// Commands that failed, or couldn't be run.
+    Red,
+    
+    /// Commands whose stderr was used instead of stdout, or that are retried.
+    Yellow,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "\x1b[32m",
+            Color::Red => "\x1b[31m",
+            Color::Yellow => "\x1b[33m",
+        }
+    }
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Whether stdout is taken by the archive, so that reports go to stderr instead.
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Whether stdout and stderr are colored, respectively.
+static COLOR_STDOUT: AtomicBool = AtomicBool::new(false);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// How much is printed to the console: -1 for `--quiet`, 0 by default, and one more for every `-v`.
+static VERBOSITY: AtomicI8 = AtomicI8::new(0);
+
+/// Sets the format for the rest of the run; must be called before any events are emitted.
+pub fn init(format: LogFormat, to_stderr: bool) {
+    FORMAT.set(format).expect("log format was already set");
+    TO_STDERR.store(to_stderr, Ordering::Relaxed);
+}
+
+pub fn set_color(choice: ColorChoice) {
+    let auto = |terminal: bool| terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+    let (stdout, stderr) = match choice {
+        ColorChoice::Auto => (auto(std::io::stdout().is_terminal()), auto(std::io::stderr().is_terminal())),
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+    };
+    COLOR_STDOUT.store(stdout, Ordering::Relaxed);
+    COLOR_STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// Paints the text in the color, if the stream it's printed to is colored.
+pub fn paint(color: Color, text: impl Display, stderr: bool) -> String {
+    let colored = match stderr {
+        true => &COLOR_STDERR,
+        false => &COLOR_STDOUT,
+    };
+    match colored.load(Ordering::Relaxed) {
+        true => format!("{}{text}\x1b[0m", color.code()),
+        false => text.to_string(),
+    }
+}
+
+pub fn set_verbosity(level: i8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+/// The level of diagnostics to print; informational lines need 0, and extra diagnostics need 1 (`-v`) or 2 (`-vv`).
+pub fn verbosity() -> i8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Prints a line of the report; to stdout, unless the archive is written there.
+pub fn print(line: &str) {
+    crate::progress::print_line(line, TO_STDERR.load(Ordering::Relaxed));
+}
+
+/// Prints a line of the report in the given color.
+pub fn print_colored(color: Color, line: &str) {
+    print(&paint(color, line, TO_STDERR.load(Ordering::Relaxed)));
+}
+
+/// Whether events are being emitted, instead of the human-readable lines.
+pub fn enabled() -> bool {
+    FORMAT.get().copied().unwrap_or_default() == LogFormat::Json
+}
+
+/// Emits an event as a single line of JSON, with the given fields following the event-type and timestamp.
+pub fn emit(event: &str, fields: Value) {
+    if !enabled() {
+        return;
+    }
+    
+    let mut object = vec![
+        ("event".to_string(), Value::from(event)),
+        ("time".to_string(), json::timestamp(std::time::SystemTime::now()).into()),
+    ];
+    match fields {
+        Value::Object(fields) => object.extend(fields),
+        Value::Null => {},
+        other => object.push(("data".to_string(), other)),
+    }
+    print(&Value::Object(object).to_string());
+}
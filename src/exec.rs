@@ -0,0 +1,714 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{capture::Capture, Notice};
+
+/// Constraints on how long a child-process may run, how much output it may produce, and how it's scheduled.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// How long the child may run before being asked to terminate.
+    pub timeout: Option<Duration>,
+    
+    /// How long to wait after asking, before killing the child forcefully.
+    pub kill_after: Duration,
+    
+    /// The signal asking the child to exit, instead of `SIGTERM`; Unix only.
+    pub term_signal: Option<i32>,
+    
+    /// How long the child may go without writing to stdout or stderr, before being asked to terminate like on timeout.
+    pub stall_timeout: Option<Duration>,
+    
+    /// How many bytes each of stdout and stderr may hold.
+    pub max_output: Option<u64>,
+    
+    /// What happens to outputs exceeding `max_output`.
+    pub on_oversize: Oversize,
+    
+    /// How many bytes of address space the child may use; Unix only.
+    pub memory: Option<u64>,
+    
+    /// How much CPU time the child may use, before being killed; Unix only.
+    pub cpu_time: Option<Duration>,
+    
+    /// The niceness of the child, from -20 (most favorable) to 19 (least favorable); on Windows, any positive
+    /// niceness means the below-normal priority class, and 15 or more the idle one.
+    pub nice: Option<i32>,
+    
+    /// The I/O scheduling class of the child; Linux only.
+    pub io_class: Option<IoClass>,
+}
+
+/// The I/O scheduling classes of Linux, for `ioprio_set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IoClass {
+    /// Only gets disk time when no other process needs it.
+    Idle,
+    
+    /// The default class, but at its lowest priority level.
+    BestEffort,
+}
+
+/// What happens to outputs exceeding their maximum size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Oversize {
+    /// Keep the output up to the maximum size, dropping the rest.
+    #[default]
+    Truncate,
+    
+    /// Leave the output out of the archive entirely; it's still listed in the manifest.
+    Skip,
+    
+    /// Stop reading the output, so the command fails (usually from `SIGPIPE`), keeping what was read.
+    Error,
+    
+    /// Keep the output in full, but outside of the archive; the binary writes it into `--spill-dir` instead.
+    Spill,
+}
+
+impl Oversize {
+    /// The name of the policy, as given on the command-line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Oversize::Truncate => "truncate",
+            Oversize::Skip => "skip",
+            Oversize::Error => "error",
+            Oversize::Spill => "spill",
+        }
+    }
+}
+
+/// A shell to run commands with, instead of splitting them into arguments ourselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    /// `sh -c`, the POSIX shell.
+    Sh,
+    
+    /// `cmd /C`, the Windows command prompt.
+    Cmd,
+    
+    /// `powershell -Command`, or `pwsh` outside of Windows.
+    Powershell,
+}
+
+/// The shell used by `--shell` without a value.
+#[cfg(windows)]
+pub const DEFAULT_SHELL: &str = "cmd";
+
+#[cfg(not(windows))]
+pub const DEFAULT_SHELL: &str = "sh";
+
+impl Shell {
+    /// Builds a command running the whole line in this shell.
+    pub fn command(self, line: &str) -> Command {
+        match self {
+            Shell::Sh => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(line);
+                command
+            },
+            Shell::Cmd => {
+                let mut command = Command::new("cmd");
+                command.arg("/C");
+                // Cmd does its own parsing, which the usual quoting of arguments would get in the way of.
+                #[cfg(windows)]
+                std::os::windows::process::CommandExt::raw_arg(&mut command, line);
+                #[cfg(not(windows))]
+                command.arg(line);
+                command
+            },
+            Shell::Powershell => {
+                let mut command = Command::new(if cfg!(windows) { "powershell" } else { "pwsh" });
+                command.args(["-NoProfile", "-NonInteractive", "-Command", line]);
+                command
+            },
+        }
+    }
+}
+
+/// A container engine to run commands with, for `--container`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Engine {
+    #[default]
+    Docker,
+    Podman,
+}
+
+/// Splits the line into the program and its arguments; or wraps it in the shell, if any.
+/// 
+/// Fails for lines with unbalanced quotes or a trailing backslash.
+fn words(line: &str, shell: Option<Shell>) -> io::Result<Vec<String>> {
+    match shell {
+        Some(shell) => {
+            let command = shell.command(line);
+            Ok(std::iter::once(command.get_program()).chain(command.get_args())
+                .map(|s| s.to_string_lossy().into_owned())
+                .collect())
+        },
+        None => shlex::split(line).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unbalanced quotes or trailing backslash")),
+    }
+}
+
+/// Builds a command running the line locally, split into the program and its arguments, or in the shell, if any.
+/// 
+/// Fails for lines that can't be split into words, due to unbalanced quotes or a trailing backslash, or that are empty.
+pub fn local(line: &str, shell: Option<Shell>) -> io::Result<Command> {
+    if let Some(shell) = shell {
+        return Ok(shell.command(line));
+    }
+    let words = words(line, None)?;
+    let (program, args) = words.split_first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// Builds a command running the line on the given host via `ssh`, in the working directory and with the variables;
+/// with `clean_env`, these and `PATH` are the only ones.
+/// 
+/// The line is split (or wrapped in the shell) locally and re-quoted, so that the remote shell passes the arguments on as-is.
+pub fn remote(host: &str, line: &str, shell: Option<Shell>, cwd: Option<&Path>, env: &[(String, String)], clean_env: bool) -> io::Result<Command> {
+    let words = words(line, shell)?;
+    let quote = |s: &str| shlex::try_quote(s).expect("failed to quote command").into_owned();
+    let mut remote_line = String::new();
+    if let Some(cwd) = cwd {
+        remote_line += &format!("cd {} && ", quote(&cwd.to_string_lossy()));
+    }
+    match clean_env {
+        // Keeps the `PATH` of the remote host, expanded by its shell.
+        true => remote_line += "env -i PATH=\"$PATH\" ",
+        false if !env.is_empty() => remote_line += "env ",
+        false => {},
+    }
+    for (key, value) in env {
+        remote_line += &quote(&format!("{key}={value}"));
+        remote_line += " ";
+    }
+    remote_line += &words.iter().map(|w| quote(w)).collect::<Vec<_>>().join(" ");
+    
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes", "--", host, &remote_line]);
+    Ok(command)
+}
+
+/// Builds a command running the line in a fresh container of the given image, with the mounts, working directory and variables.
+pub fn container(engine: Engine, image: &str, mounts: &[String], line: &str, shell: Option<Shell>, cwd: Option<&Path>, env: &[(String, String)]) -> io::Result<Command> {
+    let mut command = Command::new(match engine {
+        Engine::Docker => "docker",
+        Engine::Podman => "podman",
+    });
+    // Interactive, so that stdin reaches the command.
+    command.args(["run", "--rm", "-i"]);
+    for mount in mounts {
+        command.arg("-v").arg(mount);
+    }
+    if let Some(cwd) = cwd {
+        command.arg("-w").arg(cwd);
+    }
+    for (key, value) in env {
+        command.arg("-e").arg(format!("{key}={value}"));
+    }
+    command.arg("--").arg(image).args(words(line, shell)?);
+    Ok(command)
+}
+
+/// The result of running a child-process to completion.
+#[derive(Debug)]
+pub struct Outcome {
+    pub status: ExitStatus,
+    pub stdout: Capture,
+    pub stderr: Capture,
+    
+    /// Whether the child was terminated for exceeding its timeout.
+    pub timed_out: bool,
+    
+    /// Whether stdout or stderr exceeded the maximum size.
+    pub oversize: bool,
+    
+    /// The CPU time used by the child and the children it waited for, in user and system mode; Unix only.
+    pub cpu_time: Option<Duration>,
+}
+
+/// Runs the command to completion, capturing its output and enforcing the limits.
+/// 
+/// The child's stdin is read from the given file, or else is empty.
+/// 
+/// Note: This blocks until the child finishes, ON PURPOSE.
+pub fn run(command: Command, stdin: Option<&Path>, limits: &Limits, notify: &dyn Fn(Notice)) -> io::Result<Outcome> {
+    run_watched(command, stdin, limits, &AtomicU64::new(0), None, notify)
+}
+
+/// Like [`run`], adding the bytes of stdout and stderr to `captured` as they come in, for showing progress.
+/// 
+/// With `tee`, their lines are also handed to it as they come in, along with whether they're from stderr.
+pub fn run_watched(mut command: Command, stdin: Option<&Path>, limits: &Limits, captured: &AtomicU64, tee: Option<&Lines<'_>>, notify: &dyn Fn(Notice)) -> io::Result<Outcome> {
+    let stdin = match stdin {
+        Some(path) => Stdio::from(File::open(path)?),
+        None => Stdio::null(),
+    };
+    
+    // Give children their own process group, so that terminating them also reaches their own children,
+    // which would otherwise keep the pipes open; this also keeps Ctrl-C from reaching them, see `stop_all`.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    
+    #[cfg(unix)]
+    if limits.memory.is_some() || limits.cpu_time.is_some() {
+        let (memory, cpu_time) = (limits.memory, limits.cpu_time);
+        let set = move || -> io::Result<()> {
+            let limit = |soft: u64, hard: u64| libc::rlimit { rlim_cur: soft as libc::rlim_t, rlim_max: hard as libc::rlim_t };
+            if let Some(bytes) = memory {
+                checked(unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit(bytes, bytes)) })?;
+            }
+            if let Some(time) = cpu_time {
+                // Whole seconds only; the child gets SIGXCPU at the soft limit, and SIGKILL a second later.
+                let seconds = time.as_secs().max(1);
+                checked(unsafe { libc::setrlimit(libc::RLIMIT_CPU, &limit(seconds, seconds + 1)) })?;
+            }
+            Ok(())
+        };
+        // Safety: only calls `setrlimit`, which is async-signal-safe.
+        unsafe { std::os::unix::process::CommandExt::pre_exec(&mut command, set) };
+    }
+    
+    #[cfg(unix)]
+    if limits.nice.is_some() || limits.io_class.is_some() {
+        let (nice, io_class) = (limits.nice, limits.io_class);
+        let set = move || -> io::Result<()> {
+            if let Some(nice) = nice {
+                checked(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })?;
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(class) = io_class {
+                // The class goes into the upper bits, the level within the class (0 to 7, lower is better) below.
+                const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                let priority = match class {
+                    IoClass::Idle => 3 << 13,
+                    IoClass::BestEffort => 2 << 13 | 7,
+                };
+                checked(unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) } as libc::c_int)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = io_class;
+            Ok(())
+        };
+        // Safety: only calls `setpriority` and `ioprio_set`, which are async-signal-safe.
+        unsafe { std::os::unix::process::CommandExt::pre_exec(&mut command, set) };
+    }
+    
+    #[cfg(windows)]
+    if let Some(nice) = limits.nice.filter(|nice| *nice > 0) {
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+        const IDLE_PRIORITY_CLASS: u32 = 0x40;
+        let class = if nice >= 15 { IDLE_PRIORITY_CLASS } else { BELOW_NORMAL_PRIORITY_CLASS };
+        std::os::windows::process::CommandExt::creation_flags(&mut command, class);
+    }
+    
+    let spawning = Instant::now();
+    let mut child = command
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    notify(Notice::Info(1, format!("Spawned process {} in {:?}: {}", child.id(), spawning.elapsed(), display(&command))));
+    #[cfg(unix)]
+    register(&child, limits);
+    
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    
+    // Spilled outputs are kept in full, so their size is only checked afterwards.
+    let cap = limits.max_output.filter(|_| limits.on_oversize != Oversize::Spill);
+    let drain = limits.on_oversize != Oversize::Error;
+    
+    let activity = &Activity::new(captured);
+    std::thread::scope(|scope| {
+        let stdout = scope.spawn(move || capture(&mut Watched(&mut stdout_pipe, activity, tee.map(|tee| Tee::new(tee, false))), cap, drain));
+        let stderr = scope.spawn(move || capture(&mut Watched(&mut stderr_pipe, activity, tee.map(|tee| Tee::new(tee, true))), cap, drain));
+        
+        let ((status, cpu_time), timed_out) = match limits.timeout.is_some() || limits.stall_timeout.is_some() {
+            true => wait_with_timeout(&mut child, limits, activity, notify)?,
+            false => (wait(&mut child, true)?.expect("blocking wait returns a status"), false),
+        };
+        
+        let (stdout, stdout_over) = stdout.join().expect("stdout reader panicked")?;
+        let (stderr, stderr_over) = stderr.join().expect("stderr reader panicked")?;
+        let oversize = stdout_over || stderr_over
+            || limits.max_output.is_some_and(|max| stdout.len() > max || stderr.len() > max);
+        Ok(Outcome { status, stdout, stderr, timed_out, oversize, cpu_time })
+    })
+}
+
+/// Streams the content through a filter command, like `gzip -9`, capturing its output.
+pub fn pipe(mut command: Command, content: &mut Capture, notify: &dyn Fn(Notice)) -> io::Result<Outcome> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    notify(Notice::Info(1, format!("Spawned filter process {}: {}", child.id(), display(&command))));
+    
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    
+    std::thread::scope(|scope| {
+        let stdout = scope.spawn(move || capture(&mut stdout_pipe, None, true));
+        let stderr = scope.spawn(move || capture(&mut stderr_pipe, None, true));
+        
+        // A filter may well stop reading early, which is up to its exit code to judge.
+        match io::copy(&mut content.reader()?, &mut stdin) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err),
+            _ => drop(stdin),
+        }
+        
+        let (status, cpu_time) = wait(&mut child, true)?.expect("blocking wait returns a status");
+        let (stdout, _) = stdout.join().expect("stdout reader panicked")?;
+        let (stderr, _) = stderr.join().expect("stderr reader panicked")?;
+        Ok(Outcome { status, stdout, stderr, timed_out: false, oversize: false, cpu_time })
+    })
+}
+
+/// When a child last wrote any output, for `stall_timeout`, and how much.
+struct Activity<'a> {
+    start: Instant,
+    
+    /// In milliseconds since the start.
+    latest: AtomicU64,
+    captured: &'a AtomicU64,
+}
+
+impl<'a> Activity<'a> {
+    fn new(captured: &'a AtomicU64) -> Self {
+        Self { start: Instant::now(), latest: AtomicU64::new(0), captured }
+    }
+    
+    fn touch(&self, bytes: usize) {
+        self.latest.fetch_max(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+        self.captured.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    
+    /// How long it's been since the latest output, or since the start if there was none.
+    fn silent_for(&self) -> Duration {
+        self.start.elapsed().saturating_sub(Duration::from_millis(self.latest.load(Ordering::Relaxed)))
+    }
+}
+
+/// A pipe whose reads count as activity, and are echoed if teed.
+struct Watched<'a, 'b, R>(R, &'a Activity<'b>, Option<Tee<'b>>);
+
+impl<R: Read> Read for Watched<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        if read > 0 {
+            self.1.touch(read);
+        }
+        if let Some(tee) = &mut self.2 {
+            tee.feed(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Is handed the lines of stdout and stderr as they come in, along with whether they're from stderr.
+pub type Lines<'a> = dyn Fn(&str, bool) + Sync + 'a;
+
+/// Hands on the lines of a pipe as they come in, each at once so that those of parallel children don't mix.
+struct Tee<'a> {
+    tee: &'a Lines<'a>,
+    stderr: bool,
+    
+    /// The line read so far, up to its newline.
+    line: Vec<u8>,
+}
+
+impl<'a> Tee<'a> {
+    /// Lines longer than this are printed in parts, rather than held back indefinitely.
+    const MAX_LINE: usize = 16 * 1024;
+    
+    fn new(tee: &'a Lines<'a>, stderr: bool) -> Self {
+        Self { tee, stderr, line: Vec::new() }
+    }
+    
+    fn feed(&mut self, bytes: &[u8]) {
+        for part in bytes.split_inclusive(|b| *b == b'\n') {
+            self.line.extend_from_slice(part);
+            if part.ends_with(b"\n") || self.line.len() >= Self::MAX_LINE {
+                self.flush();
+            }
+        }
+    }
+    
+    fn flush(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.line);
+        (self.tee)(line.trim_end_matches(['\n', '\r']), self.stderr);
+        self.line.clear();
+    }
+}
+
+impl Drop for Tee<'_> {
+    /// A last line without a newline is handed on all the same.
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Captures a pipe up to the given number of bytes; beyond that, the rest is either drained or left unread.
+/// 
+/// Returns whether the pipe had more than that.
+fn capture(pipe: &mut impl Read, cap: Option<u64>, drain: bool) -> io::Result<(Capture, bool)> {
+    let mut capture = Capture::default();
+    let Some(cap) = cap else {
+        return io::copy(pipe, &mut capture).map(|_| (capture, false));
+    };
+    io::copy(&mut pipe.take(cap), &mut capture)?;
+    
+    let mut probe = [0u8; 1];
+    if pipe.read(&mut probe)? == 0 {
+        return Ok((capture, false));
+    }
+    if drain {
+        io::copy(pipe, &mut io::sink())?;
+    }
+    Ok((capture, true))
+}
+
+/// Turns the result of a libc call into an error, if it failed.
+#[cfg(unix)]
+fn checked(result: libc::c_int) -> io::Result<()> {
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// The full command line of a command, quoted for a POSIX shell.
+pub fn display(command: &Command) -> String {
+    std::iter::once(command.get_program()).chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .map(|arg| shlex::try_quote(&arg).map(|a| a.into_owned()).unwrap_or_else(|_| format!("{arg:?}")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The signals that can be given by name, without their `SIG`-prefix.
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+];
+
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[];
+
+/// Parses a signal given by name, like `SIGINT` or `int`, or by number.
+pub fn parse_signal(text: &str) -> Result<i32, String> {
+    let upper = text.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS.iter().find(|(known, _)| *known == name).map(|(_, signal)| *signal)
+        .or_else(|| text.parse().ok().filter(|signal| *signal > 0))
+        .ok_or_else(|| format!("unknown signal `{text}`"))
+}
+
+/// The signal that killed the process, if any.
+#[cfg(unix)]
+pub fn signal(status: ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(&status)
+}
+
+#[cfg(not(unix))]
+pub fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// How often a child with a timeout is polled for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Set once all children are being stopped, so that those spawned afterwards are stopped right away.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// The process groups of the children spawned by [`run`] that weren't reaped yet, along with when they were asked
+/// to exit; for [`stop_all`].
+#[cfg(unix)]
+static CHILDREN: std::sync::Mutex<Vec<(libc::pid_t, Option<Instant>)>> = std::sync::Mutex::new(Vec::new());
+
+/// Asks all running children to exit, like on timeout, killing those still running after the grace period;
+/// children spawned from now on are asked right away. For `--fail-fast` and Ctrl-C; Unix only.
+pub fn stop_all(limits: &Limits) {
+    if STOPPING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    
+    #[cfg(unix)]
+    {
+        let signal = limits.term_signal.unwrap_or(libc::SIGTERM);
+        for (pid, asked) in children().iter_mut() {
+            // SAFETY: Listed children aren't reaped yet, so their process groups are still ours.
+            unsafe { libc::kill(-*pid, signal) };
+            *asked = Some(Instant::now());
+        }
+        
+        // Never joined; it's simply gone when the process exits.
+        let kill_after = limits.kill_after;
+        std::thread::Builder::new()
+            .name("killer".into())
+            .spawn(move || loop {
+                std::thread::sleep(Duration::from_millis(100));
+                for (pid, _) in children().iter().filter(|(_, asked)| asked.is_some_and(|at| at.elapsed() >= kill_after)) {
+                    // SAFETY: See above.
+                    unsafe { libc::kill(-*pid, libc::SIGKILL) };
+                }
+            })
+            .expect("failed to spawn killer thread");
+    }
+    #[cfg(not(unix))]
+    let _ = limits;
+}
+
+/// Whether [`stop_all`] was called.
+pub fn stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+fn children() -> std::sync::MutexGuard<'static, Vec<(libc::pid_t, Option<Instant>)>> {
+    CHILDREN.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Lists the child for [`stop_all`], asking it to exit right away if that was called already.
+#[cfg(unix)]
+fn register(child: &Child, limits: &Limits) {
+    let pid = child.id() as libc::pid_t;
+    let mut children = children();
+    let asked = stopping().then(|| {
+        // SAFETY: See `terminate`.
+        unsafe { libc::kill(-pid, limits.term_signal.unwrap_or(libc::SIGTERM)) };
+        Instant::now()
+    });
+    children.push((pid, asked));
+}
+
+/// Calls into libc until it's not interrupted by a signal.
+#[cfg(unix)]
+fn retry(mut call: impl FnMut() -> libc::c_int) -> io::Result<libc::c_int> {
+    loop {
+        match call() {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            result => return Ok(result),
+        }
+    }
+}
+
+/// Reaps the child once it exited, along with the CPU time it used; `None` if it's still running and `block` isn't set.
+#[cfg(unix)]
+fn wait(child: &mut Child, block: bool) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    let pid = child.id() as libc::pid_t;
+    
+    // Wait without reaping first, so that the pid can't be reused while it's still listed in `CHILDREN`.
+    // SAFETY: `siginfo_t` is plain data, for which all zeroes is valid.
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOWAIT | if block { 0 } else { libc::WNOHANG };
+    retry(|| unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) })?;
+    // With `WNOHANG`, the info is left zeroed while the child is still running.
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+    children().retain(|(child, _)| *child != pid);
+    
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data, for which all zeroes is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: See `terminate`; std's own `wait` is never called on this child afterwards.
+    retry(|| unsafe { libc::wait4(pid, &mut status, 0, &mut usage) })?;
+    
+    let time = |t: libc::timeval| Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64);
+    let status = std::os::unix::process::ExitStatusExt::from_raw(status);
+    Ok(Some((status, Some(time(usage.ru_utime) + time(usage.ru_stime)))))
+}
+
+#[cfg(not(unix))]
+fn wait(child: &mut Child, block: bool) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    match block {
+        true => child.wait().map(|status| Some((status, None))),
+        false => child.try_wait().map(|status| status.map(|status| (status, None))),
+    }
+}
+
+/// Waits for the child, terminating it once it exceeds its timeout or stalls; returns whether it had to.
+fn wait_with_timeout(child: &mut Child, limits: &Limits, activity: &Activity, notify: &dyn Fn(Notice)) -> io::Result<((ExitStatus, Option<Duration>), bool)> {
+    let start = Instant::now();
+    let mut terminated_at = None;
+    
+    loop {
+        if let Some(status) = wait(child, false)? {
+            return Ok((status, terminated_at.is_some()));
+        }
+        
+        let elapsed = start.elapsed();
+        match terminated_at {
+            None if limits.timeout.is_some_and(|timeout| elapsed >= timeout) => {
+                terminate(child, limits.term_signal)?;
+                terminated_at = Some(elapsed);
+            },
+            None if limits.stall_timeout.is_some_and(|stall| activity.silent_for() >= stall) => {
+                notify(Notice::Info(1, format!("Process {} wrote nothing for {:?}; terminating it", child.id(), activity.silent_for())));
+                terminate(child, limits.term_signal)?;
+                terminated_at = Some(elapsed);
+            },
+            Some(at) if elapsed >= at + limits.kill_after => {
+                // This fails if the child exited since the last poll, which is fine.
+                kill(child).ok();
+                return Ok((wait(child, true)?.expect("blocking wait returns a status"), true));
+            },
+            _ => {}
+        }
+        
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Politely asks the child to exit, with `SIGTERM` unless another signal is given.
+#[cfg(unix)]
+fn terminate(child: &mut Child, signal: Option<i32>) -> io::Result<()> {
+    // SAFETY: The child has not been reaped yet, so its pid (and process group) is still ours.
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), signal.unwrap_or(libc::SIGTERM)) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// There is no polite way to ask on this platform, so just kill it.
+#[cfg(not(unix))]
+fn terminate(child: &mut Child, _signal: Option<i32>) -> io::Result<()> {
+    child.kill()
+}
+
+/// Forcefully kills the child, along with its process group.
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    // SAFETY: See `terminate`.
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
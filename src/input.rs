@@ -0,0 +1,168 @@
+//! Reading commands from structured inputs, where each record may also name its entry.
+
+use std::path::{Path, PathBuf};
+
+use cmd2zip::json::{self, Value};
+
+/// How the commands of `--input` files are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum InputFormat {
+    /// By the extension of the file: `.csv`, `.jsonl` and `.ndjson` are structured, anything else has one command per line.
+    #[default]
+    Auto,
+    
+    /// One command per line.
+    Lines,
+    
+    /// A CSV file with a header row, naming the `command` column and any of `name`, `cwd`, `group` and `env:VARIABLE`.
+    Csv,
+    
+    /// One JSON object per line, like `{"command": "...", "name": "...", "cwd": "...", "group": "...", "env": {"VARIABLE": "..."}}`.
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Resolves `auto` for the given input.
+    pub fn of(self, path: &Path) -> Self {
+        if self != InputFormat::Auto {
+            return self;
+        }
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("csv") => InputFormat::Csv,
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            _ => InputFormat::Lines,
+        }
+    }
+}
+
+/// Settings of a single command from a structured input, overriding the usual ones.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// The name of the entry, instead of generating one.
+    pub name: Option<String>,
+    
+    pub cwd: Option<PathBuf>,
+    
+    /// Environment variables in addition to those of `--env`.
+    pub env: Vec<(String, String)>,
+    
+    /// Values of the `--args-list` placeholders, substituted into the command as it's run.
+    pub args: Vec<(String, String)>,
+    
+    /// The group of the command, instead of the one of `--group`.
+    pub group: Option<String>,
+}
+
+/// A command read from a structured input, along with the line its record starts on.
+pub struct Record {
+    pub line: usize,
+    pub command: String,
+    pub overrides: Overrides,
+}
+
+/// Parses CSV as per RFC 4180; the first row is the header, naming the columns.
+pub fn parse_csv(text: &str) -> Result<Vec<Record>, String> {
+    let rows = csv_rows(text)?;
+    let Some(((_, header), rows)) = rows.split_first() else {
+        return Ok(Vec::new());
+    };
+    
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let command = column("command").ok_or("the header has no `command` column")?;
+    let (name, cwd, group) = (column("name"), column("cwd"), column("group"));
+    let env: Vec<(usize, &str)> = header.iter().enumerate()
+        .filter_map(|(i, h)| h.trim().strip_prefix("env:").map(|key| (i, key)))
+        .collect();
+    
+    let cell = |row: &[String], i: usize| row.get(i).filter(|c| !c.is_empty()).cloned();
+    rows.iter()
+        .filter(|(_, row)| row.iter().any(|c| !c.is_empty()))
+        .map(|(line, row)| Ok(Record {
+            line: *line,
+            command: cell(row, command).ok_or(format!("line {line} has no command"))?,
+            overrides: Overrides {
+                name: name.and_then(|i| cell(row, i)),
+                cwd: cwd.and_then(|i| cell(row, i)).map(PathBuf::from),
+                env: env.iter().filter_map(|(i, key)| Some((key.to_string(), cell(row, *i)?))).collect(),
+                args: Vec::new(),
+                group: group.and_then(|i| cell(row, i)),
+            },
+        }))
+        .collect()
+}
+
+/// Splits CSV into rows of cells, along with the line each row starts on.
+fn csv_rows(text: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let (mut line, mut start) = (1, 1);
+    let mut quoted = false;
+    
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            },
+            (true, '"') => quoted = false,
+            (true, c) => {
+                line += (c == '\n') as usize;
+                cell.push(c);
+            },
+            (false, '"') if cell.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut cell)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {},
+            (false, '\n') => {
+                row.push(std::mem::take(&mut cell));
+                rows.push((start, std::mem::take(&mut row)));
+                line += 1;
+                start = line;
+            },
+            (false, c) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("line {start} has an unterminated quote"));
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push((start, row));
+    }
+    Ok(rows)
+}
+
+/// Parses one JSON object per line; empty lines are skipped.
+pub fn parse_jsonl(text: &str) -> Result<Vec<Record>, String> {
+    text.lines().enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            let line = i + 1;
+            let object = json::parse(l).map_err(|err| format!("line {line}: {err}"))?;
+            let string = |key: &str| match object.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::String(s)) => Ok(Some(s.clone())),
+                Some(_) => Err(format!("line {line}: `{key}` is not a string")),
+            };
+            let env = match object.get("env") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Object(fields)) => fields.iter()
+                    .map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())).ok_or(format!("line {line}: `env.{k}` is not a string")))
+                    .collect::<Result<_, _>>()?,
+                Some(_) => return Err(format!("line {line}: `env` is not an object")),
+            };
+            Ok(Record {
+                line,
+                command: string("command")?.ok_or(format!("line {line} has no command"))?,
+                overrides: Overrides {
+                    name: string("name")?,
+                    cwd: string("cwd")?.map(PathBuf::from),
+                    env,
+                    args: Vec::new(),
+                    group: string("group")?,
+                },
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,307 @@
+//! Just enough JSON to write out machine-readable metadata, and to read structured inputs.
+
+use std::{
+    fmt::{self, Display, Write},
+    time::SystemTime,
+};
+
+/// A JSON value; objects keep their keys in insertion order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Builds an object from key/value pairs.
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Value)>) -> Self {
+        Value::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+    
+    /// Looks up the value of a key, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a JSON document; anything but whitespace following the value is an error.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { bytes: text.as_bytes(), at: 0 };
+    let value = parser.value()?;
+    parser.whitespace();
+    match parser.at < parser.bytes.len() {
+        true => Err(parser.error("trailing characters")),
+        false => Ok(value),
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at offset {}", self.at)
+    }
+    
+    fn whitespace(&mut self) {
+        while self.bytes.get(self.at).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.at += 1;
+        }
+    }
+    
+    fn eat(&mut self, literal: &str) -> bool {
+        let found = self.bytes[self.at..].starts_with(literal.as_bytes());
+        if found {
+            self.at += literal.len();
+        }
+        found
+    }
+    
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        self.whitespace();
+        match self.eat(literal) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected `{literal}`"))),
+        }
+    }
+    
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        match self.bytes.get(self.at) {
+            Some(b'n') if self.eat("null") => Ok(Value::Null),
+            Some(b't') if self.eat("true") => Ok(Value::Bool(true)),
+            Some(b'f') if self.eat("false") => Ok(Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if !self.eat("]") {
+                    loop {
+                        values.push(self.value()?);
+                        self.whitespace();
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Value::Array(values))
+            },
+            Some(b'{') => {
+                self.at += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if !self.eat("}") {
+                    loop {
+                        self.whitespace();
+                        if self.bytes.get(self.at) != Some(&b'"') {
+                            return Err(self.error("expected a key"));
+                        }
+                        let key = self.string()?;
+                        self.expect(":")?;
+                        fields.push((key, self.value()?));
+                        self.whitespace();
+                        if self.eat("}") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Value::Object(fields))
+            },
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.at;
+                while self.bytes.get(self.at).is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.at += 1;
+                }
+                let number = std::str::from_utf8(&self.bytes[start..self.at]).expect("ascii");
+                number.parse().map(Value::Int)
+                    .or_else(|_| number.parse().map(Value::Float))
+                    .map_err(|_| self.error("invalid number"))
+            },
+            _ => Err(self.error("expected a value")),
+        }
+    }
+    
+    fn string(&mut self) -> Result<String, String> {
+        self.at += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.bytes.get(self.at) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.at += 1;
+                    return String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"));
+                },
+                Some(b'\\') => {
+                    self.at += 1;
+                    let escaped = match self.bytes.get(self.at) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&code) && self.bytes[self.at + 1..].starts_with(b"\\u") {
+                                self.at += 2;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (self.hex4()?.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.at += 1;
+                    out.extend(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                Some(&b) => {
+                    out.push(b);
+                    self.at += 1;
+                },
+            }
+        }
+    }
+    
+    /// Reads the four hex digits following the current position, leaving it on the last one.
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.at + 1..self.at + 5).and_then(|d| std::str::from_utf8(d).ok());
+        let code = digits.and_then(|d| u32::from_str_radix(d, 16).ok()).ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.at += 4;
+        Ok(code)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Float(n) if n.is_finite() => write!(f, "{n}"),
+            Value::Float(_) => f.write_str("null"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            },
+            Value::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Int(i as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(i: u32) -> Self {
+        Value::Int(i as i64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(i: u64) -> Self {
+        Value::Int(i as i64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(i: usize) -> Self {
+        Value::Int(i as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Formats a point in time as RFC 3339 timestamp in UTC, with millisecond precision.
+pub fn timestamp(time: SystemTime) -> String {
+    let t = time::OffsetDateTime::from(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t.year(), t.month() as u8, t.day(),
+        t.hour(), t.minute(), t.second(), t.millisecond()
+    )
+}
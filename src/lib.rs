@@ -0,0 +1,92 @@
+//! Runs a set of commands as child-processes, capturing their output as files into an archive... because temporary files are annoying!
+//! 
+//! This is the library behind the `cmd2zip` binary; the [`Runner`] does what the binary does, minus the command-line:
+//! 
+//! ```no_run
+//! use std::{path::Path, process::Command};
+//! use cmd2zip::{archive, Job, Runner};
+//! 
+//! let archive = archive::open(archive::Format::Zip, Path::new("out.zip"), false, &archive::Options::default())?;
+//! let summary = Runner::new(archive)
+//!     .threads(4)
+//!     .on_complete(|c| println!("{} << {} bytes", c.name, c.bytes))
+//!     .run(["a", "b"].map(|s| {
+//!         Job::new(move || {
+//!             let mut command = Command::new("echo");
+//!             command.arg(s);
+//!             Ok(command)
+//!         }).name(format!("{s}.txt"))
+//!     }))?;
+//! assert_eq!(summary.failed, 0);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//! 
+//! The library prints nothing itself; warnings and the like are handed to the caller as [`Notice`]s instead.
+
+use std::sync::Arc;
+
+/// Like `eprintln!`, but moves the progress bar out of the way first; only used by the binary, as the library hands
+/// whatever it has to tell to the caller as [`Notice`]s.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::progress::print_line(&format!($($arg)*), true)
+    };
+}
+
+/// Like [`status!`], but only printed if the verbosity is at least the given level; see [`events::verbosity`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! verbose {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::events::verbosity() >= $level {
+            $crate::status!($($arg)*)
+        }
+    };
+}
+
+pub mod archive;
+pub mod capture;
+pub mod each;
+pub mod encoding;
+#[doc(hidden)]
+pub mod events;
+pub mod exec;
+#[doc(hidden)]
+pub mod json;
+pub mod names;
+#[doc(hidden)]
+pub mod progress;
+mod runner;
+pub mod writer;
+
+pub use runner::{BenchKeep, Completion, EmptyStdout, ErrFormat, Job, Output, Pipeline, Runner, Scope, StderrLayout, Summary};
+
+/// Something the library has to tell while it works, like a warning; handed to the caller rather than printed,
+/// so that it's up to them how to show it, if at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    /// What's going on, worth telling at the given verbosity and above.
+    Info(u8, String),
+    
+    /// Something that's likely not what was asked for, but carried on with regardless.
+    Warning(String),
+    
+    /// Something that failed, like a stage of a job; what it fails along with is up to the returned results.
+    Error(String),
+}
+
+impl Notice {
+    /// Rewrites the message, e.g. to tell which job it's about.
+    pub fn map(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            Notice::Info(level, message) => Notice::Info(level, f(message)),
+            Notice::Warning(message) => Notice::Warning(f(message)),
+            Notice::Error(message) => Notice::Error(f(message)),
+        }
+    }
+}
+
+/// Where [`Notice`]s go, for the parts of the library that hold on to it, like the archive writer.
+pub type Notify = Arc<dyn Fn(Notice) + Send + Sync>;
@@ -1,19 +1,63 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
-    path::PathBuf,
-    io::{Write, Seek, BufRead},
+    path::{Path, PathBuf},
+    io::{self, Read, BufRead},
+    panic::AssertUnwindSafe,
     process::Command,
+    time::{Duration, SystemTime},
     sync::{
         Arc,
         Mutex,
-        atomic::{AtomicUsize, Ordering}
+        atomic::{AtomicBool, Ordering},
     }
 };
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use regex::Regex;
-use rayon::ThreadPoolBuilder;
-use zip::{ZipWriter, write::FileOptions};
+
+mod completions;
+mod config;
+mod control;
+mod input;
+mod metrics;
+mod sample;
+mod script;
+mod summary;
+mod throttle;
+mod tui;
+mod upload;
+mod verify;
+mod watch;
+
+use cmd2zip::{archive, each, encoding, events, exec, json, names, progress, status, verbose, writer};
+use cmd2zip::{BenchKeep, Completion, EmptyStdout, ErrFormat, Job, Notice, Pipeline, Runner, StderrLayout};
+
+use archive::{Compression, CompressRule, Format, ModeRule, Modes, Zip64};
+use encoding::Encoding;
+use exec::Oversize;
+use events::{Color, ColorChoice, LogFormat};
+use input::{InputFormat, Overrides};
+use names::Strategy;
+use writer::{Checksum, Collision, Dedup, Record};
+
+/// Stands in for the hash of the content in generated file-names, until it's known; for `--name-hash`.
+const HASH_PLACEHOLDER: &str = "{hash}";
+
+/// Stands in for the name printed by the command, until it's known; for `--name-from-output`.
+const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+/// Separates stages of commands; those following it only start once all before it finished.
+const STAGE_SEPARATOR: &str = "---";
+
+/// The name of the entry listing all commands, for `--dry-run=list`.
+const DRY_RUN_LIST_NAME: &str = "commands.txt";
+
+/// Exit code for when any of the commands failed.
+const EXIT_FAILED: i32 = 1;
+
+/// Exit code for invalid usage, like unreadable inputs or an unwritable archive.
+const EXIT_USAGE: i32 = 2;
 
 /// # cmd2zip
 /// 
@@ -25,12 +69,22 @@ use zip::{ZipWriter, write::FileOptions};
 /// 
 /// - Commands starting with `#` are printed to the console, without being run.
 /// 
+/// - A command of just `---` separates stages: the commands following it only start once all before it finished.
+/// 
+/// - For tools that can't write to stdout, `{out}` within a command is replaced with the path of a temporary file,
+///   which is written to the archive instead of stdout; extensions may follow it, as in `ffmpeg -i x.mov {out}.mp4`.
+/// 
 /// - If a command fails, it's output is written to the archive as `.err`-file.
 /// 
-/// - On windows, backward-slashes within glob-expanded commands become forward-slashes.
+/// - On windows, backward-slashes within commands become forward-slashes, unless `--path-style` says otherwise.
 /// 
 /// - Finished commands are listed via stdout; anything else goes to stderr.
 /// 
+/// - Exits with 0 if all commands succeeded, 1 if any failed, and 2 on invalid usage.
+/// 
+/// - `cmd2zip completions <SHELL>` and `cmd2zip manpage` print shell completions and a man page, respectively;
+///   to run a command named like either of them, put `--` before it.
+/// 
 /// ## Example
 /// 
 /// Generating PNG images by globbing SVGs into resvg:
@@ -39,18 +93,132 @@ use zip::{ZipWriter, write::FileOptions};
 /// cmd2zip -o "icons.zip" -p '(?P<name>[\w\-]+)\.svg$' -r '$name.png' --cmd-prefix "resvg -w 128 -h 128" --cmd-postfix " -c" ./icons/*.svg
 /// ```
 /// 
+/// ...or, more simply, via a template:
+/// 
+/// ```sh
+/// cmd2zip -o "icons.zip" --each "resvg -w 128 -h 128 {} -c" --name-postfix .png ./icons/*.svg
+/// ```
+/// 
 #[derive(Debug, Parser)]
+#[command(args_override_self = true, args_conflicts_with_subcommands = true)]
 struct CmdToZip {
-    /// Also pull commands from the given file or stdin (via `-`).
-    #[arg(short = 'i', long = "input")]
-    input: Option<PathBuf>,
+    #[command(subcommand)]
+    generate: Option<completions::Generate>,
+    
+    /// Read options from this config file, as `key = value` pairs named after the long options, like `threads = 4`.
+    /// 
+    /// Defaults to `.cmd2zip.toml` in the current directory, if there is one.
+    /// Options given on the command line take precedence; repeatable ones are added to.
+    #[arg(long = "config", value_name = "PATH")]
+    config: Option<PathBuf>,
+    
+    /// Don't read `.cmd2zip.toml` from the current directory.
+    #[arg(long = "no-config", conflicts_with = "config")]
+    no_config: bool,
+    
+    /// Also pull commands from the given files or stdin (via `-`).
+    /// 
+    /// May be given multiple times; files are read in the order given,
+    /// before any of the positional commands.
+    #[arg(short = 'i', long = "input", action = clap::ArgAction::Append)]
+    input: Vec<PathBuf>,
+    
+    /// Split the commands read via `--input` on NUL bytes instead of newlines, as written by e.g. `find -print0`.
+    #[arg(short = '0', long = "null")]
+    null: bool,
+    
+    /// How the commands of `--input` are laid out; CSV and JSONL records may also give the entry name, cwd and env.
+    /// 
+    /// Entry names given by records are used as-is, instead of being generated.
+    #[arg(long = "input-format", value_enum, value_name = "FORMAT", default_value_t)]
+    input_format: InputFormat,
     
-    /// The name/path of the zip archive to output to.
+    /// The name/path of the zip archive to output to; `-` writes it to stdout.
+    /// 
+    /// An `s3://` or `http(s)://` URL uploads the archive while it's written, instead of storing it locally;
+    /// via `aws s3 cp` or an HTTP PUT with `curl`, which must be installed.
+    /// 
+    /// With `--format dir`, this is the directory to write the files into, created as needed.
+    /// 
+    /// New archives are written to a `.part`-file next to it first, which only replaces the output once it's finished;
+    /// so a failed run leaves the previous archive as it was. Archives appended to are written in place.
     /// 
-    /// Location MUST be writable.
+    /// Location MUST be writable. When writing to stdout, the finished commands are listed via stderr instead.
     #[arg(short = 'o', long = "output", default_value = "output.zip")]
     output: PathBuf,
     
+    /// The format of the archive to output.
+    #[arg(short = 'f', long = "format", value_enum, default_value_t = Format::Zip)]
+    format: Format,
+    
+    /// The compression method to use for zip entries, or for the solid stream of 7z archives.
+    #[arg(long = "compression", value_enum)]
+    compression: Option<Compression>,
+    
+    /// Store zip entries that are compressed already uncompressed, instead of compressing them again for no gain;
+    /// as told by their extension, like `.png`, `.mp4` or `.gz`, or by their first bytes.
+    #[arg(long = "auto-store")]
+    auto_store: bool,
+    
+    /// Compress the zip entries whose names match the glob pattern differently, like `*.json=zstd:19` or `*.png=store`;
+    /// `*` matches any characters, including `/`.
+    /// 
+    /// May be given multiple times; the first matching rule applies, before `--auto-store`.
+    #[arg(long = "compress-rule", value_name = "GLOB=METHOD[:LEVEL]", value_parser = CompressRule::parse, action = clap::ArgAction::Append)]
+    compress_rules: Vec<CompressRule>,
+    
+    /// Pad the headers of stored (uncompressed) zip entries so that their data starts at a multiple of N bytes,
+    /// like `zipalign`, so it can be memory-mapped straight from the archive.
+    /// 
+    /// Compressed entries are left as they are; use with `--compression stored`, `--auto-store` or `--compress-rule`.
+    #[arg(long = "align", value_name = "N", value_parser = clap::value_parser!(u16).range(2..))]
+    align: Option<u16>,
+    
+    /// The Unix permissions of file entries, in octal; the default is `0644`.
+    #[arg(long = "file-mode", value_name = "MODE", value_parser = archive::parse_mode)]
+    file_mode: Option<u32>,
+    
+    /// Make the entries whose names match the glob pattern executable, like `*.sh`;
+    /// by default with the file mode plus the executable bits, or with the given mode, like `bin/*=0750`.
+    /// 
+    /// May be given multiple times; the first matching rule applies.
+    #[arg(long = "exec-mode", value_name = "GLOB[=MODE]", value_parser = ModeRule::parse, action = clap::ArgAction::Append)]
+    exec_modes: Vec<ModeRule>,
+    
+    /// The compression level; the valid range depends on the compression method.
+    /// 
+    /// For tarballs, this is the level of the gzip/zstd stream, and for 7z archives that of their solid stream.
+    #[arg(long = "level", allow_negative_numbers = true)]
+    level: Option<i32>,
+    
+    /// When to use the zip64 extensions for zip entries, which lift the 4 GiB limit on their size.
+    /// 
+    /// Archives over 4 GiB in total get them regardless.
+    #[arg(long = "zip64", value_enum, value_name = "WHEN", default_value_t = Zip64::Auto)]
+    zip64: Zip64,
+    
+    /// Spread the entries over multiple numbered archives, like `output.001.zip`, each at most about this size.
+    /// 
+    /// Accepts suffixes like `K`, `M` or `G`; an entry that exceeds the size on its own gets an archive to itself.
+    #[arg(long = "split-size", value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["append", "replace_existing"])]
+    split_size: Option<u64>,
+    
+    /// Route entries into separate archives by their file-name, like `output.SHARD.zip`.
+    /// 
+    /// The shard is the first capture group of the pattern, or its whole match; other entries go into the usual archive.
+    #[arg(long = "shard-by", value_name = "REGEX", conflicts_with_all = ["append", "replace_existing"])]
+    shard_by: Option<Regex>,
+    
+    /// Encrypt the zip archive with the given password, using AES-256.
+    /// 
+    /// Can't be combined with `--append`.
+    #[arg(long = "password", env = "CMD2ZIP_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    
+    /// Read the password to encrypt the zip archive with from the first line of the given file.
+    #[arg(long = "password-file", value_name = "PATH", conflicts_with = "password")]
+    password_file: Option<PathBuf>,
+    
     /// Prefix to be prepended to all commands.
     /// 
     /// Does NOT partake in name generation.
@@ -83,6 +251,48 @@ struct CmdToZip {
     #[arg(short = 'r', long = "name-replace", requires = "name_pattern")]
     name_replace: Option<String>,
     
+    /// Name each file after a template like `{stem}_{index:04}.png`, with these placeholders:
+    /// 
+    /// - `{index}` is the number of the command in input order, from 0.
+    /// 
+    /// - `{stem}`, `{ext}` and `{dirname}` are parts of its input path: the match of `--name-pattern`,
+    ///   the path of `--each` or entry of `--input-archive`, or else the last word of the command.
+    /// 
+    /// - `{date}` is the date of the run, like `2024-01-31`, in UTC.
+    /// 
+    /// - `{cmd_hash}` is the first 8 hex digits of the SHA-256 of the command.
+    /// 
+    /// - `{N}` and `{NAME}` are the captures of `--name-pattern`, if given.
+    /// 
+    /// Numbers are padded with zeros as in `{index:04}`. The name prefix and postfix still apply.
+    #[arg(long = "name-template", value_name = "TEMPLATE", value_parser = names::Template::parse, conflicts_with_all = ["name_replace", "name_hash", "name_from_output"])]
+    name_template: Option<names::Template>,
+    
+    /// Pad numeric names with zeros to this many digits, like `00001`, so that they sort in order.
+    /// 
+    /// Applies to the numbers of the default names, `{index}` of `--name-template`, and `--name-from-output`.
+    #[arg(long = "index-width", value_name = "DIGITS", default_value_t = 0)]
+    index_width: usize,
+    
+    /// Number the commands from this instead of 0; e.g. to continue the numbering of a previous run with `--append`.
+    #[arg(long = "start-index", value_name = "N", default_value_t = 0)]
+    start_index: usize,
+    
+    /// Name each file after the hash of its content, for content-addressed archives; pairs well with `--dedup`.
+    /// 
+    /// The name prefix and postfix still apply, as in `--name-hash sha256 --name-postfix .png`.
+    /// Identical outputs get the same name, so only the first is kept, unless `--on-collision` says otherwise.
+    #[arg(long = "name-hash", value_enum, value_name = "HASH", conflicts_with = "name_pattern")]
+    name_hash: Option<Checksum>,
+    
+    /// Name each file after what its command printed: either `first-line`, or a regex whose first capture group
+    /// (or entire match) is used, like `exported: (\S+)`.
+    /// 
+    /// Stdout is searched first, then stderr. The name prefix and postfix still apply;
+    /// commands that printed no name are numbered in input order instead.
+    #[arg(long = "name-from-output", value_name = "first-line|REGEX", value_parser = parse_name_from, conflicts_with_all = ["name_pattern", "name_hash"])]
+    name_from_output: Option<NameFrom>,
+    
     /// Prefix to prepend to all generated filenames.
     /// 
     /// Applied AFTER regex match/replace.
@@ -99,206 +309,1939 @@ struct CmdToZip {
     #[arg(short = 't', long = "threads", env = "RAYON_NUM_THREADS", default_value_t = 0)]
     threads: usize,
     
-    /// The maximum number of commands to run.
+    /// Size the thread-pool for this many child processes, so that `--control-file` can raise the parallelism up to it.
+    /// 
+    /// Defaults to `--threads`; only that many commands run at once, unless changed via the control file.
+    #[arg(long = "max-threads", value_name = "N", requires = "control_file", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_threads: Option<usize>,
+    
+    /// Put the commands matching the regex into a group, as in `gpu=blender|ffmpeg`, for `--group-limit`;
+    /// may be given multiple times, the first matching one applies.
+    /// 
+    /// The `group` column or field of CSV and JSONL inputs takes precedence.
+    #[arg(long = "group", value_name = "NAME=REGEX", value_parser = parse_group, action = clap::ArgAction::Append)]
+    groups: Vec<(String, Regex)>,
+    
+    /// Run at most this many commands of the group at once, as in `gpu=1`; may be given multiple times.
+    /// 
+    /// Commands of other groups, or of none, are only limited like all commands are, see `--threads`.
+    #[arg(long = "group-limit", value_name = "NAME=N", value_parser = parse_group_limit, action = clap::ArgAction::Append)]
+    group_limits: Vec<(String, usize)>,
+    
+    /// Watch this file while running, to steer the run from outside: a number in it sets how many commands run at once,
+    /// and a line `status` prints the running commands, like `SIGUSR1` does on Unix.
+    /// 
+    /// The file is read at the start, if it exists, and again whenever it's modified; e.g. `echo 2 > ctl` to slow down.
+    #[arg(long = "control-file", value_name = "PATH")]
+    control_file: Option<PathBuf>,
+    
+    /// Keep this file up to date with metrics of the run in the OpenMetrics text format, rewriting it every few seconds;
+    /// e.g. for the textfile collector of the Prometheus node exporter.
+    /// 
+    /// Covers the commands queued, completed and failed, the bytes written, and how many commands are running.
+    #[arg(long = "metrics-file", value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    
+    /// Serve the metrics of `--metrics-file` over HTTP on this address while running, e.g. `127.0.0.1:9464`.
+    #[arg(long = "metrics-listen", value_name = "ADDR")]
+    metrics_listen: Option<std::net::SocketAddr>,
+    
+    /// Start at most this many commands per second, e.g. `0.5` for one every two seconds.
+    #[arg(long = "rate", value_name = "N", value_parser = parse_rate)]
+    rate: Option<f64>,
+    
+    /// Don't start any command while the load average of the last minute exceeds this; Unix only.
+    /// 
+    /// Commands that are already running are left alone.
+    #[arg(long = "max-load", value_name = "LOAD")]
+    max_load: Option<f64>,
+    
+    /// The maximum number of commands to run, counted after those skipped by `--skip`.
+    /// 
+    /// Comments, and commands left out by `--filter` or `--exclude`, don't count.
     #[arg(short = 'l', long = "limit")]
     limit: Option<usize>,
     
+    /// Skip this many commands before running any; e.g. `--skip 5000 --limit 5000` runs the second five thousand.
+    /// 
+    /// Comments, and commands left out by `--filter` or `--exclude`, don't count.
+    #[arg(long = "skip", value_name = "N", default_value_t = 0)]
+    skip: usize,
+    
+    /// Run only this many commands, picked at random; for spot-checking a pipeline before the full run.
+    /// 
+    /// All commands are read in before any is run. Comments are dropped;
+    /// sampling happens before `--filter`, `--exclude`, `--skip` and `--limit` apply.
+    #[arg(long = "sample", value_name = "N")]
+    sample: Option<usize>,
+    
+    /// The seed for `--sample`, to pick the same commands again; otherwise, a random one is used and printed.
+    #[arg(long = "seed", value_name = "N", requires = "sample")]
+    seed: Option<u64>,
+    
+    /// Only run the commands matching this regex, skipping all others.
+    #[arg(long = "filter", value_name = "REGEX")]
+    filter: Option<Regex>,
+    
+    /// Skip the commands matching this regex.
+    #[arg(long = "exclude", value_name = "REGEX")]
+    exclude: Option<Regex>,
+    
+    /// What `--filter` and `--exclude` match against.
+    #[arg(long = "filter-on", value_enum, value_name = "WHAT", default_value_t)]
+    filter_on: FilterOn,
+    
+    /// Terminate commands that are still running after the given duration, e.g. `30s` or `5m`.
+    /// 
+    /// Their output is written to the archive as `.timeout`-file.
+    #[arg(long = "timeout", value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    
+    /// Terminate commands that wrote nothing to stdout or stderr for the given duration, however long they ran before;
+    /// e.g. for commands hanging on a prompt.
+    /// 
+    /// Like with `--timeout`, their output is written to the archive as `.timeout`-file.
+    #[arg(long = "stall-timeout", value_name = "DURATION", value_parser = parse_duration)]
+    stall_timeout: Option<Duration>,
+    
+    /// How long to wait for a terminated command to exit, before killing it forcefully.
+    #[arg(long = "kill-after", value_name = "DURATION", value_parser = parse_duration, default_value = "5s")]
+    kill_after: Duration,
+    
+    /// The signal asking commands to exit, when they time out, on `--fail-fast` and on Ctrl-C; e.g. `SIGINT`,
+    /// for commands that only write out partial results on that. Default is `SIGTERM`; Unix only.
+    /// 
+    /// Given by name, with or without the `SIG`-prefix, or by number.
+    #[arg(long = "term-signal", value_name = "SIGNAL", value_parser = exec::parse_signal)]
+    term_signal: Option<i32>,
+    
+    /// The most memory (address space, to be exact) each command may use, like `4G`; Unix only.
+    /// 
+    /// Commands exceeding it fail to allocate more, which usually makes them fail; like other failures,
+    /// their output goes into an `.err`-file. Only applies to local commands, not `--remote` or `--container` ones.
+    #[arg(long = "memory-limit", value_name = "SIZE", value_parser = parse_size)]
+    memory_limit: Option<u64>,
+    
+    /// The most CPU time each command may use, in whole seconds, before it's killed; Unix only.
+    /// 
+    /// Only applies to local commands, not `--remote` or `--container` ones.
+    #[arg(long = "cpu-time-limit", value_name = "DURATION", value_parser = parse_duration)]
+    cpu_time_limit: Option<Duration>,
+    
+    /// The niceness of each command, from -20 to 19, like with `nice`; higher values leave more CPU time to others.
+    /// 
+    /// On Windows, positive values run commands with below-normal priority, and 15 or more with idle priority.
+    /// Only applies to local commands, not `--remote` or `--container` ones.
+    #[arg(long, value_name = "N", allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+    
+    /// The I/O scheduling class of each command, to leave the disks to others; Linux only.
+    #[arg(long, value_name = "CLASS")]
+    ionice: Option<exec::IoClass>,
+    
+    /// How often to retry commands that failed or timed out, before giving up on them.
+    #[arg(long = "retries", value_name = "N", default_value_t = 0)]
+    retries: u32,
+    
+    /// How long to wait before the first retry; doubles with every further retry, up to 5 minutes (or the initial delay, if that's longer).
+    #[arg(long = "retry-delay", value_name = "DURATION", value_parser = parse_duration, default_value = "500ms")]
+    retry_delay: Duration,
+    
+    /// Run each command through a shell, allowing for pipes, redirection and the like.
+    /// 
+    /// Defaults to `sh` on Unix, and `cmd` on Windows; pick another via e.g. `--shell=powershell`.
+    #[arg(long = "shell", value_enum, value_name = "SHELL", num_args = 0..=1, require_equals = true, default_missing_value = exec::DEFAULT_SHELL)]
+    shell: Option<exec::Shell>,
+    
+    /// The shell to run commands through on Windows only, overriding `--shell` there; for config files shared across platforms.
+    /// 
+    /// `none` splits the commands into arguments instead, as without `--shell`.
+    #[arg(long = "windows-shell", value_enum, value_name = "SHELL")]
+    windows_shell: Option<WindowsShell>,
+    
+    /// What to do with the path separators within commands, which glob-expansion emits natively.
+    /// 
+    /// - `keep` leaves them as they are; the default outside of Windows.
+    /// 
+    /// - `forward` turns backward-slashes into forward-slashes; the default on Windows, where many tools accept both.
+    /// 
+    /// - `backward` turns forward-slashes into backward-slashes, for native Windows tools and UNC paths.
+    #[arg(long = "path-style", value_enum, value_name = "STYLE", default_value_t = PathStyle::native())]
+    path_style: PathStyle,
+    
+    /// The working directory to run all commands in; relative paths within commands are resolved against it.
+    #[arg(long = "cwd", value_name = "DIR")]
+    cwd: Option<PathBuf>,
+    
+    /// Set an environment variable for all commands; may be given multiple times.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env, action = clap::ArgAction::Append)]
+    env: Vec<(String, String)>,
+    
+    /// Set the environment variables of this file for all commands, one `KEY=VALUE` per line; may be given multiple times.
+    /// 
+    /// Blank lines and lines starting with `#` are ignored, as is a leading `export`; values may be quoted.
+    /// Variables of `--env` take precedence.
+    #[arg(long = "env-file", value_name = "PATH", action = clap::ArgAction::Append)]
+    env_files: Vec<PathBuf>,
+    
+    /// Run the commands with an empty environment, except for `PATH` and the variables of `--env` and `--env-file`,
+    /// for reproducible runs; on Windows, `SystemRoot` is kept as well, which most programs need to start at all.
+    /// 
+    /// With `--remote`, the environment on the remote host is cleared instead; containers start clean anyway.
+    #[arg(long = "clean-env", default_value = "false")]
+    clean_env: bool,
+    
+    /// Run the commands on this host via `ssh`, instead of locally; may be given multiple times for a pool of hosts.
+    /// 
+    /// The outputs are still captured into the local archive; `--cwd` and `--env` apply on the remote host.
+    /// Authentication must not be interactive, e.g. via keys or an agent.
+    #[arg(long = "remote", value_name = "USER@HOST", action = clap::ArgAction::Append)]
+    remote: Vec<String>,
+    
+    /// Run each command in a fresh container of this image, like `docker run --rm IMAGE ...`, instead of locally.
+    /// 
+    /// The outputs are still captured into the local archive; `--cwd` and `--env` apply within the container.
+    #[arg(long = "container", value_name = "IMAGE", conflicts_with = "remote")]
+    container: Option<String>,
+    
+    /// The container engine to run `--container` with.
+    #[arg(long = "container-engine", value_enum, value_name = "ENGINE", default_value_t, requires = "container")]
+    container_engine: exec::Engine,
+    
+    /// Mount a host path into the container, like `SRC:DST` or `SRC:DST:ro`; may be given multiple times.
+    #[arg(long = "mount", value_name = "SRC:DST", action = clap::ArgAction::Append, requires = "container")]
+    mounts: Vec<String>,
+    
+    /// The maximum number of bytes each of stdout and stderr may hold; accepts suffixes like `K`, `M` or `G`.
+    #[arg(long = "max-output-size", value_name = "SIZE", value_parser = parse_size)]
+    max_output_size: Option<u64>,
+    
+    /// The most output held in memory at once, like `2G`, counting both running commands and finished ones
+    /// waiting for the archive writer; beyond it, output is spilled to temporary files instead.
+    /// 
+    /// Without it, each stdout and stderr keeps up to 8 MiB in memory, which adds up with many commands at once
+    /// or many finished ones waiting behind a slow one with `--ordered`.
+    #[arg(long = "memory-budget", value_name = "SIZE", value_parser = parse_size)]
+    memory_budget: Option<u64>,
+    
+    /// Refuse to start unless the file system of the output has at least this much free space, like `20G`;
+    /// an estimate of how large the archive gets. Unix only.
+    /// 
+    /// A new archive replaces the previous one only once it's finished, so both need to fit meanwhile.
+    #[arg(long = "require-space", value_name = "SIZE", value_parser = parse_size)]
+    require_space: Option<u64>,
+    
+    /// What to do with outputs exceeding `--max-output-size`; the manifest and summary record these either way.
+    /// 
+    /// - `truncate` keeps the output up to the maximum size.
+    /// 
+    /// - `skip` leaves it out of the archive.
+    /// 
+    /// - `error` stops reading it, failing the command.
+    /// 
+    /// - `spill` writes it in full into `--spill-dir`, instead of the archive.
+    #[arg(long = "on-oversize", value_enum, value_name = "POLICY", default_value_t, requires = "max_output_size")]
+    on_oversize: Oversize,
+    
+    /// Where `--on-oversize spill` writes outputs to; defaults to the output path, plus `.oversize`.
+    #[arg(long = "spill-dir", value_name = "DIR")]
+    spill_dir: Option<PathBuf>,
+    
+    /// Feed the contents of the given file to every command via stdin; otherwise, stdin is empty.
+    #[arg(long = "stdin-from", value_name = "PATH")]
+    stdin_from: Option<PathBuf>,
+    
+    /// Give every command a fresh temporary directory to write files into, for tools that won't write to stdout;
+    /// its path replaces `{dir}` within the command, and is in the `CMD2ZIP_OUT` environment variable.
+    /// 
+    /// Once a command succeeds, every file within its directory is written to the archive,
+    /// under the generated file-name as directory, instead of its stdout. Only applies to local commands.
+    #[arg(long = "capture-dir", conflicts_with_all = ["remote", "container"])]
+    capture_dir: bool,
+    
+    /// Run a follow-up command on the output of every command that succeeded, writing its output instead.
+    /// 
+    /// `{}` is replaced with the path of a temporary file holding the output; without it, the output is fed via stdin.
+    /// May be given multiple times, for a chain of stages that each work on the output of the one before.
+    /// The stages run locally, split like the commands are, or via `--shell` if given; if one fails, so does the command.
+    #[arg(long = "then", value_name = "TEMPLATE", action = clap::ArgAction::Append)]
+    then: Vec<String>,
+    
+    /// Stream the stdout of every command that succeeded through this filter command, like `gzip -9`,
+    /// writing the filter's output into the archive instead.
+    /// 
+    /// The filter runs locally, split like the commands are, or via `--shell` if given;
+    /// if it fails, so does the command, with the filter's stderr in its `.err`-entry.
+    #[arg(long = "pipe", value_name = "FILTER")]
+    pipe: Option<String>,
+    
+    /// Transcode the output of every command from this encoding into UTF-8, before any `--then` or `--pipe`;
+    /// for tools that print UTF-16 or a legacy codepage, like many on Windows do.
+    #[arg(long = "encoding", value_enum, default_value_t = Encoding::Keep)]
+    encoding: Encoding,
+    
+    /// Turn CRLF line endings in the output of every command into LF, along with `--encoding`.
+    #[arg(long = "normalize-newlines")]
+    normalize_newlines: bool,
+    
+    /// Feed each command the file at this path via stdin, expanded from the name pattern like `--name-replace`.
+    /// 
+    /// A typical replacement would be `$1.md`, for running e.g. `pandoc -f markdown -t html` over many files.
+    #[arg(long = "stdin-replace", value_name = "REPLACEMENT", requires = "name_pattern", conflicts_with = "stdin_from")]
+    stdin_replace: Option<String>,
+    
+    /// Stop running further commands as soon as one fails, finishing the archive with what was captured.
+    /// 
+    /// Commands that are still running are asked to exit with `--term-signal`, like on Ctrl-C; Unix only.
+    #[arg(long = "fail-fast", default_value = "false")]
+    fail_fast: bool,
+    
+    /// How the `.err`- and `.timeout`-entries of failed commands are written.
+    /// 
+    /// - `text` is a report of the exit code (or signal), duration and full command, followed by both stdout and stderr.
+    /// 
+    /// - `json` is the same report as JSON object.
+    /// 
+    /// - `raw` is only stdout, or stderr if that's empty.
+    #[arg(long = "err-format", value_enum, value_name = "FORMAT", default_value_t)]
+    err_format: ErrFormat,
+    
+    /// What to do with commands that succeeded without writing anything to stdout.
+    /// 
+    /// Failed commands get their stderr instead regardless, unless it's captured via `--capture-stderr`.
+    #[arg(long = "on-empty-stdout", value_enum, value_name = "POLICY", default_value_t)]
+    on_empty_stdout: EmptyStdout,
+    
+    /// Write stderr into entries of its own, instead of using it in place of an empty stdout.
+    /// 
+    /// - `suffix` names them like the stdout entry, plus `.stderr`.
+    /// 
+    /// - `dir` names them like the stdout entry, but within a `stderr/` directory.
+    /// 
+    /// Commands without any stderr output get no such entry.
+    #[arg(long = "capture-stderr", value_enum, value_name = "LAYOUT", num_args = 0..=1, default_missing_value = "suffix")]
+    capture_stderr: Option<StderrLayout>,
+    
+    /// What to do when an entry of the same name was already written, during this run or (when appending) before.
+    /// 
+    /// - `error` stops writing, keeping what was written so far.
+    /// 
+    /// - `overwrite` replaces the earlier entry.
+    /// 
+    /// - `skip` keeps the earlier entry.
+    /// 
+    /// - `rename` numbers the later entry, as in `name-1.ext`.
+    #[arg(long = "on-collision", value_enum, value_name = "POLICY", default_value_t = Collision::Error)]
+    on_collision: Collision,
+    
+    /// Also write entries for the directories that generated file-names are in, e.g. via `-r 'thumbnails/$name.png'`.
+    /// 
+    /// Most extractors create directories as needed, but some only create those they find entries for.
+    #[arg(long = "dir-entries", default_value = "false")]
+    dir_entries: bool,
+    
+    /// Strip the directories from generated file-names, keeping only the last component.
+    #[arg(long = "flatten", default_value = "false")]
+    flatten: bool,
+    
+    /// Keep a leading `/` in generated file-names, instead of making them relative.
+    /// 
+    /// Names are sanitized regardless: `..` may not escape the archive, and characters invalid on Windows become `_`.
+    #[arg(long = "allow-absolute-paths", default_value = "false")]
+    allow_absolute_paths: bool,
+    
     /// Append to the zip archive specified by `output`, instead of replacing it.
     #[arg(short, long = "append", default_value = "false")]
     append: bool,
     
+    /// When appending, don't run commands whose file-name is already in the archive.
+    /// 
+    /// Failed commands are retried, since their outputs went into `.err`- or `.timeout`-files.
+    #[arg(long = "skip-existing", default_value = "false", requires = "append")]
+    skip_existing: bool,
+    
+    /// Append to the archive if it exists, with each entry replacing any earlier one of the same name;
+    /// implies `--on-collision overwrite`.
+    /// 
+    /// Unlike with that alone, the replaced entries' data is dropped from zip archives, rather than only left
+    /// unreachable, so that no extractor can pick the stale one. Tarballs can only be appended to.
+    #[arg(long = "replace-existing")]
+    replace_existing: bool,
+    
+    /// Append to the archive if it exists, only running commands whose input file was modified after their entry;
+    /// like `make` would.
+    /// 
+    /// The input file is the path given to `--each`, or else the part of the command matched by `--name-pattern`.
+    /// Commands without an entry, or whose input file doesn't exist, are run regardless; their entries replace
+    /// the earlier ones, unless `--on-collision` says otherwise.
+    /// Entries are dated to when their archive was opened, so inputs modified during a run are rebuilt by the next;
+    /// zip archives only keep these dates to within 2 seconds, erring on the side of rebuilding.
+    #[arg(long = "incremental", conflicts_with = "deterministic")]
+    incremental: bool,
+    
+    /// Record every written entry in the given file as the run progresses, for `--resume`.
+    #[arg(long = "state", value_name = "PATH")]
+    state: Option<PathBuf>,
+    
+    /// Continue an interrupted run, appending to its archive and skipping the commands its `--state` file lists.
+    /// 
+    /// An archive left unfinished by the interruption is recovered first; anything not in it is run again.
+    /// That's the `.part`-file next to the output, unless the run was appending to an existing archive.
+    #[arg(long = "resume", default_value = "false", requires = "state")]
+    resume: bool,
+    
+    /// Concatenate the outputs of all commands that succeeded into a single entry of this name, in input order,
+    /// instead of writing an entry for each; like assembling JSON fragments into one JSONL file.
+    /// 
+    /// Failed commands still get `.err`-entries of their own, as do separately captured stderrs.
+    #[arg(long = "merge-into", value_name = "NAME", conflicts_with_all = ["append", "replace_existing", "incremental", "watch", "resume"])]
+    merge_into: Option<String>,
+    
+    /// What goes between the outputs concatenated by `--merge-into`; `\n`, `\t`, `\0` and `\\` are unescaped.
+    #[arg(long = "merge-separator", value_name = "SEPARATOR", requires = "merge_into", default_value = "", hide_default_value = true, value_parser = parse_separator)]
+    merge_separator: String,
+    
+    /// Write the entries into the archive in the order of the commands, instead of the order they finish in.
+    /// 
+    /// Finished outputs are held back until those of all earlier commands were written.
+    #[arg(long = "ordered", default_value = "false")]
+    ordered: bool,
+    
+    /// Make the archive byte-identical across runs with the same commands and outputs.
+    /// 
+    /// Implies `--ordered`; entries are written with their modification time taken from `SOURCE_DATE_EPOCH`
+    /// (or 1980-01-01, if that isn't set), and the manifest leaves out timings.
+    #[arg(long = "deterministic", default_value = "false")]
+    deterministic: bool,
+    
+    /// Instead of writing an archive, compare the outputs of the commands against the entries of this one,
+    /// a zip archive or a directory, which is left as it is; for catching commands whose output isn't reproducible.
+    /// 
+    /// Entries are compared by their SHA-256, and listed if they differ, are missing from the archive,
+    /// or if the archive has entries this run didn't produce; if any are, the exit code is 1.
+    #[arg(long = "verify", value_name = "ARCHIVE", conflicts_with_all = [
+        "output", "append", "replace_existing", "incremental", "resume", "state", "split_size", "shard_by", "watch", "dry",
+    ])]
+    verify: Option<PathBuf>,
+    
     /// Instead of running and capturing commands, write the commands themself to the archive.
-    #[arg(short = 'd', long = "dry-run", default_value = "false")]
-    dry: bool,
+    /// 
+    /// - `files` writes each command into an entry of its own, named like its output would be, plus `.txt`.
+    /// 
+    /// - `list` writes a single `commands.txt` entry, with a line of the entry name and command of each, separated by a tab;
+    ///   names that are invalid or used by more than one command are reported, failing the run.
+    #[arg(short = 'd', long = "dry-run", value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "files")]
+    dry: Option<DryRun>,
     
-    /// The commands to run; allows for glob-expansion, even on Windows!
-    #[arg(action = clap::ArgAction::Append)]
-    commands: Vec<String>
-}
-
-
-fn main() {
-    let args = wild::args_os();
-    let mut args = CmdToZip::parse_from(args);
+    /// Write the commands that failed to the given file, one per line, exactly as they were read in.
+    /// 
+    /// The file is always created, so it'll be empty if no command failed;
+    /// feed it back via `--input` and `--append` to retry only the failures.
+    #[arg(long = "failed-out", visible_alias = "failed-list", value_name = "PATH")]
+    failed_out: Option<PathBuf>,
     
-    let prefix = Arc::new(args.prefix.map(|s| s + " ").unwrap_or_else(||String::default()));
-    let postfix = Arc::new(args.postfix.unwrap_or_else(||String::default()));
+    /// Write the `.err`- and `.timeout`-entries of failed commands into this directory of the archive,
+    /// e.g. `errors/`, instead of next to the outputs of the commands that succeeded.
+    #[arg(long = "failed-dir", value_name = "DIR", value_parser = parse_failed_dir)]
+    failed_dir: Option<String>,
     
-    let pool = ThreadPoolBuilder::new()
-        .num_threads(0)
-        .build()
-        .expect("failed to build thread-pool");
+    /// Don't write failed commands into the archive at all; they're still reported, counted and written to `--failed-out`.
+    #[arg(long = "drop-failed", default_value = "false", conflicts_with = "failed_dir")]
+    drop_failed: bool,
     
-    let mut name_gen: Arc<dyn Fn(&str) -> String + Send + Sync> = match (args.name_pattern, args.name_replace) {
-        (Some(r), None) => {
-            eprintln!("-- Using regex-based name generator without replacement: {}", r.as_str());
-            Arc::new(move |c: &str| {
-                r.find(c).expect("failed to capture").as_str().to_string()
-            })
-        },
-        (Some(r), Some(p)) => {
-            eprintln!("-- Using regex-based name generator with replacement expansion: {} / {}", r.as_str(), p.as_str());
-            Arc::new(move |c: &str| {
-                let captures = r.captures(c).expect("failed to capture pattern");
-                let mut name = String::with_capacity(16);
-                captures.expand(&p, &mut name);
-                name
-            })
-        },
-        (None, Some(_)) => panic!("cannot specify replacement without regex"),
-        (None, None) => {
-            eprintln!("-- Using numeric name generator.");
-            let counter = Arc::new(AtomicUsize::new(0));
-            Arc::new(
-                move |_c: &str| {
-                    let num = counter.fetch_add(1, Ordering::Relaxed);
-                    format!("{}", num)
-                }
-            )
-        },
-    };
+    /// Also write a `manifest.json` entry, describing how every other entry was produced.
+    #[arg(long = "manifest", default_value = "false")]
+    manifest: bool,
     
-    if let Some(np) = args.name_prefix {
-        let old = name_gen.clone();
-        name_gen = Arc::new(move |c| {
-            format!("{}{}", np, (old)(c))
-        });
-    }
+    /// The comment of the zip archive; `{info}` within it is replaced with the default comment, and an empty one leaves it out.
+    /// 
+    /// The default describes how the archive was made: the version of cmd2zip, the full invocation,
+    /// and (unless `--deterministic`) the host and when the run started and finished.
+    #[arg(long = "archive-comment", value_name = "TEXT", default_value = "{info}", hide_default_value = true)]
+    archive_comment: String,
     
-    if let Some(np) = args.name_postfix {
-        let old = name_gen.clone();
-        name_gen = Arc::new(move |c| {
-            format!("{}{}", (old)(c), np)
-        });
-    }
+    /// Attach the full command line of every entry to it as comment; only zip archives can hold those.
+    #[arg(long = "comment-commands", default_value = "false")]
+    comment_commands: bool,
     
-    let archive = if args.append {
-        let archive = File::options().write(true).append(true).open(&args.output).unwrap();
-        let archive = ZipWriter::new_append(archive).expect("failed to open archive for appending");
-        archive
-    } else {
-        let archive = File::create(&args.output).unwrap();
-        let archive = ZipWriter::new(archive);
-        archive
-    };
+    /// Also write an entry listing the checksums of all other entries written in this run, like `SHA256SUMS`.
+    /// 
+    /// With `--manifest`, each of its entries gets its checksum as well.
+    #[arg(long = "checksums", value_enum, value_name = "HASH")]
+    checksums: Option<Checksum>,
     
-    let archive = Mutex::new(archive);
-    let archive = Arc::new(archive);
+    /// Don't store outputs identical to earlier ones twice; instead, skip them or link them to the earlier entry.
+    /// 
+    /// `manifest` requires `--manifest`, where the duplicates are recorded with the name of their original.
+    #[arg(long = "dedup", value_enum, value_name = "MODE")]
+    dedup: Option<Dedup>,
     
-    let commands: Box<dyn Iterator<Item = String>> = if let Some(input) = args.input {
-        Box::new(open_input(input).chain(args.commands))
-    } else {
-        Box::new(args.commands.into_iter())
-    };
+    /// Also write the summary printed at the end of the run into the archive, as `summary.txt`.
+    #[arg(long = "summary-entry", default_value = "false")]
+    summary_entry: bool,
     
-    let tasks = Arc::new(AtomicUsize::new(0));
+    /// Also write when each command started, how long it took, the CPU time it used and the size of its output
+    /// into the archive, as `timings.csv`.
+    /// 
+    /// CPU time covers all attempts, `--then` stages and the `--pipe` filter; it's left empty where unknown, like on Windows.
+    #[arg(long = "timings", default_value = "false")]
+    timings: bool,
     
-    for command in commands {
-        
-        if let Some(limit) = &mut args.limit {
-            *limit -= 1;
-            if *limit == 0 {
-                eprintln!("!! Reached command limit");
-                break;
-            }
-        }
-        
-        let tasks = tasks.clone();
-        let archive = archive.clone();
-        let prefix = prefix.clone();
-        let postfix = postfix.clone();
-        let name_gen = name_gen.clone();
-        
-        // Ignore commands starting with a hashtag
-        if command.starts_with('#') {
-            eprintln!("## {}", &command[1..]);
-            continue;
-        }
-        
-        tasks.fetch_add(1, Ordering::Relaxed);
-        
-        pool.spawn(move || {
-            // FIXME: The wild-crate emits backward-slashes on windows, which may break some commands.
-            // TODO: Perhaps make this an option?
-            #[cfg(target_os = "windows")]
-            let command = command.replace("\\", "/");
-            
-            let full_command = format!("{prefix}{command}{postfix}");
-            
-            // Generate file-name!
-            let mut name = (name_gen)(&command);
-            
-            // --- Build the command and run the child-process
-            
-            // Note: This blocks until the child finishes, ON PURPOSE.
-            let (status, mut stdout, mut stderr) = if ! args.dry {
-                let output = build_command(&full_command).output().expect("failed to run command");
-                (output.status.success(), output.stdout, output.stderr)
-            } else {
-                name = name + ".txt";
-                (true, full_command.as_bytes().to_vec(), vec![])
-            };
-            
-            // --- Process output...
-            let mut using = "stdout";
-            
-            if stdout.len() == 0 {
-                eprintln!("!! Command had no stdout, writing stderr instead: {full_command}");
-                std::mem::swap(&mut stdout, &mut stderr);
-                using = "stderr";
-            }
-            
-            if !status {
-                eprintln!("!! Command failed: {full_command}\n{}", std::str::from_utf8(&stdout).unwrap());
-                name = name + ".err";
-            }
-            
-            println!("`{name}` << {} bytes from {using} << `{full_command}`", stdout.len());
-            append_to_archive(&archive, &name, &stdout);
-            
-            tasks.fetch_sub(1, Ordering::Relaxed);
-        });
-    }
+    /// Benchmark the commands: run each this many times in a row, and write how long the runs took into the archive,
+    /// as `bench.json`; with their minimum, mean, maximum and standard deviation, in seconds.
+    /// 
+    /// Only the commands themselves are repeated and timed, not their `--then` stages or the `--pipe` filter;
+    /// a failing run ends the repetitions of its command, which is then reported as failed.
+    #[arg(long = "bench", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    bench: Option<u32>,
+    
+    /// Which of the runs of `--bench` write their output into the archive.
+    /// 
+    /// Files in `--capture-dir` are always those of the last run.
+    #[arg(long = "bench-keep", value_enum, value_name = "RUN", default_value_t, requires = "bench")]
+    bench_keep: BenchKeep,
     
-    eprintln!("-- Waiting for all children to finish...");
+    /// Write the statistics of `--bench` into this file instead of the archive; `-` prints them to stdout.
+    #[arg(long = "bench-out", value_name = "PATH", requires = "bench")]
+    bench_out: Option<PathBuf>,
     
-    // Now wait for all children to finish...
-    while tasks.load(Ordering::Relaxed) != 0 {}
+    /// Also write the commands that were run into the archive as a shell script of this name,
+    /// with each of them writing its output to the file named like its entry; e.g. `commands.sh`.
+    #[arg(long = "emit-script", value_name = "NAME")]
+    emit_script: Option<String>,
     
-    let mut a = archive.lock().expect("failed to re-acquire archive writer");
-    a.finish().expect("failed to finish writing archive");
-    drop(a);
-    drop(archive);
+    /// How many of the slowest commands the summary lists.
+    #[arg(long = "slowest", value_name = "N", default_value_t = 5)]
+    slowest: usize,
     
-    eprintln!("-- Done!");
-}
-
-fn open_input(input: PathBuf) -> Box<dyn std::iter::Iterator<Item = String>> {
-    if input == PathBuf::from("-") {
-        Box::new(
-            std::io::stdin()
-            .lines()
-            .flatten()
-        )
-    } else {
-        Box::new(
-            std::io::BufReader::new(std::fs::File::open(input).expect("failed to open input file"))
-            .lines()
-            .flatten()
-        )
-    }
-}
-
-fn build_command(command: &str) -> Command {
-    let split_command = shlex::split(command).expect("failed to shlex command");
-    let mut child = Command::new(&split_command[0]);
-    child.args(&split_command[1..]);
-    child
+    /// How to report progress on stdout; `json` emits one object per event, one per line.
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    
+    /// Don't list the finished commands, nor print informational lines; warnings and errors are still printed.
+    /// 
+    /// Doesn't affect `--log-format json`.
+    #[arg(short = 'q', long = "quiet", conflicts_with = "verbose")]
+    quiet: bool,
+    
+    /// Print extra diagnostics: `-v` for the full command line and spawn time of every child-process,
+    /// `-vv` also for the time spent writing entries and waiting on the archive writer.
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+    
+    /// When to color the console output: green for commands that succeeded, red for failures,
+    /// and yellow for those whose stderr was used instead of stdout.
+    /// 
+    /// `auto` colors it if it goes to a terminal, unless the `NO_COLOR` environment variable is set.
+    #[arg(long = "color", value_enum, value_name = "WHEN", default_value_t)]
+    color: ColorChoice,
+    
+    /// Don't show a progress bar, even if stderr is a terminal.
+    #[arg(long = "no-progress", default_value = "false")]
+    no_progress: bool,
+    
+    /// Show a full-screen view of the run instead of the progress bar; Unix only.
+    /// 
+    /// It lists the commands running right now, how long they've been running and how much output they wrote so far,
+    /// along with the tally and the latest failures. Everything else printed meanwhile is shown once it's closed.
+    /// Keys: `p` pauses (or resumes) starting further commands, `+` and `-` change how many run at once,
+    /// and `q` stops the run like Ctrl-C does.
+    #[arg(long = "tui", default_value = "false")]
+    tui: bool,
+    
+    /// Also print the output of each command to stderr as it comes in, line by line, prefixed with its entry name;
+    /// lines written to stderr have their prefix in yellow.
+    #[arg(long = "tee", default_value = "false")]
+    tee: bool,
+    
+    /// Treat the commands as file paths instead, running this template for each of them.
+    /// 
+    /// - `{}` is replaced with the path.
+    /// 
+    /// - `{.}` is replaced with the path without its extension.
+    /// 
+    /// - `{/}` is replaced with the basename of the path.
+    /// 
+    /// - `{/.}` is replaced with the basename without its extension.
+    /// 
+    /// - `{//}` is replaced with the directory of the path, or `.` if it has none.
+    /// 
+    /// Unless a name pattern is given, the basename of each path (without extension) is used as file-name.
+    #[arg(long = "each", visible_alias = "arg-template", value_name = "TEMPLATE")]
+    each: Option<String>,
+    
+    /// Run every command once for each value listed in this file, one per line, substituting it for `{NAME}`;
+    /// like `--args-list res=sizes.txt` with `resvg -w {res} icon.svg`.
+    /// 
+    /// May be given multiple times, running every command for each combination of the values of all lists;
+    /// in `--each` templates as well. Entry names may hold the placeholders too, like `--name-postfix '@{res}.png'`;
+    /// if they don't, the entries of each combination go into directories named after its values, like `128/icon.png`.
+    #[arg(long = "args-list", value_name = "NAME=FILE", value_parser = parse_args_list, action = clap::ArgAction::Append,
+        conflicts_with = "batch_size")]
+    args_lists: Vec<(String, PathBuf)>,
+    
+    /// Run the `--each` template for this many paths at once, rather than one at a time, like `xargs -n`;
+    /// for tools that are quick per path, but slow to start.
+    /// 
+    /// Each word of the template holding a placeholder is repeated for every path of the batch,
+    /// so `gzip -c {}` becomes `gzip -c a b c`, and `--in={}` becomes `--in=a --in=b --in=c`.
+    /// The output of a batch goes into a single entry, named after its first path.
+    #[arg(long = "batch-size", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        requires = "each", conflicts_with_all = ["watch", "incremental", "stdin_replace"])]
+    batch_size: Option<usize>,
+    
+    /// Run the command for every entry of this zip archive instead, feeding it the entry's content via stdin;
+    /// for transforming one archive into another, like `--input-archive in.zip -o out.zip "gzip -d"`.
+    /// 
+    /// Exactly one command must be given, as the template; `{name}` in it is replaced with the name of the entry.
+    /// Its output is written under that same name, unless a name pattern (which sees the name) says otherwise.
+    /// Directory entries are left out.
+    #[arg(long = "input-archive", value_name = "ARCHIVE", conflicts_with_all = ["each", "input", "stdin_from", "stdin_replace", "watch"])]
+    input_archive: Option<PathBuf>,
+    
+    /// Once the archive is written, keep watching the paths given to `--each`, re-running the command of any that
+    /// changes and replacing its entry in the archive.
+    /// 
+    /// Turns cmd2zip into a tiny incremental build during development; stop it with Ctrl+C.
+    /// A re-run command that failed before leaves its `.err`-entry behind.
+    #[arg(long = "watch", requires = "each", conflicts_with_all = ["state", "split_size", "shard_by", "password", "password_file", "dry"])]
+    watch: bool,
+    
+    /// How often `--watch` checks the paths for changes.
+    #[arg(long = "watch-interval", value_name = "DURATION", value_parser = parse_duration, default_value = "1s")]
+    watch_interval: Duration,
+    
+    /// Only run the commands of these paths, ignoring `--skip`, `--limit` and `--sample`; how `--watch` re-runs them.
+    #[arg(long = "watch-only", value_name = "PATH", hide = true, action = clap::ArgAction::Append)]
+    watch_only: Vec<String>,
+    
+    /// The commands to run; allows for glob-expansion, even on Windows!
+    #[arg(action = clap::ArgAction::Append)]
+    commands: Vec<String>
+}
+
+
+/// What `--dry-run` writes into the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DryRun {
+    /// An entry for every command, holding the command itself.
+    Files,
+    
+    /// A single entry listing all the commands along with their entry names.
+    List,
+}
+
+fn main() {
+    let mut args: Vec<_> = wild::args_os().collect();
+    
+    // Options of the config file would come before the subcommand, where they'd conflict with it.
+    let subcommand = args.get(1).and_then(|a| a.to_str()).is_some_and(|a| CmdToZip::command().find_subcommand(a).is_some());
+    
+    // Re-runs by `--watch` read the config file themselves.
+    let argv = args.clone();
+    if let Some(path) = config::find(&args).filter(|_| !subcommand) {
+        match config::load(&path) {
+            Ok(options) => drop(args.splice(1..1, options)),
+            Err(err) => {
+                status!("!! Failed to read config file `{}`: {err}", path.display());
+                std::process::exit(EXIT_USAGE);
+            },
+        }
+    }
+    // The password mustn't end up in the archive comment.
+    let mut secret = false;
+    let invocation = args.iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .map(|arg| match std::mem::replace(&mut secret, arg == "--password") {
+            true => "***".to_string(),
+            false if arg.starts_with("--password=") => "--password=***".to_string(),
+            false => arg,
+        })
+        .map(|arg| shlex::try_quote(&arg).map(|a| a.into_owned()).unwrap_or(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let started = SystemTime::now();
+    let mut args = CmdToZip::parse_from(args);
+    if let Some(generate) = args.generate.take() {
+        if let Err(err) = generate.print(CmdToZip::command()) {
+            status!("!! Failed to print: {err}");
+            std::process::exit(EXIT_FAILED);
+        }
+        return;
+    }
+    let to_stdout = args.output == Path::new("-");
+    let upload_url = args.output.to_str().filter(|o| upload::is_url(o)).map(String::from);
+    events::init(args.log_format, to_stdout);
+    events::set_color(args.color);
+    events::set_verbosity(if args.quiet { -1 } else { args.verbose.min(i8::MAX as u8) as i8 });
+    
+    let streamed = match (to_stdout, upload_url.is_some()) {
+        (true, _) => Some("written to stdout"),
+        (_, true) => Some("uploaded"),
+        _ => None,
+    };
+    
+    if let Some(streamed) = streamed {
+        if args.split_size.is_some() || args.shard_by.is_some() {
+            status!("!! Archives {streamed} can't be split");
+            std::process::exit(EXIT_USAGE);
+        }
+        
+        if args.format == Format::Dir {
+            status!("!! Directories can't be {streamed}");
+            std::process::exit(EXIT_USAGE);
+        }
+        
+        if args.append || args.watch || args.incremental {
+            status!("!! Archives {streamed} can't be appended to");
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+    
+    if args.dedup == Some(Dedup::Manifest) && !args.manifest {
+        status!("!! `--dedup manifest` requires `--manifest`");
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    if let Some(budget) = args.memory_budget {
+        cmd2zip::capture::set_memory_budget(budget);
+    }
+    
+    if to_stdout && args.bench_out.as_deref() == Some(Path::new("-")) {
+        status!("!! `--bench-out -` can't print to stdout while the archive is written there");
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    if let Some(required) = args.require_space {
+        let dir = args.output.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        match free_space(dir) {
+            _ if to_stdout || upload_url.is_some() => status!("!! The archive isn't written locally; ignoring `--require-space`"),
+            Some(free) if free < required => {
+                status!(
+                    "!! Only {} free at `{}`, but `--require-space` asks for {}",
+                    progress::format_bytes(free as f64),
+                    dir.display(),
+                    progress::format_bytes(required as f64),
+                );
+                std::process::exit(EXIT_USAGE);
+            },
+            Some(_) => {},
+            None => status!("!! Failed to tell the free space at `{}`; ignoring `--require-space`", dir.display()),
+        }
+    }
+    
+    let prefix = args.prefix.map(|s| s + " ").unwrap_or_default();
+    let postfix = args.postfix.unwrap_or_default();
+    let each = args.each;
+    let local = args.remote.is_empty() && args.container.is_none();
+    let limited = cfg!(unix) && local;
+    if (args.memory_limit.is_some() || args.cpu_time_limit.is_some()) && !limited {
+        status!("!! Resource limits only apply to local commands on Unix; ignoring `--memory-limit` and `--cpu-time-limit`");
+    }
+    if args.ionice.is_some() && !cfg!(target_os = "linux") {
+        status!("!! I/O scheduling classes are only available on Linux; ignoring `--ionice`");
+    }
+    let limits = exec::Limits {
+        timeout: args.timeout,
+        kill_after: args.kill_after,
+        term_signal: args.term_signal,
+        stall_timeout: args.stall_timeout,
+        max_output: args.max_output_size,
+        on_oversize: args.on_oversize,
+        memory: args.memory_limit.filter(|_| limited),
+        cpu_time: args.cpu_time_limit.filter(|_| limited),
+        nice: args.nice,
+        io_class: args.ionice,
+    };
+    if args.max_load.is_some() && throttle::load_average().is_none() {
+        status!("!! The load average isn't available on this platform; ignoring `--max-load`");
+    }
+    let throttle = throttle::Throttle::new(args.rate, args.max_load);
+    
+    let spill_dir = args.spill_dir.clone().unwrap_or_else(|| {
+        let mut path = args.output.clone().into_os_string();
+        path.push(".oversize");
+        path.into()
+    });
+    
+    if let Some(cwd) = args.cwd.as_ref().filter(|cwd| local && !cwd.is_dir()) {
+        status!("!! Working directory `{}` is not a directory", cwd.display());
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    let mut env = Vec::new();
+    for path in &args.env_files {
+        match read_env_file(path) {
+            Ok(vars) => env.extend(vars),
+            Err(err) => {
+                status!("!! Failed to read env file `{}`: {err}", path.display());
+                std::process::exit(EXIT_USAGE);
+            },
+        }
+    }
+    args.env.splice(0..0, env);
+    
+    let script = args.emit_script.as_ref().map(|_| script::Script::new(args.cwd.clone(), args.env.clone()));
+    if let Some(shell) = args.windows_shell.filter(|_| cfg!(windows)) {
+        args.shell = match shell {
+            WindowsShell::Cmd => Some(exec::Shell::Cmd),
+            WindowsShell::Powershell => Some(exec::Shell::Powershell),
+            WindowsShell::None => None,
+        };
+    }
+    let (shell, cwd, env, remote, clean_env) = (args.shell, args.cwd, args.env, args.remote, args.clean_env);
+    let (container, engine, mounts) = (args.container, args.container_engine, args.mounts);
+    let build = Arc::new(move |command: &str, overrides: &Overrides| -> io::Result<Command> {
+        let cwd = overrides.cwd.as_deref().or(cwd.as_deref());
+        let env: Vec<(String, String)> = env.iter().chain(&overrides.env).cloned().collect();
+        
+        if let Some(image) = &container {
+            return exec::container(engine, image, &mounts, command, shell, cwd, &env);
+        }
+        
+        if !remote.is_empty() {
+            // Every worker sticks to one host, so that the hosts share the load evenly.
+            let host = &remote[rayon::current_thread_index().unwrap_or(0) % remote.len()];
+            return exec::remote(host, command, shell, cwd, &env, clean_env);
+        }
+        
+        let mut child = exec::local(command, shell)?;
+        if let Some(cwd) = cwd {
+            child.current_dir(cwd);
+        }
+        if clean_env {
+            child.env_clear();
+            for key in ["PATH"].into_iter().chain(cfg!(windows).then_some("SystemRoot")) {
+                if let Some(value) = std::env::var_os(key) {
+                    child.env(key, value);
+                }
+            }
+        }
+        child.envs(env);
+        Ok(child)
+    });
+    
+    let threads = match args.threads {
+        0 => std::thread::available_parallelism().map_or(1, usize::from),
+        threads => threads,
+    };
+    let max_threads = args.max_threads.unwrap_or(threads).max(threads);
+    let control = Arc::new(control::Control::new(threads, max_threads, args.group_limits.iter().cloned().collect()));
+    
+    let listener = args.metrics_listen.map(|addr| std::net::TcpListener::bind(addr).unwrap_or_else(|err| {
+        status!("!! Failed to listen for metrics on `{addr}`: {err}");
+        std::process::exit(EXIT_USAGE);
+    }));
+    let metrics = (args.metrics_file.is_some() || listener.is_some())
+        .then(|| metrics::Metrics::start(control.clone(), args.metrics_file.clone(), listener));
+    
+    if let Some(path) = args.stdin_from.as_ref().filter(|path| !path.is_file()) {
+        status!("!! Stdin file `{}` is not a file", path.display());
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    if args.incremental && each.is_none() && args.name_pattern.is_none() {
+        status!("!! `--incremental` requires `--each` or `--name-pattern`, to tell the input file of each command");
+        std::process::exit(EXIT_USAGE);
+    }
+    if args.incremental && (matches!(args.format, Format::TarGz | Format::TarZst | Format::TarZstSeekable | Format::SevenZip) || args.password.is_some() || args.password_file.is_some()) {
+        status!("!! `--incremental` requires an archive that can be appended to: an unencrypted zip archive, a tar or a directory");
+        std::process::exit(EXIT_USAGE);
+    }
+    if args.name_hash.is_some() && args.on_collision == Collision::Error {
+        args.on_collision = Collision::Skip;
+    }
+    if args.replace_existing {
+        args.append = args.output.exists();
+        args.on_collision = Collision::Overwrite;
+        if matches!(args.format, Format::Tar | Format::TarGz | Format::TarZst | Format::TarZstSeekable | Format::SevenZip) {
+            status!("!! Tarballs and 7z archives can't drop replaced entries; extractors pick the last of each name instead.");
+        }
+    }
+    if args.incremental {
+        args.append = args.output.exists();
+        if args.on_collision == Collision::Error {
+            args.on_collision = Collision::Overwrite;
+        }
+    }
+    
+    // The input file of a command, for `--incremental`.
+    let input_pattern = args.name_pattern.clone().filter(|_| each.is_none());
+    let input_of = move |c: &str| -> Option<PathBuf> {
+        match &input_pattern {
+            Some(r) => r.find(c).map(|m| m.as_str().into()),
+            None => Some(c.into()),
+        }
+    };
+    
+    let strategy = match (args.name_pattern.clone(), args.name_replace, args.name_template) {
+        (pattern, None, Some(template)) => {
+            verbose!(0, "-- Using template-based name generator.");
+            Strategy::Template { pattern, template, whole: each.is_some() || args.input_archive.is_some() }
+        },
+        (Some(r), None, _) => {
+            verbose!(0, "-- Using regex-based name generator without replacement: {}", r.as_str());
+            Strategy::Find(r)
+        },
+        (Some(r), Some(p), _) => {
+            verbose!(0, "-- Using regex-based name generator with replacement expansion: {} / {}", r.as_str(), p.as_str());
+            Strategy::Replace(r, p)
+        },
+        (None, Some(_), _) => panic!("cannot specify replacement without regex"),
+        (None, None, _) if args.name_hash.is_some() => {
+            verbose!(0, "-- Using content-hash name generator.");
+            Strategy::Fixed(HASH_PLACEHOLDER.to_string())
+        },
+        (None, None, _) if args.name_from_output.is_some() => {
+            verbose!(0, "-- Using output-based name generator.");
+            Strategy::Fixed(OUTPUT_PLACEHOLDER.to_string())
+        },
+        (None, None, _) if args.input_archive.is_some() => {
+            verbose!(0, "-- Using entry name generator.");
+            Strategy::Whole
+        },
+        (None, None, _) if each.is_some() => {
+            verbose!(0, "-- Using basename name generator.");
+            Strategy::Stem
+        },
+        (None, None, _) => {
+            verbose!(0, "-- Using numeric name generator.");
+            Strategy::Numbered
+        },
+    };
+    let naming = names::Generator::new(strategy, args.start_index, args.index_width)
+        .affixes(args.name_prefix.unwrap_or_default(), args.name_postfix.unwrap_or_default());
+    
+    let summary = summary::Summary::new();
+    
+    // With `--input-archive`, its entries take the place of the commands, and the one command given is the template.
+    let template = args.input_archive.as_ref().map(|_| match <[String; 1]>::try_from(std::mem::take(&mut args.commands)) {
+        Ok([template]) => template,
+        Err(_) => {
+            status!("!! `--input-archive` requires exactly one command, to run for each entry");
+            std::process::exit(EXIT_USAGE);
+        },
+    });
+    let mut entries = Vec::new();
+    let input_archive = args.input_archive.as_ref().map(|path| {
+        let mut archive = File::open(path).map_err(zip::result::ZipError::from).and_then(zip::ZipArchive::new).unwrap_or_else(|err| {
+            status!("!! Failed to open input archive `{}`: {err}", path.display());
+            std::process::exit(EXIT_USAGE);
+        });
+        // In the order of the archive, rather than that of `file_names`.
+        for i in 0..archive.len() {
+            let name = archive.by_index_raw(i).map(|e| e.name().to_string()).unwrap_or_default();
+            if !name.is_empty() && !name.ends_with('/') {
+                entries.push(name);
+            }
+        }
+        Arc::new(Mutex::new(archive))
+    });
+    
+    let mut commands: Box<dyn Iterator<Item = (Origin, String, Overrides)> + '_> = Box::new(open_inputs(&args.input, args.null, args.input_format).chain(
+        args.commands.into_iter().enumerate().map(|(i, c)| (Origin::new("<args>", i), c, Overrides::default()))
+    ));
+    if let Some(path) = &args.input_archive {
+        let source = path.to_string_lossy().into_owned();
+        commands = Box::new(entries.into_iter().enumerate().map(move |(i, n)| (Origin::new(&source, i), n, Overrides::default())));
+    }
+    
+    if !args.args_lists.is_empty() {
+        let lists: Vec<(&String, Vec<String>)> = args.args_lists.iter().map(|(name, path)| {
+            let content = std::fs::read_to_string(path).unwrap_or_else(|err| {
+                status!("!! Failed to read list `{}`: {err}", path.display());
+                std::process::exit(EXIT_USAGE);
+            });
+            (name, content.lines().filter(|l| !l.trim().is_empty()).map(String::from).collect())
+        }).collect();
+        
+        // Every combination of the values, with the first list varying slowest.
+        let combinations: Vec<Vec<(String, String)>> = lists.iter().fold(vec![Vec::new()], |combinations, (name, values)| {
+            combinations.iter()
+                .flat_map(|combination| values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.to_string(), value.clone()));
+                    combination
+                }))
+                .collect()
+        });
+        verbose!(0, "-- Running every command for {} combinations of values", combinations.len());
+        
+        let combinations = Arc::new(combinations);
+        commands = Box::new(commands.flat_map(move |(origin, command, overrides)| {
+            // Comments and stage separators only once.
+            let once = command.starts_with('#') || command.trim() == STAGE_SEPARATOR;
+            let combinations = combinations.clone();
+            (0..if once { 1 } else { combinations.len() }).map(move |i| {
+                let mut overrides = overrides.clone();
+                if !once {
+                    overrides.args = combinations[i].clone();
+                }
+                (origin.clone(), command.clone(), overrides)
+            })
+        }));
+    }
+    
+    if !args.watch_only.is_empty() {
+        let only: HashSet<String> = std::mem::take(&mut args.watch_only).into_iter().collect();
+        commands = Box::new(commands.filter(move |(_, c, _)| only.contains(c)));
+        (args.sample, args.skip, args.limit) = (None, 0, None);
+    }
+    
+    if let Some(n) = args.sample {
+        let seed = args.seed.unwrap_or_else(sample::random_seed);
+        let (sampled, total) = sample::sample(commands.filter(|(_, c, _)| !c.starts_with('#') && c.trim() != STAGE_SEPARATOR), n, seed);
+        status!("-- Sampled {} of {total} commands (seed {seed})", sampled.len());
+        commands = Box::new(sampled.into_iter());
+    }
+    
+    // With `--batch-size`, the paths are grouped into batches, one per line of a single command;
+    // filtered beforehand, so that the batches come out full.
+    if let (Some(size), Some(template)) = (args.batch_size, each.clone()) {
+        let (filter, exclude, filter_on, path_style) = (args.filter.clone(), args.exclude.clone(), args.filter_on, args.path_style);
+        let (prefix, postfix, summary) = (prefix.clone(), postfix.clone(), &summary);
+        let single = |item: &(Origin, String, Overrides)| item.1.starts_with('#') || item.1.trim() == STAGE_SEPARATOR;
+        let kept = commands.filter(move |item| {
+            let full = format!("{prefix}{}{postfix}", each::expand(&template, &path_style.apply(item.1.clone())));
+            let filtered = match filter_on {
+                FilterOn::Input => &item.1,
+                FilterOn::Full => &full,
+            };
+            let excluded = filter.as_ref().is_some_and(|r| !r.is_match(filtered)) || exclude.as_ref().is_some_and(|r| r.is_match(filtered));
+            if excluded && !single(item) {
+                summary.skipped();
+            }
+            single(item) || !excluded
+        });
+        commands = Box::new(each::batches(kept, size, single, |batch| {
+            let mut items = batch.into_iter();
+            let (origin, first, overrides) = items.next().expect("batch is empty");
+            (origin, items.fold(first, |command, (_, path, _)| command + "\n" + &path), overrides)
+        }));
+    }
+    
+    let failed_out = args.failed_out.map(|path| {
+        let file = File::create(&path).unwrap_or_else(|err| {
+            status!("!! Failed to create failed-commands file `{}`: {err}", path.display());
+            std::process::exit(EXIT_USAGE);
+        });
+        Arc::new(Mutex::new(file))
+    });
+    
+    let password = args.password_file.map(|path| {
+        let content = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+            status!("!! Failed to read password file `{}`: {err}", path.display());
+            std::process::exit(EXIT_USAGE);
+        });
+        content.lines().next().unwrap_or_default().to_string()
+    }).or(args.password);
+    
+    let mtime = args.deterministic.then(|| {
+        let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().map(|epoch| epoch.trim().parse::<u64>().unwrap_or_else(|err| {
+            status!("!! Invalid SOURCE_DATE_EPOCH `{epoch}`: {err}");
+            std::process::exit(EXIT_USAGE);
+        }));
+        // The earliest time zip archives can represent.
+        SystemTime::UNIX_EPOCH + Duration::from_secs(epoch.unwrap_or(315532800))
+    });
+    
+    if args.deterministic && password.is_some() {
+        status!("!! Encrypted entries are salted randomly, so the archive won't be byte-identical across runs");
+    }
+    
+    let options = archive::Options {
+        compression: args.compression,
+        level: args.level,
+        password,
+        mtime,
+        zip64: args.zip64,
+        align: args.align,
+        modes: Modes { file: args.file_mode, exec: args.exec_modes.clone() },
+        compact: args.replace_existing,
+    };
+    
+    // Commands recorded in the state file by the interrupted run, keyed by the name of their entry.
+    let mut done: Vec<(String, String)> = Vec::new();
+    
+    if args.resume {
+        if let Some(content) = args.state.as_ref().and_then(|path| std::fs::read_to_string(path).ok()) {
+            // Lines cut off by the interruption don't parse, and their commands are run again.
+            done = content.lines()
+                .filter_map(|line| json::parse(line).ok())
+                .filter_map(|entry| Some((entry.get("name")?.as_str()?.to_string(), entry.get("command")?.as_str()?.to_string())))
+                .collect();
+        }
+        
+        // The interrupted run may not have gotten to move its archive into place.
+        let part = archive::part_path(&args.output);
+        if part.is_file() {
+            verbose!(0, "-- Picking up the unfinished archive `{}`", part.display());
+            if let Err(err) = std::fs::rename(&part, &args.output) {
+                status!("!! Failed to move archive `{}` into place: {err}", part.display());
+                std::process::exit(EXIT_USAGE);
+            }
+        }
+        
+        if args.output.exists() {
+            args.append = true;
+            match archive::recover(args.format, &args.output) {
+                Ok(None) => {},
+                Ok(Some(count)) => status!("!! Archive `{}` was not finished; recovered {count} entries", args.output.display()),
+                Err(err) => {
+                    status!("!! Failed to recover archive `{}`: {err}", args.output.display());
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+        }
+    }
+    
+    let state = args.state.as_ref().map(|path| {
+        let file = match args.resume {
+            true => File::options().create(true).append(true).open(path),
+            false => File::create(path),
+        };
+        file.unwrap_or_else(|err| {
+            status!("!! Failed to open state file `{}`: {err}", path.display());
+            std::process::exit(EXIT_USAGE);
+        })
+    });
+    
+    if args.verify.is_none() {
+        archive::ignored(args.format, &options).into_iter().for_each(tell);
+    }
+    
+    let mut upload = None;
+    let archive: Box<dyn archive::ArchiveWriter> = if let Some(path) = &args.verify {
+        verbose!(0, "-- Verifying against `{}`", path.display());
+        Box::new(verify::Verifier::open(path).unwrap_or_else(|err| {
+            status!("!! Failed to open archive `{}`: {err}", path.display());
+            std::process::exit(EXIT_USAGE);
+        }))
+    } else if let Some(url) = &upload_url {
+        verbose!(0, "-- Uploading archive to `{url}`");
+        upload::Upload::start(url).and_then(|(started, stdin)| {
+            upload = Some(started);
+            archive::open_stream(args.format, stdin, &options)
+        }).unwrap_or_else(|err| {
+            status!("!! Failed to upload archive to `{url}`: {err}");
+            std::process::exit(EXIT_USAGE);
+        })
+    } else if args.split_size.is_some() || args.shard_by.is_some() {
+        archive::open_split(args.format, &args.output, &options, args.split_size, args.shard_by, Arc::new(tell))
+    } else {
+        archive::open(args.format, &args.output, args.append, &options).unwrap_or_else(|err| {
+            status!("!! Failed to open archive `{}`: {err}", args.output.display());
+            std::process::exit(EXIT_USAGE);
+        })
+    };
+    
+    let existing: HashSet<String> = match args.skip_existing {
+        true => archive.existing_names().iter().cloned().collect(),
+        false => HashSet::new(),
+    };
+    
+    // When the entries already in the archive were modified, for `--incremental`.
+    let modified: HashMap<String, SystemTime> = match args.incremental {
+        true => archive.existing_names().iter().filter_map(|n| Some((n.clone(), archive.modified(n)?))).collect(),
+        false => HashMap::new(),
+    };
+    
+    // Only entries that made it into the archive count as done.
+    let done: HashSet<String> = {
+        let names: HashSet<&String> = archive.existing_names().iter().collect();
+        done.into_iter().filter(|(n, _)| names.contains(n)).map(|(_, c)| c).collect()
+    };
+    
+    if args.format != Format::Zip && (args.auto_store || !args.compress_rules.is_empty()) {
+        status!("!! Compression rules only apply to zip archives; ignoring them.");
+    }
+    if args.align.is_some() && args.format != Format::Zip {
+        status!("!! Alignment only applies to zip archives; ignoring it.");
+    } else if args.align.is_some() && args.compression != Some(Compression::Stored) && !args.auto_store && args.compress_rules.iter().all(|r| r.compression != Compression::Stored) {
+        status!("!! Only stored entries are aligned, but none will be stored; see `--compression stored`.");
+    }
+    if args.format != Format::Zip && !["{info}", ""].contains(&args.archive_comment.as_str()) {
+        status!("!! Archive comments only apply to zip archives; ignoring it.");
+    }
+    let comment = Some(&args.archive_comment).filter(|c| args.format == Format::Zip && !c.is_empty()).map(|comment| {
+        let mut info = format!("Written by cmd2zip {}\nInvocation: {invocation}", env!("CARGO_PKG_VERSION"));
+        if !args.deterministic {
+            info += &format!("\nHost: {}", hostname().unwrap_or_else(|| "unknown".to_string()));
+            info += &format!("\nStarted: {}\nFinished: {{finished}}", json::timestamp(started));
+        }
+        comment.replace("{info}", &info)
+    });
+    
+    let config = writer::Config {
+        manifest: args.manifest,
+        on_collision: args.on_collision,
+        dir_entries: args.dir_entries,
+        state,
+        ordered: args.ordered || args.deterministic || args.merge_into.is_some(),
+        reproducible: args.deterministic,
+        checksums: args.checksums,
+        dedup: args.dedup,
+        comment_commands: args.comment_commands,
+        comment,
+        auto_store: args.auto_store,
+        compress_rules: args.compress_rules.clone(),
+        merge: args.merge_into.clone(),
+        merge_separator: args.merge_separator.clone().into_bytes(),
+    };
+    
+    let any_failed = AtomicBool::new(false);
+    
+    let tui = match args.tui {
+        true => tui::Tui::start(control.clone(), limits.clone())
+            .or_else(|| {
+                status!("!! `--tui` needs a terminal on Unix; showing the progress bar instead");
+                None
+            }),
+        false => None,
+    };
+    let drawer = if args.no_progress || tui.is_some() { None } else { progress::start() };
+    
+    // The paths of the commands that were run, for `--watch`.
+    let mut watched = Vec::new();
+    
+    // The entry names and commands of `--dry-run=list`, along with their index and origin.
+    let listed = Mutex::new(Vec::new());
+    
+    // Dumps the status on `SIGUSR1`, and follows the control file.
+    let watcher = control::start(control.clone(), args.control_file.clone(), limits.clone());
+    
+    let pipeline = Pipeline {
+        shell: args.shell,
+        stages: args.then.clone(),
+        filter: args.pipe.clone(),
+        retries: args.retries,
+        retry_delay: args.retry_delay,
+        encoding: args.encoding,
+        normalize_newlines: args.normalize_newlines,
+        err_format: args.err_format,
+        on_empty_stdout: args.on_empty_stdout,
+        capture_stderr: args.capture_stderr,
+        failed_dir: args.failed_dir.clone(),
+        drop_failed: args.drop_failed,
+        bench: args.bench,
+        bench_keep: args.bench_keep,
+        spill_dir: Some(spill_dir),
+        reproducible: args.deterministic,
+        dry_run: args.dry.is_some(),
+        capture_dir: args.capture_dir,
+        stdin_replace: args.name_pattern.clone().zip(args.stdin_replace.clone()),
+        input_archive,
+        failed_out,
+        fail_fast: args.fail_fast,
+        watch: args.watch,
+    };
+    let mut runner = Runner::new(archive)
+        .threads(max_threads)
+        .config(config)
+        .limits(limits.clone())
+        .pipeline(pipeline)
+        .on_notice(tell)
+        .pace(move || throttle.wait());
+    if args.tee {
+        runner = runner.tee(|name, line, stderr| {
+            let prefix = match stderr {
+                true => events::paint(Color::Yellow, format_args!("[{name}]"), true),
+                false => format!("[{name}]"),
+            };
+            progress::print_line(&format!("{prefix} {line}"), true);
+        });
+    }
+    
+    // Blocks until all workers are done; a panicking worker is re-raised once the archive is finished.
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| runner.scope(|scope| {
+        // Shared with all the workers, for the duration of the scope.
+        let (prefix, postfix, each, name_from_output) = (&prefix, &postfix, &each, &args.name_from_output);
+        let (build, input_of, template) = (&build, &input_of, &template);
+        let (existing, done, modified, summary, script) = (&existing, &done, &modified, &summary, &script);
+        let (control, listed) = (&*control, &listed);
+        
+        // The commands held back until their group has room.
+        let mut pending = control::Pending::new(control);
+        
+        for (index, (origin, command, overrides)) in commands.enumerate() {
+            if args.fail_fast && scope.failed() {
+                status!("!! A command failed; not running any further commands");
+                break;
+            }
+            if exec::stopping() {
+                break;
+            }
+            pending.start_ready();
+            
+            // Ignore commands starting with a hashtag
+            if command.starts_with('#') {
+                status!("## {}", command.strip_prefix('#').unwrap_or_default());
+                continue;
+            }
+            
+            if command.trim() == STAGE_SEPARATOR {
+                verbose!(0, "-- Waiting for the commands of this stage to finish ({origin})");
+                pending.drain();
+                scope.wait();
+                continue;
+            }
+            
+            let original = command.clone();
+            
+            let command = args.path_style.apply(command);
+            
+            let full_command = match (each, template) {
+                (Some(template), _) if args.batch_size.is_some() => format!("{prefix}{}{postfix}", each::expand_batch(template, &command.lines().collect::<Vec<_>>())),
+                (Some(template), _) => format!("{prefix}{}{postfix}", each::expand(template, &command)),
+                (_, Some(template)) => format!("{prefix}{}{postfix}", template.replace("{name}", &shlex::try_quote(&original).unwrap_or_default())),
+                (None, None) => format!("{prefix}{command}{postfix}"),
+            };
+            let full_command = overrides.args.iter().fold(full_command, |full_command, (name, value)| {
+                full_command.replace(&format!("{{{name}}}"), &shlex::try_quote(value).unwrap_or_default())
+            });
+            
+            let filtered = match args.filter_on {
+                FilterOn::Input => &original,
+                FilterOn::Full => &full_command,
+            };
+            // Batches were filtered path by path already.
+            let excluded = args.filter.as_ref().is_some_and(|r| !r.is_match(filtered)) || args.exclude.as_ref().is_some_and(|r| r.is_match(filtered));
+            if excluded && args.batch_size.is_none() {
+                summary.skipped();
+                continue;
+            }
+            
+            if args.skip > 0 {
+                args.skip -= 1;
+                summary.skipped();
+                continue;
+            }
+            
+            if let Some(limit) = &mut args.limit {
+                if *limit == 0 {
+                    status!("!! Reached command limit");
+                    break;
+                }
+                *limit -= 1;
+            }
+            
+            // Generate file-name! Done here rather than by the workers, so that numbering follows the input order.
+            let generated = match &overrides.name {
+                Some(name) => Some(name.clone()),
+                None if args.batch_size.is_some() => naming.name(command.lines().next().unwrap_or_default()),
+                None => naming.name(&command),
+            };
+            let Some(generated) = generated else {
+                status!("{}", events::paint(Color::Red, format_args!("!! Not running command, as it doesn't match `--name-pattern` ({origin}): {full_command}"), true));
+                scope.fail(&original, &tell);
+                summary.failed();
+                continue;
+            };
+            let generated = match overrides.args.iter().any(|(name, _)| generated.contains(&format!("{{{name}}}"))) {
+                true => overrides.args.iter().fold(generated, |generated, (name, value)| generated.replace(&format!("{{{name}}}"), value)),
+                false => overrides.args.iter().rev().fold(generated, |generated, (_, value)| format!("{value}/{generated}")),
+            };
+            
+            let build = build.clone();
+            let run_overrides = overrides.clone();
+            let mut job = Job::from_line(full_command.as_str(), move |line, env| {
+                let mut overrides = run_overrides.clone();
+                overrides.env.extend_from_slice(env);
+                build(line, &overrides)
+            }).input(original.as_str());
+            job.stdin = args.stdin_from.clone();
+            
+            progress::queued();
+            let mut slot = scope.reserve(&job);
+            let group = overrides.group.clone()
+                .or_else(|| args.groups.iter().find(|(_, r)| r.is_match(&full_command)).map(|(group, _)| group.clone()));
+            pending.start(group, move |group| scope.spawn(move |scope| {
+                let _group = group;
+                
+                // Commands that were queued before a failure or Ctrl-C are skipped as well.
+                if (args.fail_fast && scope.failed()) || exec::stopping() {
+                    summary.skipped();
+                    return;
+                }
+                
+                progress::started();
+                
+                // What the library tells about each command, it tells along with where the command came from.
+                let context = |notice: Notice| tell(notice.map(|message| format!("{message} ({origin}): {full_command}")));
+                
+                let mut name = match names::sanitize(&generated, args.allow_absolute_paths) {
+                    Ok(name) => name,
+                    Err(err) => {
+                        status!("{}", events::paint(Color::Red, format_args!("!! Not running command, as its {err} ({origin}): {full_command}"), true));
+                        scope.fail(&original, &context);
+                        summary.failed();
+                        progress::finished(true);
+                        return;
+                    }
+                };
+                
+                if args.flatten {
+                    name = name.rsplit('/').next().unwrap_or_default().to_string();
+                }
+                
+                // Entries of inputs that weren't modified since are up to date.
+                let up_to_date = modified.get(&name).is_some_and(|entry| {
+                    let input = input_of(&command).and_then(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok());
+                    input.is_some_and(|input| input <= *entry)
+                });
+                
+                if existing.contains(&name) || done.contains(&full_command) || up_to_date {
+                    match up_to_date {
+                        true => verbose!(0, "-- Skipping command, as `{name}` is up to date ({origin}): {full_command}"),
+                        false => verbose!(0, "-- Skipping command, as `{name}` already exists ({origin}): {full_command}"),
+                    }
+                    events::emit("skipped", json::Value::object([
+                        ("name", json::Value::from(name.as_str())),
+                        ("command", full_command.as_str().into()),
+                    ]));
+                    summary.skipped();
+                    progress::finished(false);
+                    return;
+                }
+                
+                if let Some(cwd) = overrides.cwd.as_ref().filter(|cwd| local && !cwd.is_dir()) {
+                    status!("{}", events::paint(Color::Red, format_args!("!! Not running command, as its working directory `{}` is not a directory ({origin}): {full_command}", cwd.display()), true));
+                    scope.fail(&original, &context);
+                    summary.failed();
+                    progress::finished(true);
+                    return;
+                }
+                
+                if args.dry == Some(DryRun::List) {
+                    listed.lock().expect("failed to lock mutex").push((index, origin.to_string(), name, full_command.clone()));
+                    progress::finished(false);
+                    return;
+                }
+                
+                // Waits while `--control-file` lowered the parallelism below what's running.
+                let gate = args.dry.is_none().then(|| control.start(index, &name, &full_command));
+                
+                events::emit("started", json::Value::object([
+                    ("command", json::Value::from(full_command.as_str())),
+                    ("origin", origin.to_string().into()),
+                ]));
+                
+                job.name = Some(name);
+                job.captured = gate.as_ref().map(control::Slot::captured);
+                
+                // Note: This blocks until the child finishes, ON PURPOSE.
+                let mut output = scope.run(job, &context);
+                
+                let (success, timed_out) = (output.success, output.record.timed_out);
+                if output.record.oversize.is_some() {
+                    summary.oversize();
+                }
+                
+                if let Some(hash) = args.name_hash {
+                    let digest = hash.digest(&mut output.stdout.reader().expect("failed to read output")).expect("failed to hash output");
+                    output.name = output.name.replace(HASH_PLACEHOLDER, &digest);
+                }
+                
+                if let Some(from) = name_from_output {
+                    let printed = [output.stdout.head(), output.stderr.head()].into_iter()
+                        .find_map(|output| from.find(&String::from_utf8_lossy(output)))
+                        .and_then(|printed| names::sanitize(&output.name.replace(OUTPUT_PLACEHOLDER, &printed), args.allow_absolute_paths).ok());
+                    output.name = printed.unwrap_or_else(|| {
+                        status!("{}", events::paint(Color::Yellow, format_args!("!! Command printed no name, numbering it instead ({origin}): {full_command}"), true));
+                        output.name.replace(OUTPUT_PLACEHOLDER, &format!("{:0width$}", args.start_index + index, width = args.index_width))
+                    });
+                }
+                
+                if timed_out {
+                    status!("{}", events::paint(Color::Red, format_args!("!! Command timed out ({origin}): {full_command}"), true));
+                } else if !success {
+                    let head = if output.stdout.is_empty() { output.stderr.head() } else { output.stdout.head() };
+                    status!("{}\n{}", events::paint(Color::Red, format_args!("!! Command failed ({origin}): {full_command}"), true), String::from_utf8_lossy(head));
+                }
+                
+                let captured = match output.files.is_empty() {
+                    true => output.stdout.len(),
+                    false => output.files.iter().map(|(_, content)| content.len()).sum(),
+                };
+                let runs = std::mem::take(&mut output.runs);
+                let Completion { name, bytes, kept, record, .. } = scope.write(output, &mut slot, &context);
+                
+                if !success {
+                    control.failed(&name, &full_command);
+                }
+                
+                // With events enabled, this is reported along with the record instead; with `--quiet`, not at all.
+                if !events::enabled() && !args.quiet {
+                    let (using, attempts) = (record.stream, record.attempts);
+                    let color = match (success, using) {
+                        (false, _) => Color::Red,
+                        (true, "stderr") => Color::Yellow,
+                        (true, _) => Color::Green,
+                    };
+                    if attempts > 1 {
+                        events::print_colored(color, &format!("`{name}` << {captured} bytes from {using} << `{full_command}` (after {attempts} attempts)"));
+                    } else {
+                        events::print_colored(color, &format!("`{name}` << {captured} bytes from {using} << `{full_command}`"));
+                    }
+                }
+                
+                let event = if success { "finished" } else { "failed" };
+                events::emit(event, record.to_json(&name, bytes));
+                progress::finished(!success);
+                
+                summary.finished(&name, &record, bytes, success);
+                if args.bench.is_some() && !runs.is_empty() && success {
+                    summary.benched(&name, &record.command, runs);
+                }
+                if let Some(script) = script.as_ref().filter(|_| kept) {
+                    script.add(index, &name, &record.command, &overrides, record.stream == "stderr");
+                }
+            }));
+        }
+        pending.drain();
+        
+        progress::input_done();
+        verbose!(0, "-- Waiting for all children to finish...");
+        scope.wait();
+        
+        if scope.failed() {
+            any_failed.store(true, Ordering::Relaxed);
+        }
+        watched = scope.inputs();
+        
+        if let (Some(name), Some(script)) = (&args.emit_script, script) {
+            let record = Record::synthetic("script");
+            scope.slot().push(name.clone(), script.finish().into_bytes().into(), record);
+        }
+        
+        if args.summary_entry {
+            let report = summary.report(args.slowest).join("\n") + "\n";
+            let record = Record::synthetic("summary");
+            scope.slot().push(summary::ENTRY_NAME.to_string(), report.into_bytes().into(), record);
+        }
+        
+        if args.dry == Some(DryRun::List) {
+            let mut listed = std::mem::take(&mut *listed.lock().expect("failed to lock mutex"));
+            listed.sort_by_key(|(index, ..)| *index);
+            
+            let mut first = HashMap::new();
+            let mut list = String::new();
+            for (_, origin, name, command) in &listed {
+                if let Some(other) = first.insert(name.as_str(), origin.as_str()) {
+                    status!("{}", events::paint(Color::Red, format_args!("!! Entry name `{name}` of ({origin}) is also that of ({other}): {command}"), true));
+                    any_failed.store(true, Ordering::Relaxed);
+                }
+                list += &format!("{name}\t{command}\n");
+            }
+            verbose!(0, "-- Listed {} commands, with {} distinct entry names", listed.len(), first.len());
+            
+            let record = Record::synthetic("commands");
+            scope.slot().push(DRY_RUN_LIST_NAME.to_string(), list.into_bytes().into(), record);
+        }
+        
+        if args.timings {
+            let record = Record::synthetic("timings");
+            scope.slot().push(summary::TIMINGS_ENTRY_NAME.to_string(), summary.timings_csv().into_bytes().into(), record);
+        }
+        
+        if let Some(runs) = args.bench {
+            let stats = summary.bench_json(runs).to_string();
+            match &args.bench_out {
+                Some(path) if path == Path::new("-") => println!("{stats}"),
+                Some(path) => if let Err(err) = std::fs::write(path, stats + "\n") {
+                    status!("!! Failed to write benchmark statistics `{}`: {err}", path.display());
+                },
+                None => {
+                    let record = Record::synthetic("bench");
+                    scope.slot().push(summary::BENCH_ENTRY_NAME.to_string(), stats.into_bytes().into(), record);
+                },
+            }
+        }
+    })));
+    
+    control::stop(&control, watcher);
+    progress::stop(drawer);
+    if let Some(tui) = tui {
+        tui.stop();
+    }
+    if let Some(metrics) = metrics {
+        metrics.stop();
+    }
+    
+    // The archive is finished even if a worker panicked, so that everything else is kept.
+    let panicked = match result {
+        Ok(Ok(())) => None,
+        // The archive holds everything else, so it's kept all the same; only the run fails.
+        Ok(Err(err)) if err.get_ref().is_some_and(|err| err.is::<writer::Collided>()) => {
+            status!("!! Left out outputs, as {err}; see `--on-collision`");
+            any_failed.store(true, Ordering::Relaxed);
+            None
+        },
+        Ok(Err(err)) => {
+            status!("!! Failed to write archive `{}`: {err}", args.output.display());
+            std::process::exit(EXIT_FAILED);
+        },
+        Err(panic) => Some(panic),
+    };
+    
+    if let Some(Err(err)) = upload.map(upload::Upload::finish) {
+        status!("!! Failed to upload archive to `{}`: {err}", args.output.display());
+        std::process::exit(EXIT_FAILED);
+    }
+    
+    if let Some(panic) = panicked {
+        status!("!! A worker panicked; its command is missing from the archive.");
+        std::panic::resume_unwind(panic);
+    }
+    
+    for line in summary.report(args.slowest) {
+        verbose!(0, "-- {line}");
+    }
+    events::emit("summary", summary.to_json(args.slowest));
+    verbose!(0, "-- Done!");
+    
+    if args.watch && !exec::stopping() {
+        watch::watch(&argv, watched, args.watch_interval);
+    }
+    
+    if UNREADABLE_INPUT.load(Ordering::Relaxed) {
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    if any_failed.load(Ordering::Relaxed) || verify::differed() || exec::stopping() {
+        std::process::exit(EXIT_FAILED);
+    }
+}
+
+/// How `--name-from-output` finds the name in the output of a command.
+#[derive(Debug, Clone)]
+enum NameFrom {
+    FirstLine,
+    
+    /// The first capture group, or else the entire match.
+    Pattern(Regex),
+}
+
+impl NameFrom {
+    fn find(&self, output: &str) -> Option<String> {
+        let found = match self {
+            NameFrom::FirstLine => output.lines().next(),
+            NameFrom::Pattern(r) => r.captures(output).and_then(|c| c.get(1).or(c.get(0))).map(|m| m.as_str()),
+        };
+        found.map(str::trim).filter(|name| !name.is_empty()).map(String::from)
+    }
+}
+
+/// The shells of `--windows-shell`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum WindowsShell {
+    Cmd,
+    Powershell,
+    None,
+}
+
+/// What `--path-style` does to the path separators within commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PathStyle {
+    Keep,
+    Forward,
+    Backward,
+}
+
+impl PathStyle {
+    /// Forward-slashes on Windows, where the wild-crate emits backward-slashes that may break some commands.
+    fn native() -> Self {
+        if cfg!(windows) { PathStyle::Forward } else { PathStyle::Keep }
+    }
+    
+    fn apply(self, command: String) -> String {
+        match self {
+            PathStyle::Keep => command,
+            PathStyle::Forward => command.replace('\\', "/"),
+            PathStyle::Backward => command.replace('/', "\\"),
+        }
+    }
+}
+
+/// What `--filter` and `--exclude` match against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+enum FilterOn {
+    /// The command as read in.
+    #[default]
+    Input,
+    
+    /// The command with prefix, postfix and `--each` applied; as it is run.
+    Full,
+}
+
+/// Where a command came from, for use in diagnostics.
+#[derive(Debug, Clone)]
+struct Origin {
+    source: Arc<str>,
+    line: usize,
+}
+
+impl Origin {
+    fn new(source: &str, index: usize) -> Self {
+        Self { source: source.into(), line: index + 1 }
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source, self.line)
+    }
+}
+
+/// Opens all the given inputs up-front, chaining their lines (or NUL-terminated/structured records) in order.
+/// 
+/// Exits the process if any of them can't be opened, or structured ones can't be parsed, so that no command gets spawned.
+fn open_inputs(inputs: &[PathBuf], null: bool, format: InputFormat) -> Box<dyn Iterator<Item = (Origin, String, Overrides)>> {
+    if inputs.iter().filter(|i| *i == Path::new("-")).count() > 1 {
+        status!("!! Stdin (`-`) can only be used as input once");
+        std::process::exit(EXIT_USAGE);
+    }
+    
+    let mut lines: Box<dyn Iterator<Item = (Origin, String, Overrides)>> = Box::new(std::iter::empty());
+    
+    for input in inputs {
+        let (source, mut reader): (String, Box<dyn BufRead>) = if input == Path::new("-") {
+            ("<stdin>".into(), Box::new(std::io::stdin().lock()))
+        } else {
+            match File::open(input) {
+                Ok(file) => (input.display().to_string(), Box::new(std::io::BufReader::new(file))),
+                Err(err) => {
+                    status!("!! Failed to open input file `{}`: {err}", input.display());
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+        };
+        
+        let source: Arc<str> = source.into();
+        
+        let parse = match format.of(input) {
+            InputFormat::Csv => input::parse_csv,
+            InputFormat::Jsonl => input::parse_jsonl,
+            _ => {
+                lines = Box::new(lines.chain(
+                    records(source.clone(), reader, null)
+                    .enumerate()
+                    .map(move |(i, l)| (Origin { source: source.clone(), line: i + 1 }, l, Overrides::default()))
+                ));
+                continue;
+            },
+        };
+        
+        let mut text = String::new();
+        let records = reader.read_to_string(&mut text).map_err(|err| err.to_string()).and_then(|_| parse(&text));
+        let records = records.unwrap_or_else(|err| {
+            status!("!! Failed to read input file `{source}`: {err}");
+            std::process::exit(EXIT_USAGE);
+        });
+        lines = Box::new(lines.chain(
+            records.into_iter().map(move |r| (Origin { source: source.clone(), line: r.line }, r.command, r.overrides))
+        ));
+    }
+    
+    lines
+}
+
+/// Set once an input couldn't be read to its end, failing the run with [`EXIT_USAGE`] once the rest is done.
+static UNREADABLE_INPUT: AtomicBool = AtomicBool::new(false);
+
+/// The lines (or NUL-terminated records) of an input, decoded lossily, as commands are rarely anything but UTF-8.
+/// 
+/// A read error ends the input there, telling where.
+fn records(source: Arc<str>, reader: Box<dyn BufRead>, null: bool) -> impl Iterator<Item = String> {
+    let terminator = if null { b'\0' } else { b'\n' };
+    reader.split(terminator).enumerate().map_while(move |(i, record)| match record {
+        Ok(mut record) => {
+            if !null && record.ends_with(b"\r") {
+                record.pop();
+            }
+            Some(String::from_utf8_lossy(&record).into_owned())
+        },
+        Err(err) => {
+            status!("!! Failed to read input file `{source}` at line {}: {err}", i + 1);
+            UNREADABLE_INPUT.store(true, Ordering::Relaxed);
+            None
+        },
+    })
+}
+
+
+/// Parses an `--args-list` like `res=sizes.txt`.
+fn parse_args_list(input: &str) -> Result<(String, PathBuf), String> {
+    let Some((name, path)) = input.split_once('=') else {
+        return Err(format!("invalid list `{input}`; expected `NAME=FILE`"));
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid list name `{name}`; expected letters, digits and underscores"));
+    }
+    Ok((name.to_string(), path.into()))
+}
+
+/// Parses a duration like `1.5`, `30s`, `500ms`, `5m` or `1h`; plain numbers are seconds.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let split = input.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number.trim().parse().map_err(|err| format!("invalid duration `{input}`: {err}"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit `{unit}`; expected one of `ms`, `s`, `m` or `h`")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|err| format!("invalid duration `{input}`: {err}"))
+}
+
+/// Parses `first-line` or a regex for `--name-from-output`.
+fn parse_name_from(input: &str) -> Result<NameFrom, String> {
+    match input {
+        "first-line" => Ok(NameFrom::FirstLine),
+        _ => Regex::new(input).map(NameFrom::Pattern).map_err(|err| err.to_string()),
+    }
+}
+
+/// Parses a number of commands per second for `--rate`, which must be above zero.
+fn parse_rate(input: &str) -> Result<f64, String> {
+    match input.trim().parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        Ok(_) => Err(format!("invalid rate `{input}`; must be above zero")),
+        Err(err) => Err(format!("invalid rate `{input}`: {err}")),
+    }
+}
+
+/// Parses a directory of the archive for `--failed-dir`, like an entry name.
+fn parse_failed_dir(input: &str) -> Result<String, String> {
+    names::sanitize(input, false)
+}
+
+/// Parses a separator for `--merge-separator`, unescaping `\n`, `\t`, `\0` and `\\`.
+fn parse_separator(input: &str) -> Result<String, String> {
+    let mut separator = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        separator.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some(other) => return Err(format!("unknown escape `\\{other}`")),
+                None => return Err("dangling `\\` at the end".to_string()),
+            },
+            c => c,
+        });
+    }
+    Ok(separator)
+}
+
+/// Parses a size like `512`, `64K`, `100M` or `1.5G`, in powers of 1024; plain numbers are bytes.
+fn parse_size(input: &str) -> Result<u64, String> {
+    let split = input.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number.trim().parse().map_err(|err| format!("invalid size `{input}`: {err}"))?;
+    let factor = match unit.trim_end_matches(['B', 'b', 'i']).to_ascii_uppercase().as_str() {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{unit}`; expected one of `K`, `M`, `G` or `T`")),
+    };
+    match number * factor as f64 {
+        size if size >= 1.0 => Ok(size as u64),
+        _ => Err(format!("invalid size `{input}`; must be at least one byte")),
+    }
+}
+
+/// Reads the `KEY=VALUE` lines of an `--env-file`.
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut vars = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = parse_env(line).map_err(|err| format!("line {}: {err}", number + 1))?;
+        let value = value.trim();
+        let value = match value.as_bytes() {
+            [b'"', .., b'"'] | [b'\'', .., b'\''] => &value[1..value.len() - 1],
+            _ => value,
+        };
+        vars.push((key.trim_end().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Parses a `NAME=REGEX` pair for `--group`.
+fn parse_group(input: &str) -> Result<(String, Regex), String> {
+    match input.split_once('=') {
+        Some((name, pattern)) if !name.is_empty() => Ok((name.to_string(), Regex::new(pattern).map_err(|err| err.to_string())?)),
+        _ => Err(format!("invalid group `{input}`; expected `NAME=REGEX`")),
+    }
+}
+
+/// Parses a `NAME=N` pair for `--group-limit`, where `N` is at least 1.
+fn parse_group_limit(input: &str) -> Result<(String, usize), String> {
+    match input.split_once('=').map(|(name, limit)| (name, limit.parse::<usize>())) {
+        Some((name, Ok(limit))) if !name.is_empty() && limit > 0 => Ok((name.to_string(), limit)),
+        _ => Err(format!("invalid group limit `{input}`; expected `NAME=N`, with `N` at least 1")),
+    }
+}
+
+/// Parses a `KEY=VALUE` pair for `--env`.
+fn parse_env(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid environment variable `{input}`; expected `KEY=VALUE`")),
+    }
+}
+
+/// Prints what the library tells about, as the binary prints its own.
+fn tell(notice: Notice) {
+    match notice {
+        Notice::Info(level, message) => verbose!(level as i8, "-- {message}"),
+        Notice::Warning(message) => status!("{}", events::paint(Color::Yellow, format_args!("!! {message}"), true)),
+        Notice::Error(message) => status!("{}", events::paint(Color::Red, format_args!("!! {message}"), true)),
+    }
+}
+
+/// The name of this machine, for the archive comment.
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+    let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// The bytes available to unprivileged users on the file system the path is on.
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `statvfs` is plain data, for which all zeroes is valid; it's filled in by the call.
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
 }
 
-fn append_to_archive(archive: &Mutex<ZipWriter<impl Write + Seek>>, file_name: &str, file_content: &[u8]) {
-    let mut a = archive.lock().expect("failed to lock mutex");
-    a.start_file(file_name, FileOptions::default()).expect("failed to start file");
-    a.write_all(file_content).expect("failed to write file");
-    a.flush().expect("failed to flush archive writer");
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
 }
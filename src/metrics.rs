@@ -0,0 +1,139 @@
+//! Exposing the tallies of a run to monitoring, for `--metrics-file` and `--metrics-listen`.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use cmd2zip::{progress, status, verbose};
+
+use crate::control::Control;
+
+/// How often the metrics file is rewritten.
+const WRITE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a scraper may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Metrics {
+    control: Arc<Control>,
+    started: Instant,
+    file: Option<PathBuf>,
+    stopped: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Metrics {
+    /// Starts rewriting the metrics file every few seconds, and serving the metrics to anyone connecting to the listener.
+    pub fn start(control: Arc<Control>, file: Option<PathBuf>, listener: Option<TcpListener>) -> Self {
+        let started = Instant::now();
+        let stopped = Arc::new(AtomicBool::new(false));
+        
+        if let Some(listener) = listener {
+            let control = control.clone();
+            // Never joined; it's simply gone when the process exits.
+            std::thread::Builder::new()
+                .name("metrics".into())
+                .spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        if let Err(err) = serve(stream, &control, started) {
+                            verbose!(1, "-- Failed to serve metrics: {err}");
+                        }
+                    }
+                })
+                .expect("failed to spawn metrics listener");
+        }
+        
+        let writer = file.clone().map(|path| {
+            let (control, stopped) = (control.clone(), stopped.clone());
+            std::thread::Builder::new()
+                .name("metrics-file".into())
+                .spawn(move || {
+                    let mut written = None::<Instant>;
+                    while !stopped.load(Ordering::Relaxed) {
+                        if written.is_none_or(|written| written.elapsed() >= WRITE_INTERVAL) {
+                            write(&path, &render(&control, started));
+                            written = Some(Instant::now());
+                        }
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                })
+                .expect("failed to spawn metrics writer")
+        });
+        
+        Self { control, started, file, stopped, writer }
+    }
+    
+    /// Stops rewriting the metrics file, writing it one last time with the final tallies.
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(writer) = self.writer {
+            writer.join().ok();
+        }
+        if let Some(path) = &self.file {
+            write(path, &render(&self.control, self.started));
+        }
+    }
+}
+
+/// The metrics in the OpenMetrics text format.
+fn render(control: &Control, started: Instant) -> String {
+    let counts = progress::counts();
+    let (running, limit) = control.running();
+    
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+        let sample = match kind {
+            "counter" => format!("{name}_total"),
+            _ => name.to_string(),
+        };
+        writeln!(text, "# TYPE {name} {kind}\n# HELP {name} {help}\n{sample} {value}").ok();
+    };
+    metric("cmd2zip_commands_queued", "counter", "Commands queued to run.", &counts.queued);
+    metric("cmd2zip_commands_completed", "counter", "Commands that finished, whether they succeeded or not.", &counts.done);
+    metric("cmd2zip_commands_failed", "counter", "Commands that failed.", &counts.failed);
+    metric("cmd2zip_written_bytes", "counter", "Bytes of output written to the archive.", &counts.bytes);
+    metric("cmd2zip_commands_running", "gauge", "Commands running right now.", &running);
+    metric("cmd2zip_concurrency_limit", "gauge", "How many commands may run at once.", &limit);
+    metric("cmd2zip_elapsed_seconds", "gauge", "Time since the run started.", &started.elapsed().as_secs_f64());
+    text + "# EOF\n"
+}
+
+/// Replaces the metrics file as a whole, so that it's never read half-written.
+fn write(path: &Path, text: &str) {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    if let Err(err) = std::fs::write(&temp, text).and_then(|_| std::fs::rename(&temp, path)) {
+        status!("!! Failed to write metrics file `{}`: {err}", path.display());
+    }
+}
+
+/// Answers a single HTTP request with the metrics, whatever was asked for.
+fn serve(mut stream: TcpStream, control: &Control, started: Instant) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            read => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    
+    let text = render(control, started);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{text}",
+        text.len(),
+    )?;
+    stream.flush()
+}
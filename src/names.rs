@@ -0,0 +1,258 @@
+//! Generating entry names from commands, and making them safe to extract.
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use regex::Regex;
+
+use crate::writer::Checksum;
+
+/// Characters that can't appear in file names on Windows.
+const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// File names that Windows reserves for devices, regardless of their extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether the name matches the glob pattern, where `*` matches any run of characters (including `/`),
+/// and `?` any single character.
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*`, should the rest fail to match.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((after, at)) => {
+                    star = Some((after, at + 1));
+                    (p, n) = (after, at + 1);
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Normalizes a generated entry name, so that it stays within the directory it is extracted into.
+/// 
+/// - Backward-slashes become forward-slashes, and empty or `.` components are dropped.
+/// 
+/// - `..` components cancel out the preceding component; names escaping the archive are rejected.
+/// 
+/// - Control and Windows-invalid characters become `_`, as do trailing dots and spaces of components.
+/// 
+/// - Windows device names like `CON` or `nul.txt` are prefixed with `_`.
+/// 
+/// - A leading `/` is dropped, unless `allow_absolute` is set.
+pub fn sanitize(name: &str, allow_absolute: bool) -> Result<String, String> {
+    let name = name.replace('\\', "/");
+    
+    let mut components: Vec<String> = Vec::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => if components.pop().is_none() {
+                return Err(format!("entry name `{name}` escapes the archive"));
+            },
+            component => {
+                let mut component: String = component.chars()
+                    .map(|c| if c.is_control() || INVALID_CHARS.contains(&c) { '_' } else { c })
+                    .collect();
+                
+                let kept = component.trim_end_matches(['.', ' ']).len();
+                let trailing = component.len() - kept;
+                component.truncate(kept);
+                component.extend(std::iter::repeat_n('_', trailing));
+                
+                let stem = component.split('.').next().unwrap_or_default();
+                if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+                    component.insert(0, '_');
+                }
+                components.push(component);
+            },
+        }
+    }
+    
+    if components.is_empty() {
+        return Err(format!("entry name `{name}` is empty"));
+    }
+    
+    let absolute = allow_absolute && name.starts_with('/');
+    Ok(format!("{}{}", if absolute { "/" } else { "" }, components.join("/")))
+}
+
+/// A name template like `{stem}_{index:04}.png`, for `--name-template`.
+/// 
+/// Placeholders are a name of letters, digits and `_` within braces, optionally followed by `:0N` to pad numbers
+/// with zeros to `N` digits; what they stand for is up to the caller. Anything else is kept as it is.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Placeholder { name: String, width: usize, raw: String },
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            let raw = &rest[start..start + len + 1];
+            let (name, width) = raw[1..raw.len() - 1].split_once(':').unwrap_or((&raw[1..raw.len() - 1], ""));
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                parts.push(Part::Text(rest[..start + 1].to_string()));
+                rest = &rest[start + 1..];
+                continue;
+            }
+            
+            let width = match width {
+                "" => 0,
+                width => width.strip_prefix('0').and_then(|w| w.parse().ok())
+                    .ok_or_else(|| format!("invalid format `:{width}` of `{raw}`; expected `:0N`, like `{{{name}:04}}`"))?,
+            };
+            parts.push(Part::Text(rest[..start].to_string()));
+            parts.push(Part::Placeholder { name: name.to_string(), width, raw: raw.to_string() });
+            rest = &rest[start + len + 1..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+        Ok(Self { parts })
+    }
+    
+    /// Fills in the placeholders with the values `lookup` has for them; those it has none for are kept as they are.
+    pub fn expand(&self, lookup: impl Fn(&str) -> Option<String>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Placeholder { name, width, raw } => match lookup(name) {
+                    Some(value) if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) => out.push_str(&format!("{value:0>width$}")),
+                    Some(value) => out.push_str(&value),
+                    None => out.push_str(raw),
+                },
+            }
+        }
+        out
+    }
+}
+
+/// How a [`Generator`] comes up with the entry names of commands.
+#[derive(Debug, Clone)]
+pub enum Strategy {
+    /// Expanding the template, with the captures of the pattern, if any; `{stem}` and the like are of the match,
+    /// or else of the whole command if `whole`, or of its last word.
+    Template { pattern: Option<Regex>, template: Template, whole: bool },
+    
+    /// The match of the pattern.
+    Find(Regex),
+    
+    /// The replacement, expanded with the captures of the pattern.
+    Replace(Regex, String),
+    
+    /// The same name for every command, like a placeholder that's replaced once it ran.
+    Fixed(String),
+    
+    /// The whole command, like the name of an entry of `--input-archive`.
+    Whole,
+    
+    /// The file stem of the command, which is a path.
+    Stem,
+    
+    /// Numbers, counting up.
+    Numbered,
+}
+
+/// Generates the entry names of commands, numbering them in the order they're asked for.
+pub struct Generator {
+    strategy: Strategy,
+    counter: AtomicUsize,
+    width: usize,
+    prefix: String,
+    postfix: String,
+    date: String,
+}
+
+impl Generator {
+    /// Numbers start at `start`, padded with zeros to `width` digits.
+    pub fn new(strategy: Strategy, start: usize, width: usize) -> Self {
+        let date = time::OffsetDateTime::now_utc().date().to_string();
+        Self { strategy, counter: AtomicUsize::new(start), width, prefix: String::new(), postfix: String::new(), date }
+    }
+    
+    /// Put before and after every name.
+    pub fn affixes(mut self, prefix: impl Into<String>, postfix: impl Into<String>) -> Self {
+        (self.prefix, self.postfix) = (prefix.into(), postfix.into());
+        self
+    }
+    
+    /// The name of the command; `None` if it doesn't match the pattern, in which case no number is used up.
+    pub fn name(&self, command: &str) -> Option<String> {
+        let number = || format!("{:0width$}", self.counter.fetch_add(1, Ordering::Relaxed), width = self.width);
+        let name = match &self.strategy {
+            Strategy::Template { pattern, template, whole } => {
+                let captures = match pattern {
+                    Some(r) => Some(r.captures(command)?),
+                    None => None,
+                };
+                let index = number();
+                let path = match &captures {
+                    Some(captures) => captures[0].to_string(),
+                    None if *whole => command.to_string(),
+                    None => shlex::split(command).and_then(|words| words.last().cloned()).unwrap_or_default(),
+                };
+                let path = Path::new(&path);
+                template.expand(|name| {
+                    let capture = captures.as_ref().and_then(|captures| match name.parse::<usize>() {
+                        Ok(i) => captures.get(i),
+                        Err(_) => captures.name(name),
+                    });
+                    if let Some(capture) = capture {
+                        return Some(capture.as_str().to_string());
+                    }
+                    let lossy = |s: Option<&std::ffi::OsStr>| s.unwrap_or_default().to_string_lossy().into_owned();
+                    match name {
+                        "index" => Some(index.clone()),
+                        "stem" => Some(lossy(path.file_stem())),
+                        "ext" => Some(lossy(path.extension())),
+                        "dirname" => Some(lossy(path.parent().map(Path::as_os_str))),
+                        "date" => Some(self.date.clone()),
+                        "cmd_hash" => Checksum::Sha256.digest(&mut command.as_bytes()).ok().map(|hash| hash[..8].to_string()),
+                        _ => None,
+                    }
+                })
+            },
+            Strategy::Find(r) => r.find(command)?.as_str().to_string(),
+            Strategy::Replace(r, replacement) => {
+                let mut name = String::with_capacity(16);
+                r.captures(command)?.expand(replacement, &mut name);
+                name
+            },
+            Strategy::Fixed(name) => name.clone(),
+            Strategy::Whole => command.to_string(),
+            Strategy::Stem => Path::new(command).file_stem().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            Strategy::Numbered => number(),
+        };
+        Some(format!("{}{name}{}", self.prefix, self.postfix))
+    }
+}
@@ -0,0 +1,206 @@
+//! A progress bar on stderr, for interactive runs.
+
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How often the progress bar is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+const BAR_WIDTH: usize = 24;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static INPUT_DONE: AtomicBool = AtomicBool::new(false);
+
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+static RUNNING: AtomicU64 = AtomicU64::new(0);
+static DONE: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Held while anything is written to stderr, so the bar can be cleared first.
+static DRAWN: Mutex<bool> = Mutex::new(false);
+
+/// The lines printed while the console is taken over by `--tui`; `None` while it isn't.
+static HELD: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Starts drawing the progress bar, if stderr is a terminal.
+pub fn start() -> Option<JoinHandle<()>> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    
+    let start = Instant::now();
+    let drawer = std::thread::Builder::new()
+        .name("progress".into())
+        .spawn(move || {
+            while ENABLED.load(Ordering::Relaxed) {
+                draw(start.elapsed());
+                std::thread::sleep(REDRAW_INTERVAL);
+            }
+        })
+        .expect("failed to spawn progress bar");
+    Some(drawer)
+}
+
+/// Stops and removes the progress bar.
+pub fn stop(drawer: Option<JoinHandle<()>>) {
+    if let Some(drawer) = drawer {
+        ENABLED.store(false, Ordering::Relaxed);
+        drawer.join().ok();
+        suspend(|| {});
+    }
+}
+
+/// Runs the closure with the progress bar cleared, for printing other lines to the console.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let mut drawn = DRAWN.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if *drawn {
+        eprint!("\r\x1b[2K");
+        *drawn = false;
+    }
+    f()
+}
+
+/// Prints a line to stderr, or stdout, with the progress bar cleared first;
+/// while the console is taken over, lines for it are held back instead.
+pub fn print_line(line: &str, stderr: bool) {
+    if stderr || std::io::stdout().is_terminal() {
+        if let Some(held) = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_mut() {
+            held.push(line.to_string());
+            return;
+        }
+    }
+    suspend(|| match stderr {
+        true => eprintln!("{line}"),
+        false => println!("{line}"),
+    })
+}
+
+/// Holds back the lines printed to the console from now on, as it's taken over.
+pub fn hold() {
+    HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get_or_insert_with(Vec::new);
+}
+
+/// The latest of the lines held back so far.
+pub fn held(count: usize) -> Vec<String> {
+    let held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let held = held.as_deref().unwrap_or_default();
+    held[held.len().saturating_sub(count)..].to_vec()
+}
+
+/// Stops holding back lines, returning those held back so far for printing them after all.
+pub fn release() -> Vec<String> {
+    HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take().unwrap_or_default()
+}
+
+/// A command was queued to run.
+pub fn queued() {
+    TOTAL.fetch_add(1, Ordering::Relaxed);
+}
+
+/// All commands have been queued, so the total is known.
+pub fn input_done() {
+    INPUT_DONE.store(true, Ordering::Relaxed);
+}
+
+/// A command started running.
+pub fn started() {
+    RUNNING.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A command finished running.
+pub fn finished(failed: bool) {
+    RUNNING.fetch_sub(1, Ordering::Relaxed);
+    DONE.fetch_add(1, Ordering::Relaxed);
+    if failed {
+        FAILED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The tallies behind the progress bar, as of now.
+#[derive(Debug, Clone, Copy)]
+pub struct Counts {
+    pub queued: u64,
+    pub running: u64,
+    pub done: u64,
+    pub failed: u64,
+    
+    /// The bytes written to the archive.
+    pub bytes: u64,
+}
+
+pub fn counts() -> Counts {
+    Counts {
+        queued: TOTAL.load(Ordering::Relaxed),
+        running: RUNNING.load(Ordering::Relaxed),
+        done: DONE.load(Ordering::Relaxed),
+        failed: FAILED.load(Ordering::Relaxed),
+        bytes: BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// An entry was written to the archive.
+pub fn written(bytes: u64) {
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+fn draw(elapsed: Duration) {
+    let total = TOTAL.load(Ordering::Relaxed);
+    let done = DONE.load(Ordering::Relaxed);
+    let running = RUNNING.load(Ordering::Relaxed);
+    let failed = FAILED.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let input_done = INPUT_DONE.load(Ordering::Relaxed);
+    
+    let filled = if total == 0 { 0 } else { (done as usize * BAR_WIDTH) / total as usize };
+    let bar = format!("{}{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled));
+    
+    let throughput = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = if input_done && done > 0 && done < total {
+        let remaining = elapsed.mul_f64((total - done) as f64 / done as f64);
+        format!(", ETA {}", format_duration(remaining))
+    } else {
+        String::new()
+    };
+    let total = if input_done { total.to_string() } else { format!("{total}+") };
+    
+    let line = format!(
+        "[{bar}] {done}/{total} ({running} running, {failed} failed) {}, {}/s{eta}",
+        format_bytes(bytes as f64),
+        format_bytes(throughput),
+    );
+    
+    let mut drawn = DRAWN.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut stderr = std::io::stderr().lock();
+    write!(stderr, "\r\x1b[2K{line}").ok();
+    stderr.flush().ok();
+    *drawn = true;
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
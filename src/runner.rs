@@ -0,0 +1,997 @@
+//! Running jobs in parallel and capturing their outputs into an archive, for embedding in other tools.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use rayon::ThreadPoolBuilder;
+use regex::Regex;
+
+use crate::{
+    archive::{self, ArchiveWriter},
+    capture::Capture,
+    encoding::{self, Encoding},
+    exec::{self, Limits, Oversize, Shell},
+    json,
+    writer::{self, Config, Queue, Record, Slot},
+    Notice, Notify,
+};
+
+/// The longest the delay between retries grows to, unless the initial delay is longer already.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// How the entries of failed commands are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ErrFormat {
+    /// A report with the exit code, duration and command, followed by both stdout and stderr.
+    #[default]
+    Text,
+    
+    /// The same report as JSON object.
+    Json,
+    
+    /// Only the output, like for commands that succeeded.
+    Raw,
+}
+
+/// Which of the runs of a benchmarked command write their output into the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BenchKeep {
+    /// The output of the first run.
+    #[default]
+    First,
+    
+    /// The output of the last run.
+    Last,
+    
+    /// None, leaving only the statistics; failed commands still get their entries.
+    None,
+}
+
+/// What to do with commands that succeeded without any stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum EmptyStdout {
+    /// Write their stderr instead, unless it's captured separately via `--capture-stderr`.
+    #[default]
+    Stderr,
+    
+    /// Write no entry at all.
+    Skip,
+    
+    /// Write an empty entry.
+    Empty,
+    
+    /// Treat the command as failed.
+    Error,
+}
+
+/// Where stderr entries go, relative to their stdout entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StderrLayout {
+    Suffix,
+    Dir,
+}
+
+/// Builds the command of a job, afresh for every attempt; given its line and the environment variables to add, see [`Job::from_line`].
+type Build = Box<dyn FnMut(&str, &[(String, String)]) -> io::Result<Command> + Send>;
+
+/// A command to run, along with the entry its output goes into.
+pub struct Job {
+    command: Build,
+    
+    /// Whether the command is built from its line, see [`Job::from_line`].
+    templated: bool,
+    
+    /// The name of the entry; if `None`, [`Runner::run`] has its naming strategy pick one.
+    pub name: Option<String>,
+    
+    /// What the job was made from, like a line of an input file; recorded in [`Pipeline::failed_out`] should it fail,
+    /// and what its stdin is derived from, see [`Pipeline::stdin_replace`] and [`Pipeline::input_archive`].
+    pub input: Option<String>,
+    
+    /// A file to feed to the command via stdin; otherwise, stdin is empty.
+    pub stdin: Option<PathBuf>,
+    
+    /// The command line, for the record; the default is that of the built command.
+    pub line: Option<String>,
+    
+    /// A directory the command may write files into, which are written below its entry instead of its stdout;
+    /// emptied before every attempt, and removed afterwards.
+    pub out_dir: Option<PathBuf>,
+    
+    /// Whether only the file named `out` within `out_dir`, or `out` with an extension, is written, in place of stdout;
+    /// jobs that wrote none or more than one of them fail.
+    pub out_file: bool,
+    
+    /// Counts the bytes of stdout and stderr as they come in, for showing progress.
+    pub captured: Option<Arc<AtomicU64>>,
+}
+
+impl Job {
+    /// A job running the command built by `command`, which is called again for every retry.
+    pub fn new(mut command: impl FnMut() -> io::Result<Command> + Send + 'static) -> Self {
+        Self::build(Box::new(move |_, _| command()), false)
+    }
+    
+    /// A job running the command `build` makes of the line, which is called again for every retry.
+    /// 
+    /// With [`Pipeline::capture_dir`], or if the line holds `{out}`, the job gets an `out_dir`: `{dir}` and `{out}`
+    /// within the line are replaced with the paths of the directory and of the file `out` within it, and the path of
+    /// the directory is added to the environment as `CMD2ZIP_OUT`.
+    pub fn from_line(line: impl Into<String>, build: impl FnMut(&str, &[(String, String)]) -> io::Result<Command> + Send + 'static) -> Self {
+        Self::build(Box::new(build), true).line(line)
+    }
+    
+    fn build(command: Build, templated: bool) -> Self {
+        Self { command, templated, name: None, input: None, stdin: None, line: None, out_dir: None, out_file: false, captured: None }
+    }
+    
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    
+    pub fn input(mut self, input: impl Into<String>) -> Self {
+        self.input = Some(input.into());
+        self
+    }
+    
+    pub fn stdin(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stdin = Some(path.into());
+        self
+    }
+    
+    pub fn line(mut self, line: impl Into<String>) -> Self {
+        self.line = Some(line.into());
+        self
+    }
+    
+    pub fn out_dir(mut self, dir: impl Into<PathBuf>, out_file: bool) -> Self {
+        (self.out_dir, self.out_file) = (Some(dir.into()), out_file);
+        self
+    }
+    
+    pub fn captured(mut self, captured: Arc<AtomicU64>) -> Self {
+        self.captured = Some(captured);
+        self
+    }
+}
+
+/// What becomes of the jobs of a [`Runner`], beyond running their commands.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    /// The shell `stages` and `filter` run in; otherwise, they're split into arguments.
+    pub shell: Option<Shell>,
+    
+    /// Commands run one after another on the output of a job that succeeded, each writing its output instead;
+    /// `{}` within them is replaced with the path of a file holding the output, which is fed via stdin otherwise.
+    pub stages: Vec<String>,
+    
+    /// A command the final stdout of a job that succeeded is streamed through, like `gzip -9`.
+    pub filter: Option<String>,
+    
+    /// How often to retry jobs that failed or timed out, before giving up on them.
+    pub retries: u32,
+    
+    /// How long to wait before the first retry; doubles with every further retry, up to 5 minutes (or this, if longer).
+    pub retry_delay: Duration,
+    
+    /// The encoding outputs are transcoded from into UTF-8, before any stages or the filter.
+    pub encoding: Encoding,
+    
+    /// Whether to turn CRLF line endings into LF, along with `encoding`.
+    pub normalize_newlines: bool,
+    
+    pub err_format: ErrFormat,
+    pub on_empty_stdout: EmptyStdout,
+    
+    /// How stderr goes into entries of its own, if at all; otherwise, it's only used in place of an empty stdout.
+    pub capture_stderr: Option<StderrLayout>,
+    
+    /// The directory the entries of failed jobs go into, instead of next to the others.
+    pub failed_dir: Option<String>,
+    
+    /// Whether to leave failed jobs out of the archive.
+    pub drop_failed: bool,
+    
+    /// How many times to run each job in a row, timing the runs; see [`Output::runs`].
+    pub bench: Option<u32>,
+    
+    pub bench_keep: BenchKeep,
+    
+    /// Where outputs exceeding their maximum size are written with [`Oversize::Spill`]; without it, they're only left out.
+    pub spill_dir: Option<PathBuf>,
+    
+    /// Whether to leave the timings out of failure reports, so they're the same across runs.
+    pub reproducible: bool,
+    
+    /// Whether to write the command line of every job into a `.txt`-entry, instead of running it.
+    pub dry_run: bool,
+    
+    /// Whether every job built with [`Job::from_line`] gets an `out_dir`, whether its line holds `{out}` or not.
+    pub capture_dir: bool,
+    
+    /// Derives the stdin of jobs from their input: the replacement, expanded with the captures of the pattern;
+    /// jobs whose input doesn't match fail.
+    pub stdin_replace: Option<(Regex, String)>,
+    
+    /// The archive holding the stdin of every job, as the entry named by its input; extracted before it runs.
+    pub input_archive: Option<Arc<Mutex<zip::ZipArchive<File>>>>,
+    
+    /// Where the inputs of failed jobs are written, one per line, to be run again.
+    pub failed_out: Option<Arc<Mutex<File>>>,
+    
+    /// Whether to stop all the jobs still running once one of them fails.
+    pub fail_fast: bool,
+    
+    /// Whether to remember the inputs of all jobs, for [`Scope::inputs`]; e.g. to watch them for changes.
+    pub watch: bool,
+}
+
+/// What came of running a job, before it's written; see [`Scope::run`].
+/// 
+/// Its name may still be changed, e.g. after its output; the suffix of failed jobs is only added by [`Scope::write`].
+pub struct Output {
+    pub name: String,
+    
+    /// The content of the entry; stderr, if that's used in place of an empty stdout.
+    pub stdout: Capture,
+    pub stderr: Capture,
+    
+    /// The files the job wrote into its `out_dir`, named relative to it; written in place of stdout.
+    pub files: Vec<(String, Capture)>,
+    
+    pub record: Record,
+    pub success: bool,
+    
+    /// Whether the entry holding stdout is written at all.
+    pub kept: bool,
+    
+    /// How long each run took, for [`Pipeline::bench`]; the first one being the last attempt.
+    pub runs: Vec<Duration>,
+}
+
+/// How a job turned out, once written; passed to the callback of [`Runner::on_complete`].
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// The name of the entry, including the `.err`- or `.timeout`-suffix of failed jobs.
+    pub name: String,
+    
+    /// The bytes written for the job, over all of its entries.
+    pub bytes: u64,
+    pub success: bool,
+    
+    /// Whether the entry holding stdout was written, see [`Output::kept`].
+    pub kept: bool,
+    pub record: Record,
+}
+
+/// How many of the jobs succeeded, and how many failed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+type Naming = Box<dyn FnMut(usize, &Job) -> String>;
+type Callback = Box<dyn Fn(&Completion) + Send + Sync>;
+type Pace = Box<dyn Fn() + Send + Sync>;
+type Tee = Box<dyn Fn(&str, &str, bool) + Send + Sync>;
+
+/// Runs jobs on a thread-pool, writing each of their outputs into the archive as soon as they finish.
+/// 
+/// Every job goes through the same pipeline as the commands of the binary: retries, stages and the filter, and
+/// a `.err`-entry (or `.timeout`) with a failure report for failed jobs; see [`Pipeline`].
+pub struct Runner {
+    archive: Box<dyn ArchiveWriter>,
+    config: Config,
+    threads: usize,
+    limits: Limits,
+    pipeline: Pipeline,
+    naming: Naming,
+    on_complete: Option<Callback>,
+    notify: Notify,
+    pace: Option<Pace>,
+    tee: Option<Tee>,
+}
+
+impl Runner {
+    pub fn new(archive: Box<dyn ArchiveWriter>) -> Self {
+        Self {
+            archive,
+            config: Config::default(),
+            threads: 0,
+            limits: Limits::default(),
+            pipeline: Pipeline::default(),
+            naming: Box::new(|index, _| index.to_string()),
+            on_complete: None,
+            notify: Arc::new(|_| {}),
+            pace: None,
+            tee: None,
+        }
+    }
+    
+    /// The number of jobs to run in parallel; the default is 0 for all cores.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+    
+    /// What the archive writer does beyond writing entries, like the manifest or handling of name collisions.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+    
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    
+    pub fn pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+    
+    /// Names the entries of jobs without a name, given their index; the default numbers them, starting at 0.
+    pub fn naming(mut self, naming: impl FnMut(usize, &Job) -> String + 'static) -> Self {
+        self.naming = Box::new(naming);
+        self
+    }
+    
+    /// Called from the worker threads as each job completes, once its entries are handed to the archive writer.
+    pub fn on_complete(mut self, callback: impl Fn(&Completion) + Send + Sync + 'static) -> Self {
+        self.on_complete = Some(Box::new(callback));
+        self
+    }
+    
+    /// Told about what's going on, like retries or colliding entries; by default, nothing is told.
+    pub fn on_notice(mut self, callback: impl Fn(Notice) + Send + Sync + 'static) -> Self {
+        self.notify = Arc::new(callback);
+        self
+    }
+    
+    /// Called before every attempt of a job, and may block to hold it back; e.g. to limit how fast commands are started.
+    pub fn pace(mut self, pace: impl Fn() + Send + Sync + 'static) -> Self {
+        self.pace = Some(Box::new(pace));
+        self
+    }
+    
+    /// Handed every line of stdout and stderr as it comes in, along with the name of its job's entry
+    /// and whether it's from stderr.
+    pub fn tee(mut self, tee: impl Fn(&str, &str, bool) + Send + Sync + 'static) -> Self {
+        self.tee = Some(Box::new(tee));
+        self
+    }
+    
+    /// Runs all the jobs, then finishes the archive.
+    pub fn run(mut self, jobs: impl IntoIterator<Item = Job>) -> io::Result<Summary> {
+        let mut naming = std::mem::replace(&mut self.naming, Box::new(|index, _| index.to_string()));
+        let on_complete = self.on_complete.take();
+        let notify = self.notify.clone();
+        let (succeeded, failed) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        
+        self.scope(|scope| {
+            let (on_complete, notify, succeeded, failed) = (&on_complete, &notify, &succeeded, &failed);
+            for (index, mut job) in jobs.into_iter().enumerate() {
+                let name = job.name.take().unwrap_or_else(|| naming(index, &job));
+                job.name = Some(name.clone());
+                let mut slot = scope.reserve(&job);
+                scope.spawn(move |scope| {
+                    let notify = |notice: Notice| notify(notice.map(|message| format!("{message} (`{name}`)")));
+                    let output = scope.run(job, &notify);
+                    let completion = scope.write(output, &mut slot, &notify);
+                    match completion.success {
+                        true => succeeded.fetch_add(1, Ordering::Relaxed),
+                        false => failed.fetch_add(1, Ordering::Relaxed),
+                    };
+                    if let Some(callback) = on_complete {
+                        callback(&completion);
+                    }
+                });
+            }
+        })?;
+        Ok(Summary { succeeded: succeeded.into_inner(), failed: failed.into_inner() })
+    }
+    
+    /// Starts the threads and the archive writer, for `body` to spawn jobs on as it comes up with them;
+    /// finishes the archive once `body` and all the jobs it spawned are done.
+    /// 
+    /// A panicking job is re-raised only once the archive is finished, so that the outputs of all others are kept.
+    pub fn scope<'env, R>(self, body: impl for<'scope> FnOnce(&Scope<'_, 'scope, 'env>) -> R) -> io::Result<R> {
+        let Runner { archive, config, threads, limits, pipeline, notify, pace, tee, .. } = self;
+        
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build().map_err(io::Error::other)?;
+        let merge = config.merge.clone();
+        let (queue, writer) = writer::spawn(archive, config, pool.current_num_threads(), notify);
+        let shared = Shared {
+            pipeline,
+            limits,
+            pace,
+            tee,
+            merge,
+            queue: Mutex::new(queue),
+            running: Mutex::new(0),
+            finished: Condvar::new(),
+            failed: AtomicBool::new(false),
+            inputs: Mutex::new(Vec::new()),
+        };
+        
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| pool.in_place_scope(|scope| {
+            body(&Scope { scope, shared: &shared, env: PhantomData })
+        })));
+        
+        drop(shared);
+        let written = writer.join().map_err(|_| io::Error::other("archive writer panicked")).and_then(|written| written);
+        match result {
+            Ok(value) => written.map(|_| value),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// What the threads of a [`Runner`] share, for the duration of [`Runner::scope`].
+struct Shared {
+    pipeline: Pipeline,
+    limits: Limits,
+    pace: Option<Pace>,
+    tee: Option<Tee>,
+    
+    /// The entry that those of the same name are concatenated into, see [`Config::merge`].
+    merge: Option<String>,
+    queue: Mutex<Queue>,
+    
+    /// The number of jobs spawned that didn't finish yet, for [`Scope::wait`].
+    running: Mutex<usize>,
+    finished: Condvar,
+    
+    /// Whether any job failed, see [`Scope::failed`].
+    failed: AtomicBool,
+    
+    /// The inputs of the jobs so far, with [`Pipeline::watch`].
+    inputs: Mutex<Vec<String>>,
+}
+
+impl Shared {
+    /// Records the failure of a job: writes its input to [`Pipeline::failed_out`], and with [`Pipeline::fail_fast`],
+    /// stops the jobs still running.
+    fn fail(&self, input: Option<&str>) -> io::Result<()> {
+        self.failed.store(true, Ordering::Relaxed);
+        if self.pipeline.fail_fast {
+            exec::stop_all(&self.limits);
+        }
+        
+        if let (Some(failed_out), Some(input)) = (&self.pipeline.failed_out, input) {
+            let mut failed_out = failed_out.lock().expect("failed to lock mutex");
+            writeln!(failed_out, "{input}")?;
+            failed_out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts a spawned job as running, until dropped.
+struct Running<'a>(&'a Shared);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().expect("failed to lock mutex") -= 1;
+        self.0.finished.notify_all();
+    }
+}
+
+/// Hands jobs to the threads of a [`Runner`], within [`Runner::scope`].
+pub struct Scope<'a, 'scope, 'env: 'scope> {
+    scope: &'a rayon::Scope<'scope>,
+    shared: &'scope Shared,
+    env: PhantomData<&'scope mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'_, 'scope, 'env> {
+    /// Reserves the place of a job's entries in the archive, see [`Config::ordered`]; for jobs that are spawned
+    /// later than they come up, or entries that aren't from jobs at all.
+    pub fn slot(&self) -> Slot {
+        self.shared.queue.lock().expect("failed to lock mutex").slot()
+    }
+    
+    /// Reserves the place of the job's entries, like [`Scope::slot`]; with [`Pipeline::watch`], its input is
+    /// remembered, whether it's run in the end or not.
+    pub fn reserve(&self, job: &Job) -> Slot {
+        if let Some(input) = job.input.as_ref().filter(|_| self.shared.pipeline.watch) {
+            self.shared.inputs.lock().expect("failed to lock mutex").push(input.clone());
+        }
+        self.slot()
+    }
+    
+    /// Records the failure of a job that's not run at all, as [`Scope::run`] does for those that failed.
+    pub fn fail(&self, input: &str, notify: &dyn Fn(Notice)) {
+        if let Err(err) = self.shared.fail(Some(input)) {
+            notify(Notice::Error(format!("Failed to write failed command: {err}")));
+        }
+    }
+    
+    /// Whether any job failed so far.
+    pub fn failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+    
+    /// The inputs of all the jobs reserved so far, with [`Pipeline::watch`].
+    pub fn inputs(&self) -> Vec<String> {
+        self.shared.inputs.lock().expect("failed to lock mutex").clone()
+    }
+    
+    /// Runs `work` on one of the threads; usually [`Scope::run`] and [`Scope::write`], along with whatever else
+    /// the job needs.
+    pub fn spawn(&self, work: impl FnOnce(&Scope<'_, 'scope, 'env>) + Send + 'scope) {
+        let shared = self.shared;
+        *shared.running.lock().expect("failed to lock mutex") += 1;
+        self.scope.spawn(move |scope| {
+            let _running = Running(shared);
+            work(&Scope { scope, shared, env: PhantomData });
+        });
+    }
+    
+    /// Blocks until all the work spawned so far is done.
+    pub fn wait(&self) {
+        let running = self.shared.running.lock().expect("failed to lock mutex");
+        drop(self.shared.finished.wait_while(running, |running| *running > 0).expect("failed to lock mutex"));
+    }
+    
+    /// Runs the job through the pipeline, on the calling thread; what's going on is told about via `notify`.
+    /// 
+    /// Note: This blocks until the job finishes, ON PURPOSE.
+    pub fn run(&self, job: Job, notify: &dyn Fn(Notice)) -> Output {
+        let Shared { pipeline, limits, pace, tee, .. } = self.shared;
+        let Job { mut command, templated, name, input, stdin, mut line, out_dir, out_file, captured } = job;
+        let mut name = name.unwrap_or_default();
+        let captured = captured.unwrap_or_default();
+        
+        // Jobs built from their line get a directory to write files into if they ask for `{out}`, or all of them do.
+        let asked = templated && line.as_ref().is_some_and(|line| line.contains("{out}"));
+        let out_file = out_file || (asked && !pipeline.capture_dir);
+        let out_dir = out_dir.or_else(|| (templated && (pipeline.capture_dir || asked)).then(|| temp_path("out")));
+        let (built, env) = match out_dir.as_ref().filter(|_| templated) {
+            Some(dir) => {
+                let quoted = |path: &Path| shlex::try_quote(&path.to_string_lossy()).unwrap_or_default().into_owned();
+                let built = line.clone().unwrap_or_default().replace("{dir}", &quoted(dir)).replace("{out}", &quoted(&dir.join("out")));
+                (built, vec![("CMD2ZIP_OUT".to_string(), dir.to_string_lossy().into_owned())])
+            },
+            None => (line.clone().unwrap_or_default(), Vec::new()),
+        };
+        
+        // The entry of the input archive, extracted into a temporary file that's removed once the job ran.
+        let extracted = match (&pipeline.input_archive, &input) {
+            (Some(archive), Some(input)) => Some(extract(archive, input)),
+            _ => None,
+        };
+        let stdin = match (&extracted, &pipeline.stdin_replace, &input) {
+            (Some(Ok(path)), ..) => Ok(Some(path.clone())),
+            (Some(Err(err)), ..) => Err(io::Error::new(err.kind(), format!("failed to extract `{}`: {err}", input.as_deref().unwrap_or_default()))),
+            (None, Some((pattern, replacement)), Some(input)) => match pattern.captures(input) {
+                Some(captures) => {
+                    let mut path = String::with_capacity(16);
+                    captures.expand(replacement, &mut path);
+                    Ok(Some(PathBuf::from(path)))
+                },
+                None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("failed to derive stdin, as `{input}` doesn't match `{pattern}`"))),
+            },
+            _ => Ok(stdin),
+        };
+        
+        let fresh_dir = || match &out_dir {
+            Some(dir) => {
+                std::fs::remove_dir_all(dir).ok();
+                std::fs::create_dir_all(dir)
+            },
+            None => Ok(()),
+        };
+        
+        let mut attempts = 1;
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        
+        // How long each run took, for benchmarks; the first one being the last attempt.
+        let mut runs = Vec::new();
+        
+        // Summed up over all attempts, stages and the filter, as long as each of them tells.
+        let mut cpu_time = None;
+        let mut used_cpu = |time: Option<Duration>| cpu_time = match (cpu_time, time) {
+            (Some(total), Some(time)) => Some(total + time),
+            (total, time) => total.or(time),
+        };
+        
+        // Whether the job failed beyond its exit status: it couldn't be run, or a stage or the filter failed.
+        let mut filtered = true;
+        
+        let (mut exit, mut timed_out, mut oversize, mut stdout, mut stderr) = if pipeline.dry_run {
+            let line = line.get_or_insert_with(|| command(&built, &env).map(|command| exec::display(&command)).unwrap_or_default());
+            name += ".txt";
+            (None, false, false, line.as_bytes().to_vec().into(), Capture::default())
+        } else {
+            let entry = name.as_str();
+            let teed = tee.as_ref().map(|tee| move |text: &str, stderr: bool| tee(entry, text, stderr));
+            let teed = teed.as_ref().map(|teed| teed as &exec::Lines<'_>);
+            let mut run = || {
+                let stdin = stdin.as_ref().map_err(|err| io::Error::new(err.kind(), err.to_string()))?;
+                fresh_dir()?;
+                let command = command(&built, &env)?;
+                line.get_or_insert_with(|| exec::display(&command));
+                exec::run_watched(command, stdin.as_deref(), limits, &captured, teed, notify)
+            };
+            let failed = |output: &io::Result<exec::Outcome>| output.as_ref().is_ok_and(|o| o.timed_out || !o.status.success());
+            let pace = || if let Some(pace) = pace {
+                pace();
+            };
+            
+            pace();
+            let mut attempted = Instant::now();
+            let mut output = run();
+            used_cpu(output.as_ref().ok().and_then(|o| o.cpu_time));
+            
+            // Commands that couldn't be run at all won't run on the next attempt either.
+            while failed(&output) && attempts <= pipeline.retries {
+                let max = MAX_RETRY_DELAY.max(pipeline.retry_delay);
+                let delay = pipeline.retry_delay.checked_mul(2u32.saturating_pow(attempts - 1)).map_or(max, |delay| delay.min(max));
+                notify(Notice::Warning(format!("Command failed, retrying in {delay:?}")));
+                std::thread::sleep(delay);
+                
+                attempts += 1;
+                pace();
+                attempted = Instant::now();
+                output = run();
+                used_cpu(output.as_ref().ok().and_then(|o| o.cpu_time));
+            }
+            runs.push(attempted.elapsed());
+            
+            while runs.len() < pipeline.bench.unwrap_or(1) as usize && output.is_ok() && !failed(&output) {
+                attempted = Instant::now();
+                let again = run();
+                used_cpu(again.as_ref().ok().and_then(|o| o.cpu_time));
+                runs.push(attempted.elapsed());
+                
+                if again.is_err() || failed(&again) {
+                    notify(Notice::Error(format!("Command failed on run {} of the benchmark", runs.len())));
+                    output = again;
+                } else if pipeline.bench_keep == BenchKeep::Last {
+                    output = again;
+                }
+            }
+            if let Some(Ok(path)) = &extracted {
+                std::fs::remove_file(path).ok();
+            }
+            
+            match output {
+                Ok(output) => {
+                    let (mut stdout, mut stderr) = (output.stdout, output.stderr);
+                    let normalized = [&mut stdout, &mut stderr].into_iter()
+                        .try_for_each(|output| encoding::normalize(output, pipeline.encoding, pipeline.normalize_newlines));
+                    if let Err(err) = normalized {
+                        notify(Notice::Error(format!("Failed to transcode the output: {err}")));
+                        (stderr, filtered) = (format!("failed to transcode output: {err}").into_bytes().into(), false);
+                    }
+                    (Some(output.status), output.timed_out, output.oversize, stdout, stderr)
+                },
+                Err(err) => {
+                    filtered = false;
+                    (None, false, false, Capture::default(), format!("failed to run command: {err}").into_bytes().into())
+                },
+            }
+        };
+        
+        for (stage, template) in pipeline.stages.iter().enumerate() {
+            if !exit.is_some_and(|s| s.success()) || timed_out {
+                break;
+            }
+            
+            let path = temp_path(&format!("{stage}.then"));
+            let (line, stdin) = match template.contains("{}") {
+                true => (template.replace("{}", &shlex::try_quote(&path.to_string_lossy()).unwrap_or_default()), None),
+                false => (template.clone(), Some(path.as_path())),
+            };
+            let output = File::create(&path)
+                .and_then(|mut file| io::copy(&mut stdout.reader()?, &mut file))
+                .and_then(|_| exec::run(exec::local(&line, pipeline.shell)?, stdin, limits, notify));
+            std::fs::remove_file(&path).ok();
+            
+            match output {
+                Ok(output) => {
+                    used_cpu(output.cpu_time);
+                    if output.timed_out || !output.status.success() {
+                        notify(Notice::Error(format!("Stage `{line}` failed")));
+                    }
+                    (exit, timed_out, stdout, stderr) = (Some(output.status), output.timed_out, output.stdout, output.stderr);
+                    oversize |= output.oversize;
+                },
+                Err(err) => {
+                    notify(Notice::Error(format!("Failed to run stage `{line}`: {err}")));
+                    (stderr, filtered) = (format!("failed to run stage `{line}`: {err}").into_bytes().into(), false);
+                    break;
+                },
+            }
+        }
+        
+        if let Some(filter) = pipeline.filter.as_ref().filter(|_| exit.is_some_and(|s| s.success()) && !timed_out && !stdout.is_empty()) {
+            let output = exec::local(filter, pipeline.shell).and_then(|command| exec::pipe(command, &mut stdout, notify));
+            if let Ok(output) = &output {
+                used_cpu(output.cpu_time);
+            }
+            match output {
+                Ok(output) if output.status.success() => stdout = output.stdout,
+                Ok(output) => {
+                    notify(Notice::Error(format!("Filter `{filter}` failed")));
+                    (exit, stderr, filtered) = (Some(output.status), output.stderr, false);
+                },
+                Err(err) => {
+                    notify(Notice::Error(format!("Failed to run filter `{filter}`: {err}")));
+                    (stderr, filtered) = (format!("failed to run filter `{filter}`: {err}").into_bytes().into(), false);
+                },
+            }
+        }
+        
+        let duration = timer.elapsed();
+        // The files the command wrote, which are written instead of its stdout.
+        let mut files = Vec::new();
+        if let Some(dir) = &out_dir {
+            if exit.is_some_and(|s| s.success()) && !timed_out {
+                let mut skipped = Vec::new();
+                match read_files(dir, &mut skipped) {
+                    Ok(read) => {
+                        if !skipped.is_empty() {
+                            notify(Notice::Warning(format!("Command wrote {} entries that aren't regular files, leaving them out: {}", skipped.len(), skipped.join(", "))));
+                        }
+                        files = read;
+                        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    },
+                    Err(err) => {
+                        notify(Notice::Error(format!("Failed to read the files written by the command: {err}")));
+                        (stderr, filtered) = (format!("failed to read the files written by the command: {err}").into_bytes().into(), false);
+                    },
+                }
+            }
+            std::fs::remove_dir_all(dir).ok();
+        }
+        
+        // The file written to `out`, whatever its extension, goes in place of stdout.
+        if out_file && filtered && exit.is_some_and(|s| s.success()) && !timed_out {
+            let is_out = |file: &str| file == "out" || file.strip_prefix("out.").is_some_and(|ext| !ext.is_empty() && !ext.contains('/'));
+            let written: Vec<usize> = files.iter().enumerate().filter(|(_, (file, _))| is_out(file)).map(|(i, _)| i).collect();
+            match written[..] {
+                [i] => stdout = files.swap_remove(i).1,
+                [] => {
+                    notify(Notice::Error("Command wrote no file to `{out}`".to_string()));
+                    (stderr, filtered) = (b"command wrote no file to `{out}`".to_vec().into(), false);
+                },
+                _ => {
+                    let names = written.iter().map(|i| files[*i].0.as_str()).collect::<Vec<_>>().join(", ");
+                    notify(Notice::Error(format!("Command wrote more than one file to `{{out}}`: {names}")));
+                    (stderr, filtered) = (format!("command wrote more than one file to `{{out}}`: {names}").into_bytes().into(), false);
+                },
+            }
+            files.clear();
+        }
+        
+        let status = exit.is_none_or(|s| s.success()) && filtered
+            && !(oversize && limits.on_oversize == Oversize::Error)
+            && !(stdout.is_empty() && files.is_empty() && pipeline.on_empty_stdout == EmptyStdout::Error);
+        let success = status && !timed_out;
+        
+        let mut using = "stdout";
+        let mut kept = true;
+        
+        if !files.is_empty() {
+            notify(Notice::Info(1, format!("Command wrote {} files, leaving its stdout out", files.len())));
+            using = "files";
+            kept = false;
+        }
+        
+        if pipeline.bench.is_some() && pipeline.bench_keep == BenchKeep::None && success {
+            (kept, files) = (false, Vec::new());
+        }
+        
+        // Failures get a report of their own with both streams in it, see `write`.
+        let report = !success && pipeline.err_format != ErrFormat::Raw;
+        
+        // Failed jobs get their stderr regardless, as it likely tells why.
+        if stdout.is_empty() && files.is_empty() && kept {
+            match pipeline.on_empty_stdout {
+                EmptyStdout::Skip if status => {
+                    notify(Notice::Info(0, "Command had no stdout, leaving it out".to_string()));
+                    kept = false;
+                },
+                EmptyStdout::Empty if status => {},
+                _ if pipeline.capture_stderr.is_none() && !report => {
+                    notify(Notice::Warning("Command had no stdout, writing stderr instead".to_string()));
+                    std::mem::swap(&mut stdout, &mut stderr);
+                    using = "stderr";
+                },
+                _ => {},
+            }
+        }
+        
+        if oversize {
+            let max = limits.max_output.unwrap_or_default();
+            let done = match (limits.on_oversize, &pipeline.spill_dir) {
+                (Oversize::Truncate, _) => "truncated it".to_string(),
+                (Oversize::Skip, _) | (Oversize::Spill, None) => "leaving it out".to_string(),
+                (Oversize::Error, _) => "failing the command".to_string(),
+                (Oversize::Spill, Some(dir)) => format!("writing it into `{}` instead", dir.display()),
+            };
+            notify(Notice::Warning(format!("Command's output exceeded {max} bytes; {done}")));
+        }
+        
+        let record = Record {
+            command: line.unwrap_or_default(),
+            exit_code: exit.and_then(|s| s.code()),
+            signal: exit.and_then(exec::signal),
+            timed_out,
+            attempts,
+            stream: using,
+            started,
+            duration,
+            cpu_time,
+            oversize: oversize.then_some(limits.on_oversize),
+        };
+        
+        if !success {
+            if let Err(err) = self.shared.fail(input.as_deref()) {
+                notify(Notice::Error(format!("Failed to write failed command: {err}")));
+            }
+        }
+        Output { name, stdout, stderr, files, record, success, kept, runs }
+    }
+    
+    /// Writes the output of a job into its slot: naming the entries of failures, with their report,
+    /// and the stderr entry, if any.
+    pub fn write(&self, output: Output, slot: &mut Slot, notify: &dyn Fn(Notice)) -> Completion {
+        let Shared { pipeline, merge, .. } = self.shared;
+        let Output { mut name, mut stdout, mut stderr, mut files, record, success, mut kept, .. } = output;
+        
+        if record.timed_out {
+            name += ".timeout";
+        } else if !success {
+            name += ".err";
+        }
+        
+        if !success {
+            if let Some(dir) = &pipeline.failed_dir {
+                name = format!("{dir}/{name}");
+            }
+            if pipeline.drop_failed {
+                notify(Notice::Info(1, "Leaving out the output of the failed command".to_string()));
+                (kept, files) = (false, Vec::new());
+            }
+        }
+        
+        if !success && pipeline.err_format != ErrFormat::Raw {
+            match failure_report(pipeline.err_format, &record, &mut stdout, &mut stderr, pipeline.reproducible) {
+                Ok(report) => stdout = report,
+                Err(err) => notify(Notice::Error(format!("Failed to write the failure report: {err}"))),
+            }
+        }
+        
+        let stderr_entry = pipeline.capture_stderr.filter(|_| !stderr.is_empty() && (success || !pipeline.drop_failed)).map(|layout| {
+            let stderr_name = match layout {
+                StderrLayout::Suffix => format!("{name}.stderr"),
+                StderrLayout::Dir => format!("stderr/{name}"),
+            };
+            (stderr_name, Record { stream: "stderr", ..record.clone() })
+        });
+        
+        let bytes = stdout.len() + stderr_entry.as_ref().map_or(0, |_| stderr.len()) + files.iter().map(|(_, content)| content.len()).sum::<u64>();
+        
+        if let Some(spill_dir) = pipeline.spill_dir.as_ref().filter(|_| record.oversize == Some(Oversize::Spill)) {
+            let spilled = std::iter::once((&name, &mut stdout)).chain(stderr_entry.as_ref().map(|(n, _)| (n, &mut stderr)));
+            for (name, content) in spilled {
+                let path = spill_dir.join(name);
+                let written = std::fs::create_dir_all(path.parent().unwrap_or(spill_dir))
+                    .and_then(|_| io::copy(&mut content.reader()?, &mut File::create(&path)?));
+                if let Err(err) = written {
+                    notify(Notice::Warning(format!("Failed to write oversize output `{}`: {err}", path.display())));
+                }
+            }
+        }
+        
+        for (file, content) in files {
+            slot.push(format!("{name}/{file}"), content, record.clone());
+        }
+        
+        if kept {
+            let entry = match merge {
+                Some(merged) if success => merged.clone(),
+                _ => name.clone(),
+            };
+            slot.push(entry, stdout, record.clone());
+        }
+        
+        if let Some((stderr_name, record)) = stderr_entry {
+            slot.push(stderr_name, stderr, record);
+        }
+        Completion { name, bytes, success, kept, record }
+    }
+}
+
+/// Extracts the entry of the given name into a temporary file.
+fn extract(archive: &Mutex<zip::ZipArchive<File>>, name: &str) -> io::Result<PathBuf> {
+    let path = temp_path("in");
+    let mut archive = archive.lock().expect("failed to lock mutex");
+    let mut entry = archive.by_name(name).map_err(io::Error::from)?;
+    if let Err(err) = File::create(&path).and_then(|mut file| io::copy(&mut entry, &mut file)) {
+        std::fs::remove_file(&path).ok();
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// A path for a temporary file of this process, distinct from all the others it asked for.
+fn temp_path(extension: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!("cmd2zip-{}-{}.{extension}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Reads all regular files within the directory, naming them by their path relative to it, with forward-slashes;
+/// the names of everything else but directories, like symlinks, are left out and collected into `skipped`.
+fn read_files(dir: &Path, skipped: &mut Vec<String>) -> io::Result<Vec<(String, Capture)>> {
+    let mut files = Vec::new();
+    for walked in archive::walk(dir)? {
+        if walked.kind.is_file() {
+            let mut content = Capture::default();
+            io::copy(&mut File::open(&walked.path)?, &mut content)?;
+            files.push((walked.name, content));
+        } else if !walked.kind.is_dir() {
+            skipped.push(walked.name);
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the report of a failed job, with both of its streams; timings are left out if it's to be `reproducible`.
+fn failure_report(format: ErrFormat, record: &Record, stdout: &mut Capture, stderr: &mut Capture, reproducible: bool) -> io::Result<Capture> {
+    let mut report = Capture::default();
+    match format {
+        ErrFormat::Text => {
+            writeln!(report, "command: {}", record.command)?;
+            match (record.exit_code, record.signal) {
+                _ if record.timed_out => writeln!(report, "timed out")?,
+                (Some(code), _) => writeln!(report, "exit code: {code}")?,
+                (None, Some(signal)) => writeln!(report, "signal: {signal}")?,
+                (None, None) => writeln!(report, "exit code: unknown")?,
+            }
+            if !reproducible {
+                writeln!(report, "duration: {:.3}s", record.duration.as_secs_f64())?;
+            }
+            writeln!(report, "attempts: {}", record.attempts)?;
+            
+            for (stream, content) in [("stdout", stdout), ("stderr", stderr)] {
+                writeln!(report, "\n--- {stream} ({} bytes) ---", content.len())?;
+                io::copy(&mut content.reader()?, &mut report)?;
+            }
+        },
+        ErrFormat::Json => {
+            let mut json = record.to_json("", 0);
+            if let json::Value::Object(fields) = &mut json {
+                fields.retain(|(key, _)| !["name", "bytes", "stream"].contains(&key.as_str()));
+                if reproducible {
+                    fields.retain(|(key, _)| !["started", "finished", "duration"].contains(&key.as_str()));
+                }
+                for (stream, content) in [("stdout", stdout), ("stderr", stderr)] {
+                    let mut text = Vec::with_capacity(content.len() as usize);
+                    content.reader()?.read_to_end(&mut text)?;
+                    fields.push((stream.to_string(), String::from_utf8_lossy(&text).into_owned().into()));
+                }
+            }
+            writeln!(report, "{json}")?;
+        },
+        ErrFormat::Raw => unreachable!("raw outputs aren't reported"),
+    }
+    Ok(report)
+}
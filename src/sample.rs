@@ -0,0 +1,51 @@
+//! Picking a random subset of the commands, for `--sample`.
+
+use std::time::SystemTime;
+
+/// SplitMix64; plenty random for picking commands, and reproducible given its seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    
+    /// A number within `0..n`; the slight bias for huge `n` doesn't matter here.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+/// A seed that differs between runs, for when none is given.
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    Rng::new(nanos ^ ((std::process::id() as u64) << 32)).next_u64()
+}
+
+/// Picks `n` of the items uniformly at random, keeping them in their original order; also returns how many there were.
+pub fn sample<T>(items: impl Iterator<Item = T>, n: usize, seed: u64) -> (Vec<T>, usize) {
+    let mut rng = Rng::new(seed);
+    let mut reservoir: Vec<(usize, T)> = Vec::with_capacity(n);
+    let mut total = 0;
+    for (i, item) in items.enumerate() {
+        total += 1;
+        if reservoir.len() < n {
+            reservoir.push((i, item));
+        } else {
+            let j = rng.below(i as u64 + 1) as usize;
+            if j < n {
+                reservoir[j] = (i, item);
+            }
+        }
+    }
+    reservoir.sort_by_key(|(i, _)| *i);
+    (reservoir.into_iter().map(|(_, item)| item).collect(), total)
+}
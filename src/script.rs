@@ -0,0 +1,61 @@
+//! Writing the commands of a run as a shell script, for `--emit-script`.
+
+use std::{path::PathBuf, sync::Mutex};
+
+use crate::input::Overrides;
+
+/// Collects the commands as they're run, to be written in input order once all are done.
+pub struct Script {
+    cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    commands: Mutex<Vec<(usize, String)>>,
+}
+
+fn quote(s: &str) -> String {
+    shlex::try_quote(s).map(|q| q.into_owned()).unwrap_or_else(|_| format!("'{}'", s.replace('\0', "")))
+}
+
+impl Script {
+    /// The working directory and environment variables are those given for all commands.
+    pub fn new(cwd: Option<PathBuf>, env: Vec<(String, String)>) -> Self {
+        Self { cwd, env, commands: Mutex::new(Vec::new()) }
+    }
+    
+    /// Adds the command that produced the entry of the given name, from either its stdout or stderr.
+    pub fn add(&self, index: usize, name: &str, command: &str, overrides: &Overrides, stderr: bool) {
+        let cwd = overrides.cwd.as_ref().or(self.cwd.as_ref());
+        let env: Vec<String> = self.env.iter().chain(&overrides.env).map(|(k, v)| format!("export {k}={}", quote(v))).collect();
+        
+        let mut line = match (cwd, env.is_empty()) {
+            (None, true) => command.to_string(),
+            (cwd, _) => {
+                let cwd = cwd.map(|cwd| format!("cd {}", quote(&cwd.to_string_lossy())));
+                format!("({})", cwd.into_iter().chain(env).chain([command.to_string()]).collect::<Vec<_>>().join(" && "))
+            },
+        };
+        line += &format!(" {}> {}", if stderr { "2" } else { "" }, quote(name));
+        
+        if let Some((parent, _)) = name.rsplit_once('/') {
+            line = format!("mkdir -p {} && {line}", quote(parent));
+        }
+        
+        let entry = format!("# {}\n{line}\n", name.replace('\n', " "));
+        self.commands.lock().expect("failed to lock mutex").push((index, entry));
+    }
+    
+    /// The script, with the commands in input order.
+    pub fn finish(&self) -> String {
+        let mut commands = std::mem::take(&mut *self.commands.lock().expect("failed to lock mutex"));
+        commands.sort_by_key(|(index, _)| *index);
+        
+        let mut script = format!(
+            "#!/bin/sh\n# Written by cmd2zip {}; each command writes the file named above it.\n",
+            env!("CARGO_PKG_VERSION"),
+        );
+        for (_, command) in commands {
+            script += "\n";
+            script += &command;
+        }
+        script
+    }
+}
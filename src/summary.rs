@@ -0,0 +1,204 @@
+//! Tallying the commands of a run, for the summary printed at its end.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use cmd2zip::{json::{self, Value}, progress, writer::Record};
+
+/// The name of the summary entry, for `--summary-entry`.
+pub const ENTRY_NAME: &str = "summary.txt";
+
+/// The name of the timings entry, for `--timings`.
+pub const TIMINGS_ENTRY_NAME: &str = "timings.csv";
+
+/// The name of the benchmark statistics entry, for `--bench`.
+pub const BENCH_ENTRY_NAME: &str = "bench.json";
+
+/// How a single command went, for the slowest commands and `--timings`.
+#[derive(Debug, Clone)]
+struct Timing {
+    name: String,
+    record: Record,
+    bytes: u64,
+}
+
+pub struct Summary {
+    started: Instant,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    oversize: AtomicUsize,
+    bytes: AtomicU64,
+    
+    /// Every command that ran, along with the name of its entry.
+    timings: Mutex<Vec<Timing>>,
+    
+    /// How long each run of every benchmarked command took, along with the name of its entry and the command.
+    bench: Mutex<Vec<(String, String, Vec<Duration>)>>,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            succeeded: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            oversize: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            timings: Mutex::new(Vec::new()),
+            bench: Mutex::new(Vec::new()),
+        }
+    }
+    
+    /// A command was left out, by `--filter`, `--skip` and the like, or because its output already exists.
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// A command couldn't be run at all.
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// A command's output exceeded `--max-output-size`.
+    pub fn oversize(&self) {
+        self.oversize.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// A command ran, capturing the given number of bytes.
+    pub fn finished(&self, name: &str, record: &Record, bytes: u64, success: bool) {
+        match success {
+            true => self.succeeded.fetch_add(1, Ordering::Relaxed),
+            false => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.timings.lock().expect("failed to lock mutex").push(Timing { name: name.to_string(), record: record.clone(), bytes });
+    }
+    
+    /// A command was benchmarked successfully, for `--bench`.
+    pub fn benched(&self, name: &str, command: &str, runs: Vec<Duration>) {
+        self.bench.lock().expect("failed to lock mutex").push((name.to_string(), command.to_string(), runs));
+    }
+    
+    /// The statistics of the benchmarked commands, ordered by their names; for `--bench`.
+    pub fn bench_json(&self, runs: u32) -> Value {
+        let mut bench = self.bench.lock().expect("failed to lock mutex").clone();
+        bench.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        
+        let commands = bench.into_iter()
+            .map(|(name, command, runs)| {
+                let seconds: Vec<f64> = runs.iter().map(Duration::as_secs_f64).collect();
+                let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+                // The sample standard deviation, as the runs are but a sample of how long the command takes.
+                let variance = match seconds.len() {
+                    1 => 0.0,
+                    n => seconds.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64,
+                };
+                Value::object([
+                    ("name", Value::from(name)),
+                    ("command", command.into()),
+                    ("min", seconds.iter().copied().fold(f64::INFINITY, f64::min).into()),
+                    ("mean", mean.into()),
+                    ("max", seconds.iter().copied().fold(0.0, f64::max).into()),
+                    ("stddev", variance.sqrt().into()),
+                    ("runs", Value::Array(seconds.into_iter().map(Value::from).collect())),
+                ])
+            })
+            .collect();
+        Value::object([
+            ("runs", Value::from(runs)),
+            ("commands", Value::Array(commands)),
+        ])
+    }
+    
+    fn slowest(&self, count: usize) -> Vec<(Duration, String, String)> {
+        let mut timings = self.timings.lock().expect("failed to lock mutex").iter()
+            .map(|timing| (timing.record.duration, timing.name.clone(), timing.record.command.clone()))
+            .collect::<Vec<_>>();
+        timings.sort_by_key(|(duration, _, _)| std::cmp::Reverse(*duration));
+        timings.truncate(count);
+        timings
+    }
+    
+    /// Every command that ran as a line of CSV, in the order they started; for `--timings`.
+    pub fn timings_csv(&self) -> String {
+        let mut timings = self.timings.lock().expect("failed to lock mutex").clone();
+        timings.sort_by_key(|timing| timing.record.started);
+        
+        let mut csv = String::from("name,command,started,duration,cpu_time,bytes,exit_code\n");
+        for Timing { name, record, bytes } in timings {
+            let fields = [
+                csv_field(&name),
+                csv_field(&record.command),
+                json::timestamp(record.started),
+                format!("{:.3}", record.duration.as_secs_f64()),
+                record.cpu_time.map(|time| format!("{:.3}", time.as_secs_f64())).unwrap_or_default(),
+                bytes.to_string(),
+                record.exit_code.map(|code| code.to_string()).unwrap_or_default(),
+            ];
+            csv += &(fields.join(",") + "\n");
+        }
+        csv
+    }
+    
+    /// The summary as lines of text, listing up to `slowest` of the slowest commands.
+    pub fn report(&self, slowest: usize) -> Vec<String> {
+        let (succeeded, failed) = (self.succeeded.load(Ordering::Relaxed), self.failed.load(Ordering::Relaxed));
+        let mut lines = vec![format!(
+            "{} commands run: {succeeded} succeeded, {failed} failed, {} skipped; captured {} in {}",
+            succeeded + failed,
+            self.skipped.load(Ordering::Relaxed),
+            progress::format_bytes(self.bytes.load(Ordering::Relaxed) as f64),
+            progress::format_duration(self.started.elapsed()),
+        )];
+        let oversize = self.oversize.load(Ordering::Relaxed);
+        if oversize > 0 {
+            lines.push(format!("{oversize} commands exceeded the maximum output size"));
+        }
+        
+        let slowest = self.slowest(slowest);
+        if !slowest.is_empty() {
+            lines.push("Slowest commands:".to_string());
+            for (duration, name, command) in slowest {
+                lines.push(format!("  {:.2}s `{name}` << `{command}`", duration.as_secs_f64()));
+            }
+        }
+        lines
+    }
+    
+    /// The summary as fields of a `summary` event.
+    pub fn to_json(&self, slowest: usize) -> Value {
+        let (succeeded, failed) = (self.succeeded.load(Ordering::Relaxed), self.failed.load(Ordering::Relaxed));
+        let slowest = self.slowest(slowest).into_iter()
+            .map(|(duration, name, command)| Value::object([
+                ("name", Value::from(name.as_str())),
+                ("command", command.as_str().into()),
+                ("duration", duration.as_secs_f64().into()),
+            ]))
+            .collect();
+        Value::object([
+            ("run", Value::from(succeeded + failed)),
+            ("succeeded", succeeded.into()),
+            ("failed", failed.into()),
+            ("skipped", self.skipped.load(Ordering::Relaxed).into()),
+            ("oversize", self.oversize.load(Ordering::Relaxed).into()),
+            ("bytes", self.bytes.load(Ordering::Relaxed).into()),
+            ("duration", self.started.elapsed().as_secs_f64().into()),
+            ("slowest", Value::Array(slowest)),
+        ])
+    }
+}
+
+/// Quotes a field of CSV as per RFC 4180, if it needs to be.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
@@ -0,0 +1,72 @@
+//! Holding back new child-processes, for `--rate` and `--max-load`.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use cmd2zip::verbose;
+
+/// How often the load average is checked again, while it's too high.
+const LOAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Throttle {
+    /// The time between two spawns, if limited.
+    interval: Option<Duration>,
+    max_load: Option<f64>,
+    
+    /// When the next child may be spawned.
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Allows at most `rate` children per second, and none while the load average exceeds `max_load`.
+    pub fn new(rate: Option<f64>, max_load: Option<f64>) -> Self {
+        Self {
+            interval: rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            max_load,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+    
+    /// Blocks until another child may be spawned.
+    pub fn wait(&self) {
+        if let Some(max_load) = self.max_load {
+            let mut waited = false;
+            while let Some(load) = load_average().filter(|load| *load > max_load) {
+                if !waited {
+                    verbose!(1, "-- Load average of {load:.2} exceeds {max_load}; waiting before spawning");
+                    waited = true;
+                }
+                std::thread::sleep(LOAD_POLL_INTERVAL);
+            }
+        }
+        
+        if let Some(interval) = self.interval {
+            // Reserve a point in time, then sleep outside of the lock until it comes.
+            let now = Instant::now();
+            let at = {
+                let mut next = self.next.lock().expect("failed to lock mutex");
+                let at = (*next).max(now);
+                *next = at + interval;
+                at
+            };
+            std::thread::sleep(at - now);
+        }
+    }
+}
+
+/// The load average over the last minute, where available.
+#[cfg(unix)]
+pub fn load_average() -> Option<f64> {
+    let mut loads = [0f64; 3];
+    match unsafe { libc::getloadavg(loads.as_mut_ptr(), 1) } {
+        1.. => Some(loads[0]),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn load_average() -> Option<f64> {
+    None
+}
@@ -0,0 +1,256 @@
+//! A full-screen view of a run on the terminal, for `--tui`; Unix only.
+//!
+//! Lines that would otherwise be printed meanwhile are held back, and printed once the view is closed.
+
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use cmd2zip::{exec::{self, Limits}, progress, status};
+
+use crate::control::{Control, Snapshot};
+
+/// How often the view is redrawn, at the latest; key presses redraw it right away.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many lines the latest failures and the log may take up, each.
+const TAIL_LINES: usize = 5;
+
+pub struct Tui {
+    stopped: Arc<AtomicBool>,
+    drawer: JoinHandle<()>,
+}
+
+impl Tui {
+    /// Takes over the terminal; `None` if there is none to take over, to fall back on the progress bar.
+    pub fn start(control: Arc<Control>, limits: Limits) -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let terminal = Terminal::open()?;
+        
+        progress::hold();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let drawer = {
+            let stopped = stopped.clone();
+            std::thread::Builder::new()
+                .name("tui".into())
+                .spawn(move || run(terminal, &control, &limits, &stopped))
+                .expect("failed to spawn tui")
+        };
+        Some(Self { stopped, drawer })
+    }
+    
+    /// Gives the terminal back, then prints the lines held back meanwhile.
+    pub fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.drawer.join().ok();
+        for line in progress::release() {
+            eprintln!("{line}");
+        }
+    }
+}
+
+fn run(mut terminal: Terminal, control: &Control, limits: &Limits, stopped: &AtomicBool) {
+    let mut stderr = std::io::stderr();
+    write!(stderr, "\x1b[?1049h\x1b[?25l").ok();
+    
+    while !stopped.load(Ordering::Relaxed) {
+        let (width, height) = terminal.size();
+        let frame = render(&control.snapshot(), width, height);
+        write!(stderr, "\x1b[H{frame}\x1b[J").ok();
+        stderr.flush().ok();
+        
+        for key in terminal.keys(REDRAW_INTERVAL) {
+            match key {
+                b'p' => match control.toggle_pause() {
+                    true => status!("-- Paused; no further commands are started"),
+                    false => status!("-- Resumed"),
+                },
+                b'+' => control.set_limit(control.running().1 + 1),
+                b'-' => control.set_limit(control.running().1.saturating_sub(1)),
+                b'q' if !exec::stopping() => {
+                    status!("!! Aborted; stopping the running commands and finishing the archive");
+                    exec::stop_all(limits);
+                },
+                _ => {},
+            }
+        }
+    }
+    
+    write!(stderr, "\x1b[?25h\x1b[?1049l").ok();
+    stderr.flush().ok();
+}
+
+/// The whole view, as lines cut to the width of the terminal.
+fn render(snapshot: &Snapshot, width: usize, height: usize) -> String {
+    let counts = progress::counts();
+    let waiting = counts.queued.saturating_sub(counts.done).saturating_sub(snapshot.running.len() as u64);
+    let header = format!(
+        " cmd2zip  {}  |  {} done, {} failed, {waiting} waiting  |  {} running, up to {} at once{}",
+        progress::format_duration(snapshot.elapsed),
+        counts.done,
+        counts.failed,
+        snapshot.running.len(),
+        snapshot.limit,
+        if snapshot.paused { "  |  PAUSED" } else { "" },
+    );
+    
+    let failures: Vec<String> = snapshot.failures.iter().rev().take(TAIL_LINES).rev()
+        .map(|(name, command)| format!("   `{name}` << `{command}`"))
+        .collect();
+    let log: Vec<String> = progress::held(TAIL_LINES).iter()
+        .flat_map(|line| strip_escapes(line).lines().map(|line| format!("   {line}")).collect::<Vec<_>>())
+        .collect();
+    let log = &log[log.len().saturating_sub(TAIL_LINES)..];
+    
+    // What's left after the header, keys, table heading and the tails goes to the running commands.
+    let tails = if failures.is_empty() { 0 } else { failures.len() + 2 } + if log.is_empty() { 0 } else { log.len() + 2 };
+    let rows = height.saturating_sub(4 + tails).max(1);
+    
+    let mut lines = vec![
+        (true, header),
+        (false, " p pause/resume   + more at once   - fewer at once   q abort".to_string()),
+        (false, String::new()),
+        (false, format!("   {:>9}  {:>10}  ENTRY << COMMAND", "ELAPSED", "CAPTURED")),
+    ];
+    for (i, job) in snapshot.running.iter().enumerate() {
+        if i + 1 == rows && snapshot.running.len() > rows {
+            lines.push((false, format!("   ... and {} more", snapshot.running.len() - i)));
+            break;
+        }
+        lines.push((false, format!(
+            "   {:>8.1}s  {:>10}  `{}` << `{}`",
+            job.started.elapsed().as_secs_f64(),
+            progress::format_bytes(job.captured.load(Ordering::Relaxed) as f64),
+            job.name,
+            job.command,
+        )));
+    }
+    for (title, tail) in [(" Latest failures", failures.as_slice()), (" Log", log)] {
+        if !tail.is_empty() {
+            lines.push((false, String::new()));
+            lines.push((true, title.to_string()));
+            lines.extend(tail.iter().map(|line| (false, line.clone())));
+        }
+    }
+    
+    lines.into_iter()
+        .take(height)
+        .map(|(highlight, line)| {
+            let line: String = line.chars().take(width).collect();
+            match highlight {
+                true => format!("\x1b[7m{line:width$}\x1b[0m"),
+                false => format!("{line}\x1b[K"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// Removes the color codes from a line printed to the console.
+fn strip_escapes(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            },
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// The controlling terminal, switched to reading single key presses without echoing them.
+#[cfg(unix)]
+struct Terminal {
+    tty: std::fs::File,
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    fn open() -> Option<Self> {
+        use std::os::fd::AsRawFd;
+        
+        // Not stdin, which may well be where the commands are read from.
+        let tty = std::fs::File::open("/dev/tty").ok()?;
+        // SAFETY: `termios` is plain data, for which all zeroes is valid; it's filled in right away.
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut saved) } != 0 {
+            return None;
+        }
+        
+        // Ctrl-C still interrupts, as `ISIG` is left alone.
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+        Some(Self { tty, saved })
+    }
+    
+    /// The width and height of the terminal, in characters.
+    fn size(&self) -> (usize, usize) {
+        use std::os::fd::AsRawFd;
+        
+        // SAFETY: `winsize` is plain data, for which all zeroes is valid.
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        match unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24),
+        }
+    }
+    
+    /// The keys pressed within the given time, returning as soon as there are any.
+    fn keys(&mut self, timeout: Duration) -> Vec<u8> {
+        use std::{io::Read, os::fd::AsRawFd};
+        
+        let mut poll = libc::pollfd { fd: self.tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+            return Vec::new();
+        }
+        let mut keys = [0u8; 64];
+        let read = self.tty.read(&mut keys).unwrap_or(0);
+        keys[..read].to_vec()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        use std::os::fd::AsRawFd;
+        
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// There's no terminal to take over on other platforms, yet.
+#[cfg(not(unix))]
+struct Terminal;
+
+#[cfg(not(unix))]
+impl Terminal {
+    fn open() -> Option<Self> {
+        None
+    }
+    
+    fn size(&self) -> (usize, usize) {
+        (80, 24)
+    }
+    
+    fn keys(&mut self, timeout: Duration) -> Vec<u8> {
+        std::thread::sleep(timeout);
+        Vec::new()
+    }
+}
@@ -0,0 +1,55 @@
+//! Uploading the archive while it's written, for `--output` URLs.
+//!
+//! The archive is streamed into the stdin of an external uploader, so that it never touches the local disk.
+
+use std::{
+    io,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+/// A running uploader, receiving the archive on its stdin.
+pub struct Upload {
+    child: Child,
+}
+
+/// Whether the output is a URL to upload to, instead of a local path.
+pub fn is_url(output: &str) -> bool {
+    ["s3://", "http://", "https://"].iter().any(|scheme| output.starts_with(scheme))
+}
+
+impl Upload {
+    /// Starts uploading to the given URL: `s3://` via `aws s3 cp` (which uses multipart uploads), anything else via `curl` as HTTP PUT.
+    pub fn start(url: &str) -> io::Result<(Self, ChildStdin)> {
+        let mut command = match url.starts_with("s3://") {
+            true => {
+                let mut command = Command::new("aws");
+                command.args(["s3", "cp", "-", url]);
+                command
+            },
+            false => {
+                let mut command = Command::new("curl");
+                command.args(["--fail", "--silent", "--show-error", "--upload-file", "-", url]);
+                command
+            },
+        };
+        
+        let program = command.get_program().to_string_lossy().into_owned();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("failed to start `{program}`: {err}")))?;
+        
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok((Self { child }, stdin))
+    }
+    
+    /// Waits for the upload to complete; the archive must have been finished (and its stdin closed) before.
+    pub fn finish(mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("uploader {status}"))),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Comparing the outputs of a run against those in an existing archive, for `--verify`.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cmd2zip::{archive::{self, ArchiveWriter}, events::{self, Color}, json::Value, status, verbose, writer::Checksum};
+
+/// Whether any entry differed from the archive; set once the verifier is finished.
+static DIFFERED: AtomicBool = AtomicBool::new(false);
+
+pub fn differed() -> bool {
+    DIFFERED.load(Ordering::Relaxed)
+}
+
+enum Source {
+    Zip(zip::ZipArchive<File>),
+    
+    /// A directory written with `--format dir`.
+    Dir(PathBuf),
+}
+
+/// Takes the place of the archive being written, comparing each entry by its checksum instead of writing it.
+pub struct Verifier {
+    source: Source,
+    existing: Vec<String>,
+    
+    /// The entries of this run, whether they're in the archive or not.
+    seen: HashSet<String>,
+    unchanged: usize,
+    changed: Vec<String>,
+    added: Vec<String>,
+}
+
+impl Verifier {
+    /// Opens the zip archive or directory at the given path, which is only ever read from.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (source, existing) = match path.is_dir() {
+            true => {
+                let existing = archive::walk(path)?.into_iter()
+                    .filter(|walked| !walked.kind.is_dir())
+                    .map(|walked| walked.name)
+                    .collect();
+                (Source::Dir(path.to_path_buf()), existing)
+            },
+            false => {
+                let archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::from)?;
+                let existing = archive.file_names().map(String::from).collect();
+                (Source::Zip(archive), existing)
+            },
+        };
+        Ok(Self { source, existing, seen: HashSet::new(), unchanged: 0, changed: Vec::new(), added: Vec::new() })
+    }
+    
+    /// The checksum of the existing entry of the given name.
+    fn digest(&mut self, name: &str) -> io::Result<String> {
+        match &mut self.source {
+            Source::Zip(archive) => Checksum::Sha256.digest(&mut archive.by_name(name).map_err(io::Error::from)?),
+            Source::Dir(root) => Checksum::Sha256.digest(&mut File::open(root.join(name.trim_start_matches('/')))?),
+        }
+    }
+}
+
+impl ArchiveWriter for Verifier {
+    fn append(&mut self, name: &str, content: &mut dyn Read, _size: u64) -> io::Result<()> {
+        self.seen.insert(name.to_string());
+        if !self.existing.iter().any(|n| n == name) {
+            status!("{}", events::paint(Color::Yellow, format_args!("!! Entry `{name}` is not in the archive"), true));
+            self.added.push(name.to_string());
+            return Ok(());
+        }
+        
+        match Checksum::Sha256.digest(content)? == self.digest(name)? {
+            true => {
+                verbose!(1, "-- Entry `{name}` is unchanged");
+                self.unchanged += 1;
+            },
+            false => {
+                status!("{}", events::paint(Color::Red, format_args!("!! Entry `{name}` differs from the archive"), true));
+                self.changed.push(name.to_string());
+            },
+        }
+        Ok(())
+    }
+    
+    /// Directories hold no content, and whether they're listed depends on `--directories`; they're left out.
+    fn add_directory(&mut self, _name: &str) -> io::Result<()> {
+        Ok(())
+    }
+    
+    /// Entries replacing earlier ones of the same name are compared all the same.
+    fn remove(&mut self, _name: &str) {}
+    
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let missing: Vec<&String> = self.existing.iter().filter(|n| !n.ends_with('/') && !self.seen.contains(*n)).collect();
+        for name in &missing {
+            status!("{}", events::paint(Color::Red, format_args!("!! Entry `{name}` is missing from this run"), true));
+        }
+        
+        verbose!(0, "-- Verified {} entries: {} unchanged, {} changed, {} not in the archive, {} missing",
+            self.seen.len(), self.unchanged, self.changed.len(), self.added.len(), missing.len());
+        let names = |names: &mut dyn Iterator<Item = &String>| Value::Array(names.map(|n| Value::from(n.as_str())).collect());
+        events::emit("verify", Value::object([
+            ("unchanged", Value::from(self.unchanged)),
+            ("changed", names(&mut self.changed.iter())),
+            ("added", names(&mut self.added.iter())),
+            ("missing", names(&mut missing.iter().copied())),
+        ]));
+        
+        DIFFERED.store(!self.changed.is_empty() || !self.added.is_empty() || !missing.is_empty(), Ordering::Relaxed);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Re-running commands as their input files change, for `--watch`.
+
+use std::{
+    ffi::OsString,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use cmd2zip::status;
+
+fn modified(input: &str) -> Option<SystemTime> {
+    std::fs::metadata(input).and_then(|m| m.modified()).ok()
+}
+
+/// Checks the inputs for changes every `interval`, forever, re-running the commands of those that changed.
+/// 
+/// The commands are re-run by another instance of cmd2zip, given the same arguments as this one,
+/// which appends to the archive and replaces the earlier entries.
+pub fn watch(argv: &[OsString], inputs: Vec<String>, interval: Duration) -> ! {
+    let mut inputs: Vec<(String, Option<SystemTime>)> = inputs.into_iter().map(|input| {
+        let time = modified(&input);
+        (input, time)
+    }).collect();
+    status!("-- Watching {} inputs for changes; stop with Ctrl+C", inputs.len());
+    
+    let exe = std::env::current_exe().expect("failed to locate own executable");
+    loop {
+        std::thread::sleep(interval);
+        
+        // Deleted inputs are only re-run once they're back.
+        let changed: Vec<&str> = inputs.iter_mut()
+            .filter_map(|(input, time)| {
+                let now = modified(input);
+                let changed = now.is_some() && now != *time;
+                *time = now;
+                changed.then_some(input.as_str())
+            })
+            .collect();
+        if changed.is_empty() {
+            continue;
+        }
+        status!("-- {} inputs changed, re-running their commands: {}", changed.len(), changed.join(", "));
+        
+        // Options go before a `--`, if there is one, and after everything else so that they take precedence.
+        let mut args: Vec<OsString> = argv.iter().skip(1).filter(|arg| *arg != "--watch").cloned().collect();
+        let at = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+        let mut extra: Vec<OsString> = ["--append", "--on-collision", "overwrite", "--no-progress"].map(OsString::from).into();
+        for input in changed {
+            extra.push("--watch-only".into());
+            extra.push(input.into());
+        }
+        args.splice(at..at, extra);
+        
+        match Command::new(&exe).args(args).status() {
+            Ok(_) => status!("-- Watching for further changes..."),
+            Err(err) => status!("!! Failed to re-run commands: {err}"),
+        }
+    }
+}
@@ -0,0 +1,524 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{archive::{self, ArchiveWriter, Compression, CompressRule}, capture::Capture, events, exec::Oversize, progress, json::{self, Value}, Notice, Notify};
+
+/// A finished output, on its way to the archive.
+pub struct Entry {
+    pub name: String,
+    pub content: Capture,
+    pub record: Record,
+}
+
+/// How an entry came to be, for the manifest.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The full command line, including prefix/postfix.
+    pub command: String,
+    
+    /// The exit code; `None` if there was no process, or it was killed by a signal.
+    pub exit_code: Option<i32>,
+    
+    /// The signal that killed the process, if any.
+    pub signal: Option<i32>,
+    
+    pub timed_out: bool,
+    pub attempts: u32,
+    
+    /// Which stream the content was captured from.
+    pub stream: &'static str,
+    
+    pub started: SystemTime,
+    pub duration: Duration,
+    
+    /// The CPU time used by all attempts and stages, where known.
+    pub cpu_time: Option<Duration>,
+    
+    /// What was done about the output exceeding its maximum size, if it did.
+    pub oversize: Option<Oversize>,
+}
+
+impl Record {
+    /// The record of an entry cmd2zip writes itself, like the summary, rather than a command; stamped with the current time.
+    pub fn synthetic(stream: &'static str) -> Self {
+        Self {
+            command: String::new(),
+            exit_code: None,
+            signal: None,
+            timed_out: false,
+            attempts: 0,
+            stream,
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            cpu_time: None,
+            oversize: None,
+        }
+    }
+    
+    pub fn to_json(&self, name: &str, bytes: u64) -> Value {
+        Value::object([
+            ("name", name.into()),
+            ("command", self.command.as_str().into()),
+            ("exit_code", self.exit_code.into()),
+            ("signal", self.signal.into()),
+            ("timed_out", self.timed_out.into()),
+            ("attempts", self.attempts.into()),
+            ("stream", self.stream.into()),
+            ("bytes", bytes.into()),
+            ("started", json::timestamp(self.started).into()),
+            ("finished", json::timestamp(self.started + self.duration).into()),
+            ("duration", self.duration.as_secs_f64().into()),
+            ("cpu_time", self.cpu_time.map(|time| time.as_secs_f64()).into()),
+            ("oversize", self.oversize.map(Oversize::name).into()),
+        ])
+    }
+}
+
+/// What to do with an entry whose name was already written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Collision {
+    /// Leave out the later entry, failing once all others are written.
+    #[default]
+    Error,
+    
+    /// Replace the earlier entry.
+    Overwrite,
+    
+    /// Keep the earlier entry, dropping the later one.
+    Skip,
+    
+    /// Number the later entry, as in `name-1.ext`, `name-2.ext`, ...
+    Rename,
+}
+
+/// A hash to checksum written entries with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Checksum {
+    /// SHA-256, written to a `SHA256SUMS` entry.
+    Sha256,
+}
+
+impl Checksum {
+    /// The name of the entry listing the checksums, in the format of `sha256sum` and friends.
+    pub fn entry_name(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "SHA256SUMS",
+        }
+    }
+    
+    /// Hashes all of the content, as lowercase hex.
+    pub fn digest(self, content: &mut dyn Read) -> io::Result<String> {
+        match self {
+            Checksum::Sha256 => {
+                let mut content = Hashing { inner: content, hasher: Some(Sha256::new()) };
+                io::copy(&mut content, &mut io::sink())?;
+                Ok(hex(content.hasher.take().expect("hashing")))
+            },
+        }
+    }
+    
+    /// The key of the checksum in the manifest.
+    fn key(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "sha256",
+        }
+    }
+}
+
+/// What to do with an entry whose content is identical to one written before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dedup {
+    /// Drop the entry.
+    Skip,
+    
+    /// Write a `NAME.link` entry instead, containing the name of the identical entry.
+    Link,
+    
+    /// Drop the entry, recording the name of the identical entry in the manifest as `duplicate_of`.
+    Manifest,
+}
+
+/// Hashes everything read through it, if there's a hasher.
+struct Hashing<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// What the archive writer does beyond writing entries.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Whether to write a `manifest.json` entry describing all other entries.
+    pub manifest: bool,
+    
+    pub on_collision: Collision,
+    
+    /// Whether to write directory entries for the parent directories of entries.
+    pub dir_entries: bool,
+    
+    /// A file to record the name and command of every written entry in, as one JSON object per line.
+    pub state: Option<File>,
+    
+    /// Whether to write entries in the order their slots were handed out, instead of the order they arrive in.
+    pub ordered: bool,
+    
+    /// Whether to leave the timings out of the manifest, so it's the same across runs.
+    pub reproducible: bool,
+    
+    /// A hash to checksum all entries written with, both into a separate entry and the manifest.
+    pub checksums: Option<Checksum>,
+    
+    /// What to do with entries identical to earlier ones, if they're to be deduplicated at all.
+    pub dedup: Option<Dedup>,
+    
+    /// Whether to store entries that are compressed already without compressing them again.
+    pub auto_store: bool,
+    
+    /// Compression methods for the entries matching their patterns; the first matching rule applies.
+    pub compress_rules: Vec<CompressRule>,
+    
+    /// Whether to attach the command of every entry to it as comment, where the archive can hold those.
+    pub comment_commands: bool,
+    
+    /// The comment of the archive, where it can hold one; `{finished}` within it is replaced with when it's finished.
+    pub comment: Option<String>,
+    
+    /// The name of an entry that all entries of this name are concatenated into, instead of colliding;
+    /// best combined with `ordered`.
+    pub merge: Option<String>,
+    
+    /// What goes between the concatenated entries.
+    pub merge_separator: Vec<u8>,
+}
+
+/// The entries of a single command, along with its sequence number.
+type Batch = (usize, Vec<Entry>);
+
+/// Hands out a [`Slot`] for every command, in order.
+pub struct Queue {
+    sender: SyncSender<Batch>,
+    next: usize,
+    notify: Notify,
+}
+
+impl Queue {
+    pub fn slot(&mut self) -> Slot {
+        self.next += 1;
+        Slot { seq: self.next - 1, sender: self.sender.clone(), entries: Vec::new(), notify: self.notify.clone() }
+    }
+}
+
+/// Collects the entries of a single command, sending them to the writer all at once when dropped.
+/// 
+/// Being sent on drop, even commands without any entries (or panicking ones) keep their place in order.
+pub struct Slot {
+    seq: usize,
+    sender: SyncSender<Batch>,
+    entries: Vec<Entry>,
+    notify: Notify,
+}
+
+impl Slot {
+    pub fn push(&mut self, name: String, content: Capture, record: Record) {
+        self.entries.push(Entry { name, content, record });
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        // Only fails if the writer gave up, which is reported once it's joined.
+        let batch = (self.seq, std::mem::take(&mut self.entries));
+        if let Err(TrySendError::Full(batch)) = self.sender.try_send(batch) {
+            let waiting = Instant::now();
+            self.sender.send(batch).ok();
+            (self.notify)(Notice::Info(2, format!("Waited {:?} for the archive writer to catch up", waiting.elapsed())));
+        }
+    }
+}
+
+/// Yields received batches in order of their sequence numbers, or in order of arrival if not `ordered`.
+struct Batches {
+    receiver: Receiver<Batch>,
+    ordered: bool,
+    pending: BTreeMap<usize, Vec<Entry>>,
+    next: usize,
+}
+
+impl Iterator for Batches {
+    type Item = Vec<Entry>;
+    
+    fn next(&mut self) -> Option<Vec<Entry>> {
+        if !self.ordered {
+            return self.receiver.recv().ok().map(|(_, entries)| entries);
+        }
+        
+        loop {
+            if let Some(entries) = self.pending.remove(&self.next) {
+                self.next += 1;
+                return Some(entries);
+            }
+            match self.receiver.recv() {
+                Ok((seq, entries)) => {
+                    self.pending.insert(seq, entries);
+                },
+                // Nothing else is coming, so whatever is left goes out as-is.
+                Err(_) => return self.pending.pop_first().map(|(_, entries)| entries),
+            }
+        }
+    }
+}
+
+/// The name of the manifest entry.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// Moves the archive onto a thread of its own, which writes the entries sent to it through slots of the queue,
+/// and finishes the archive once the queue and all slots are gone.
+/// 
+/// At most `bound` slots can be waiting to be written, before senders are blocked.
+/// What the writer does beyond writing, like skipping colliding entries, is told about via `notify`.
+pub fn spawn(mut archive: Box<dyn ArchiveWriter>, mut config: Config, bound: usize, notify: Notify) -> (Queue, JoinHandle<io::Result<()>>) {
+    let (sender, receiver) = mpsc::sync_channel::<Batch>(bound);
+    let queue = Queue { sender, next: 0, notify: notify.clone() };
+    let batches = Batches { receiver, ordered: config.ordered, pending: BTreeMap::new(), next: 0 };
+    let writer = std::thread::Builder::new()
+        .name("archive-writer".into())
+        .spawn(move || {
+            let mut manifest = Vec::new();
+            let mut checksums: Vec<(String, String)> = Vec::new();
+            let mut originals: HashMap<String, String> = HashMap::new();
+            let mut names: HashSet<String> = archive.existing_names().iter().cloned().collect();
+            let mut collided = Vec::new();
+            let mut directories: HashSet<String> = names.iter().filter_map(|n| n.strip_suffix('/')).map(String::from).collect();
+            let mut merged: Option<Capture> = None;
+            
+            for mut entry in batches.flatten() {
+                // Outputs left out for their size are only listed in the manifest.
+                if matches!(entry.record.oversize, Some(Oversize::Skip | Oversize::Spill)) {
+                    let record = manifest_record(&config, &entry.name, &entry.record, entry.content.len(), None);
+                    manifest.push((entry.name, record));
+                    continue;
+                }
+                
+                if config.merge.as_ref() == Some(&entry.name) {
+                    let merged = match &mut merged {
+                        Some(merged) => {
+                            merged.write_all(&config.merge_separator)?;
+                            merged
+                        },
+                        None => merged.insert(Capture::default()),
+                    };
+                    io::copy(&mut entry.content.reader()?, merged)?;
+                    if config.manifest {
+                        manifest.push((entry.name.clone(), manifest_record(&config, &entry.name, &entry.record, entry.content.len(), None)));
+                    }
+                    continue;
+                }
+                
+                if names.contains(&entry.name) {
+                    match config.on_collision {
+                        Collision::Error => {
+                            notify(Notice::Error(format!("Entry `{}` already exists; leaving it out", entry.name)));
+                            collided.push(entry.name);
+                            continue;
+                        },
+                        Collision::Overwrite => {
+                            notify(Notice::Warning(format!("Entry `{}` already exists; overwriting it", entry.name)));
+                            archive.remove(&entry.name);
+                            manifest.retain(|(name, _)| *name != entry.name);
+                            checksums.retain(|(name, _)| *name != entry.name);
+                            originals.retain(|_, name| *name != entry.name);
+                        },
+                        Collision::Skip => {
+                            notify(Notice::Warning(format!("Entry `{}` already exists; skipping it", entry.name)));
+                            continue;
+                        },
+                        Collision::Rename => {
+                            let name = (1..).map(|n| numbered(&entry.name, n)).find(|n| !names.contains(n)).expect("ran out of numbers");
+                            notify(Notice::Warning(format!("Entry `{}` already exists; renaming it to `{name}`", entry.name)));
+                            entry.name = name;
+                        },
+                    }
+                }
+                
+                // Deduplicating needs the checksum before writing, so the content is read twice then.
+                let mut checksum = None;
+                if let Some(dedup) = config.dedup {
+                    let hash = {
+                        let mut content = Hashing { inner: entry.content.reader()?, hasher: Some(Sha256::new()) };
+                        io::copy(&mut content, &mut io::sink())?;
+                        hex(content.hasher.take().expect("hashing"))
+                    };
+                    
+                    if let Some(original) = originals.get(&hash) {
+                        match dedup {
+                            Dedup::Skip => {
+                                notify(Notice::Info(0, format!("Entry `{}` is identical to `{original}`; skipping it", entry.name)));
+                                continue;
+                            },
+                            Dedup::Link => {
+                                entry.content = Capture::from(original.as_bytes().to_vec());
+                                entry.name += ".link";
+                            },
+                            Dedup::Manifest => {
+                                let mut record = manifest_record(&config, &entry.name, &entry.record, entry.content.len(), Some(&hash));
+                                if let Value::Object(fields) = &mut record {
+                                    fields.push(("duplicate_of".to_string(), original.as_str().into()));
+                                }
+                                manifest.push((entry.name, record));
+                                continue;
+                            },
+                        }
+                    } else {
+                        originals.insert(hash.clone(), entry.name.clone());
+                        checksum = Some(hash);
+                    }
+                }
+                names.insert(entry.name.clone());
+                
+                if config.dir_entries {
+                    let parents = entry.name.match_indices('/').map(|(i, _)| &entry.name[..i]).filter(|p| !p.is_empty());
+                    for parent in parents {
+                        if directories.insert(parent.to_string()) {
+                            archive.add_directory(parent)?;
+                        }
+                    }
+                }
+                
+                let size = entry.content.len();
+                let compression = match config.compress_rules.iter().find(|rule| rule.matches(&entry.name)) {
+                    Some(rule) => Some((rule.compression, rule.level)),
+                    None if config.auto_store && archive::is_compressed(&entry.name, entry.content.head()) => Some((Compression::Stored, None)),
+                    None => None,
+                };
+                let hasher = config.checksums.filter(|_| checksum.is_none()).map(|_| Sha256::new());
+                let mut content = Hashing { inner: entry.content.reader()?, hasher };
+                let writing = Instant::now();
+                match compression {
+                    Some((compression, level)) => archive.append_with(&entry.name, &mut content, size, compression, level)?,
+                    None => archive.append(&entry.name, &mut content, size)?,
+                }
+                notify(Notice::Info(2, format!("Wrote entry `{}` ({size} bytes) in {:?}", entry.name, writing.elapsed())));
+                if config.comment_commands && !entry.record.command.is_empty() {
+                    archive.comment(&entry.name, &entry.record.command);
+                }
+                let checksum = checksum.or(content.hasher.map(hex));
+                progress::written(size);
+                events::emit("written", Value::object([
+                    ("name", Value::from(entry.name.as_str())),
+                    ("bytes", size.into()),
+                ]));
+                
+                // Entries not produced by a command, like a summary, have nothing to skip when resuming.
+                if let Some(state) = config.state.as_mut().filter(|_| entry.record.stream != "stderr" && !entry.record.command.is_empty()) {
+                    let line = Value::object([("name", Value::from(entry.name.as_str())), ("command", entry.record.command.as_str().into())]);
+                    writeln!(state, "{line}")?;
+                    state.flush()?;
+                }
+                
+                if config.manifest {
+                    let record = manifest_record(&config, &entry.name, &entry.record, size, checksum.as_deref());
+                    manifest.push((entry.name.clone(), record));
+                }
+                
+                if let Some(checksum) = checksum.filter(|_| config.checksums.is_some()) {
+                    checksums.push((entry.name, checksum));
+                }
+            }
+            
+            if let (Some(name), Some(mut merged)) = (&config.merge, merged) {
+                let size = merged.len();
+                let hasher = config.checksums.map(|_| Sha256::new());
+                let mut content = Hashing { inner: merged.reader()?, hasher };
+                archive.append(name, &mut content, size)?;
+                progress::written(size);
+                if let Some(hasher) = content.hasher {
+                    checksums.push((name.clone(), hex(hasher)));
+                }
+            }
+            
+            if let Some(kind) = config.checksums {
+                let sums: String = checksums.iter().map(|(name, checksum)| format!("{checksum}  {name}\n")).collect();
+                archive.append(kind.entry_name(), &mut sums.as_bytes(), sums.len() as u64)?;
+            }
+            
+            if config.manifest {
+                let manifest = Value::object([
+                    ("generator", concat!("cmd2zip ", env!("CARGO_PKG_VERSION")).into()),
+                    ("entries", Value::Array(manifest.into_iter().map(|(_, record)| record).collect())),
+                ]).to_string();
+                archive.append(MANIFEST_NAME, &mut manifest.as_bytes(), manifest.len() as u64)?;
+            }
+            
+            if let Some(comment) = &config.comment {
+                archive.set_comment(&comment.replace("{finished}", &json::timestamp(SystemTime::now())));
+            }
+            archive.finish()?;
+            
+            match collided.is_empty() {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::AlreadyExists, Collided(collided))),
+            }
+        })
+        .expect("failed to spawn archive writer");
+    (queue, writer)
+}
+
+/// What the writer fails with under [`Collision::Error`]: the names of the entries left out, as they already existed.
+#[derive(Debug)]
+pub struct Collided(pub Vec<String>);
+
+impl std::fmt::Display for Collided {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.as_slice() {
+            [name] => write!(f, "entry `{name}` already exists"),
+            names => write!(f, "{} entries already exist, the first being `{}`", names.len(), names.first().map_or("", String::as_str)),
+        }
+    }
+}
+
+impl std::error::Error for Collided {}
+
+/// The record of an entry in the manifest, with its checksum if there's one; without the timings,
+/// if the archive is to be `reproducible`.
+fn manifest_record(config: &Config, name: &str, record: &Record, size: u64, checksum: Option<&str>) -> Value {
+    let mut json = record.to_json(name, size);
+    if let Value::Object(fields) = &mut json {
+        if config.reproducible {
+            fields.retain(|(key, _)| !["started", "finished", "duration", "cpu_time"].contains(&key.as_str()));
+        }
+        if let (Some(kind), Some(checksum)) = (config.checksums, checksum) {
+            fields.push((kind.key().to_string(), checksum.into()));
+        }
+    }
+    json
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Numbers the name before its extensions, if it has any.
+fn numbered(name: &str, n: usize) -> String {
+    let start = name.rfind('/').map_or(0, |i| i + 1);
+    match name[start..].find('.').filter(|i| *i > 0) {
+        Some(i) => format!("{}-{n}{}", &name[..start + i], &name[start + i..]),
+        None => format!("{name}-{n}"),
+    }
+}
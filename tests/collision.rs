@@ -0,0 +1,28 @@
+//! Entries of the same name, as handled by `--on-collision`.
+#![cfg(unix)]
+
+mod common;
+
+use common::{cmd2zip, entries, scratch};
+
+#[test]
+fn colliding_entries_fail_the_run_but_keep_the_others() {
+    let dir = scratch("collision-error");
+    
+    let code = cmd2zip(&dir, &["-p", "x|y", "-o", "out.zip", "echo x", "echo x", "echo y"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["x", "y"]);
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn skipped_collisions_keep_the_first_entry() {
+    let dir = scratch("collision-skip");
+    
+    let code = cmd2zip(&dir, &["-p", "x|y", "--on-collision", "skip", "-o", "out.zip", "echo x", "echo x", "echo y"]);
+    assert_eq!(code, 0);
+    assert_eq!(entries(&dir.join("out.zip")), ["x", "y"]);
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
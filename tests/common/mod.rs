@@ -0,0 +1,37 @@
+//! Helpers shared by the integration tests, which run the binary on scratch directories.
+#![allow(dead_code)]
+
+use std::{fs::File, path::{Path, PathBuf}, process::Command};
+
+/// A fresh directory to run in, named after the test.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cmd2zip-test-{}-{name}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).expect("failed to create scratch directory");
+    dir
+}
+
+/// Runs cmd2zip in the directory, returning its exit code.
+pub fn cmd2zip(dir: &Path, args: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_cmd2zip"))
+        .current_dir(dir)
+        .args(args)
+        .status()
+        .expect("failed to run cmd2zip")
+        .code()
+        .expect("cmd2zip was killed")
+}
+
+/// The names of the entries of the zip archive, sorted.
+pub fn entries(path: &Path) -> Vec<String> {
+    let archive = zip::ZipArchive::new(File::open(path).expect("failed to open archive")).expect("failed to read archive");
+    let mut names: Vec<String> = archive.file_names().map(String::from).collect();
+    names.sort();
+    names
+}
+
+/// The content of an entry of the zip archive.
+pub fn read(path: &Path, name: &str) -> String {
+    let mut archive = zip::ZipArchive::new(File::open(path).expect("failed to open archive")).expect("failed to read archive");
+    std::io::read_to_string(archive.by_name(name).expect("missing entry")).expect("failed to read entry")
+}
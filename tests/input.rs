@@ -0,0 +1,27 @@
+//! Reading commands from input files via `--input`.
+#![cfg(unix)]
+
+mod common;
+
+use common::{cmd2zip, entries, scratch};
+
+#[test]
+fn invalid_utf8_lines_are_decoded_lossily_rather_than_ending_the_input() {
+    let dir = scratch("input-lossy");
+    std::fs::write(dir.join("commands.txt"), b"echo a\r\necho \xff\necho c\n").unwrap();
+    
+    assert_eq!(cmd2zip(&dir, &["-i", "commands.txt", "-o", "out.zip"]), 0);
+    assert_eq!(entries(&dir.join("out.zip")), ["0", "1", "2"]);
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn unreadable_inputs_fail_the_run() {
+    let dir = scratch("input-unreadable");
+    std::fs::create_dir(dir.join("commands")).unwrap();
+    
+    assert_eq!(cmd2zip(&dir, &["-i", "commands", "-o", "out.zip"]), 2);
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
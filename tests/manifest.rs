@@ -0,0 +1,54 @@
+//! The manifest of `--manifest`, which `--deterministic` keeps the same across runs.
+#![cfg(unix)]
+
+mod common;
+
+use std::path::Path;
+
+use common::{cmd2zip, read, scratch};
+
+/// Runs the commands into the output, returning its manifest.
+fn manifest(dir: &Path, output: &str, args: &[&str]) -> String {
+    assert_eq!(cmd2zip(dir, &[&["--deterministic", "--manifest", "-o", output], args].concat()), 0);
+    read(&dir.join(output), "manifest.json")
+}
+
+#[test]
+fn oversize_outputs_are_listed_without_timings() {
+    let dir = scratch("manifest-oversize");
+    
+    let args = ["--max-output-size", "5", "--on-oversize", "skip", "seq 1 100", "echo a"];
+    let first = manifest(&dir, "a.zip", &args);
+    assert_eq!(first, manifest(&dir, "b.zip", &args));
+    assert!(first.contains(r#""oversize":"skip""#));
+    assert!(!first.contains("duration"));
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn merged_outputs_are_listed_without_timings() {
+    let dir = scratch("manifest-merge");
+    
+    let args = ["--merge-into", "all.txt", "echo a", "echo b"];
+    let first = manifest(&dir, "a.zip", &args);
+    assert_eq!(first, manifest(&dir, "b.zip", &args));
+    assert!(first.contains(r#""name":"all.txt""#));
+    assert!(!first.contains("duration"));
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn duplicates_are_listed_without_timings_and_with_their_checksum() {
+    let dir = scratch("manifest-dedup");
+    
+    let args = ["--dedup", "manifest", "--checksums", "sha256", "-p", r"\w+$", "echo same # a", "echo same # b"];
+    let first = manifest(&dir, "a.zip", &args);
+    assert_eq!(first, manifest(&dir, "b.zip", &args));
+    assert!(first.contains(r#""duplicate_of":"a""#));
+    assert_eq!(first.matches(r#""sha256":"#).count(), 2);
+    assert!(!first.contains("duration"));
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
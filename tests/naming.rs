@@ -0,0 +1,48 @@
+//! Commands whose entry name or stdin can't be generated, via `--name-pattern`, fail on their own.
+#![cfg(unix)]
+
+mod common;
+
+use common::{cmd2zip, entries, read, scratch};
+
+#[test]
+fn commands_not_matching_the_name_pattern_fail_but_the_others_run() {
+    let dir = scratch("naming-pattern");
+    
+    let code = cmd2zip(&dir, &["-p", r"\w+$", "-o", "out.zip", "--failed-out", "failed.txt", "echo a", "echo -", "echo b"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["a", "b"]);
+    assert_eq!(std::fs::read_to_string(dir.join("failed.txt")).unwrap(), "echo -\n");
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn commands_not_matching_the_name_pattern_use_up_no_index() {
+    let dir = scratch("naming-template");
+    
+    let code = cmd2zip(&dir, &["-p", r"(\w+)$", "--name-template", "{1}_{index}", "-o", "out.zip", "echo a", "echo -", "echo b"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["a_0", "b_1"]);
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn commands_whose_stdin_cant_be_derived_fail_but_the_others_run() {
+    let dir = scratch("naming-stdin");
+    std::fs::write(dir.join("a.txt"), "alpha\n").unwrap();
+    // Named explicitly, so that only deriving the stdin of the second command fails.
+    std::fs::write(dir.join("commands.jsonl"), concat!(
+        r#"{"command": "cat # a.cmd", "name": "a"}"#, "\n",
+        r#"{"command": "cat # b", "name": "b"}"#, "\n",
+    )).unwrap();
+    
+    let code = cmd2zip(&dir, &["-p", r"(\w)\.cmd", "--stdin-replace", "$1.txt", "-i", "commands.jsonl", "-o", "out.zip"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["a", "b.err"]);
+    assert_eq!(read(&dir.join("out.zip"), "a"), "alpha\n");
+    assert!(read(&dir.join("out.zip"), "b.err").contains("failed to derive stdin"));
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,31 @@
+//! Writing the file of `{out}` in place of stdout.
+#![cfg(unix)]
+
+mod common;
+
+use common::{cmd2zip, entries, read, scratch};
+
+#[test]
+fn the_out_file_is_written_whatever_its_extension() {
+    let dir = scratch("out-extension");
+    
+    let code = cmd2zip(&dir, &["--shell", "-o", "out.zip", "echo a > {out}", "echo b > {out}.txt; echo log > $CMD2ZIP_OUT/output.log"]);
+    assert_eq!(code, 0);
+    assert_eq!(entries(&dir.join("out.zip")), ["0", "1"]);
+    assert_eq!(read(&dir.join("out.zip"), "0"), "a\n");
+    assert_eq!(read(&dir.join("out.zip"), "1"), "b\n");
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn commands_writing_several_out_files_fail() {
+    let dir = scratch("out-several");
+    
+    let code = cmd2zip(&dir, &["--shell", "-o", "out.zip", "echo a > {out}.txt; echo b > {out}.csv", "echo c > {out}"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["0.err", "1"]);
+    assert!(read(&dir.join("out.zip"), "0.err").contains("more than one file to `{out}`: out.csv, out.txt"));
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,34 @@
+//! Resuming a run via `--state` and `--resume`.
+#![cfg(unix)]
+
+mod common;
+
+use std::{io::Write, path::Path, process::{Command, Stdio}};
+
+use common::{entries, scratch};
+
+/// Runs cmd2zip on the NUL-separated commands, fed via stdin.
+fn cmd2zip(dir: &Path, commands: &str, args: &[&str]) -> i32 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cmd2zip"))
+        .current_dir(dir)
+        .args(["--shell", "-0", "-i", "-", "--state", "state", "-o", "out.zip"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("failed to run cmd2zip");
+    child.stdin.take().expect("stdin").write_all(commands.as_bytes()).expect("failed to write commands");
+    child.wait().expect("failed to wait for cmd2zip").code().expect("cmd2zip was killed")
+}
+
+#[test]
+fn resuming_skips_commands_with_newlines_and_tabs() {
+    let dir = scratch("resume");
+    let done = "echo 'a\nb'; echo ran >> log\0echo 'c\td'; echo ran >> log\0";
+    
+    assert_eq!(cmd2zip(&dir, done, &[]), 0);
+    assert_eq!(cmd2zip(&dir, &format!("{done}echo e\0"), &["--resume"]), 0);
+    assert_eq!(entries(&dir.join("out.zip")), ["0", "1", "2"]);
+    assert_eq!(std::fs::read_to_string(dir.join("log")).unwrap(), "ran\nran\n");
+    
+    std::fs::remove_dir_all(&dir).ok();
+}
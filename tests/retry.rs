@@ -0,0 +1,32 @@
+//! Retrying the failures of a run via `--failed-out`, `--input` and `--append`.
+#![cfg(unix)]
+
+mod common;
+
+use std::{fs::File, path::Path};
+
+use common::{entries, scratch};
+
+fn cmd2zip(dir: &Path, args: &[&str]) -> i32 {
+    common::cmd2zip(dir, &[&["--shell", "-p", r"\w+$", "-o", "out.zip"], args].concat())
+}
+
+#[test]
+fn rerunning_the_failed_commands_completes_the_archive() {
+    let dir = scratch("retry");
+    // The second command fails until the marker exists, as if it was flaky.
+    std::fs::write(dir.join("commands.txt"), "echo alpha # alpha\ntest -e marker && echo beta # beta\necho gamma # gamma\n").unwrap();
+    
+    let code = cmd2zip(&dir, &["-i", "commands.txt", "--failed-out", "failed.txt", "--drop-failed"]);
+    assert_eq!(code, 1);
+    assert_eq!(entries(&dir.join("out.zip")), ["alpha", "gamma"]);
+    assert_eq!(std::fs::read_to_string(dir.join("failed.txt")).unwrap(), "test -e marker && echo beta # beta\n");
+    
+    File::create(dir.join("marker")).unwrap();
+    let code = cmd2zip(&dir, &["-i", "failed.txt", "--append", "--failed-out", "failed-again.txt"]);
+    assert_eq!(code, 0);
+    assert_eq!(entries(&dir.join("out.zip")), ["alpha", "beta", "gamma"]);
+    assert_eq!(std::fs::read_to_string(dir.join("failed-again.txt")).unwrap(), "");
+    
+    std::fs::remove_dir_all(&dir).ok();
+}